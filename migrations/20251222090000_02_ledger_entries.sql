@@ -0,0 +1,86 @@
+CREATE TYPE account_type_enum AS ENUM ('customer', 'external');
+CREATE TYPE ledger_entry_direction_enum AS ENUM ('debit', 'credit');
+
+ALTER TABLE business_accounts
+    ADD COLUMN account_type account_type_enum NOT NULL DEFAULT 'customer';
+
+-- one system "external" account per business and currency, it is the other side of every credit / debit
+CREATE UNIQUE INDEX uq_business_accounts_external
+    ON business_accounts(business_id, currency)
+    WHERE account_type = 'external';
+
+CREATE TABLE ledger_entries (
+                                id BIGSERIAL PRIMARY KEY,
+                                business_id BIGINT NOT NULL,
+                                transaction_id BIGINT NOT NULL,
+                                account_id BIGINT NOT NULL,
+                                direction ledger_entry_direction_enum NOT NULL,
+                                amount NUMERIC(18,2) NOT NULL,
+                                created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                                CONSTRAINT chk_ledger_entries_amount_positive
+                                    CHECK (amount > 0),
+
+                                CONSTRAINT fk_ledger_entries_business
+                                    FOREIGN KEY (business_id)
+                                        REFERENCES businesses(id)
+                                        ON DELETE CASCADE,
+
+                                CONSTRAINT fk_ledger_entries_transaction
+                                    FOREIGN KEY (transaction_id)
+                                        REFERENCES transactions(id)
+                                        ON DELETE CASCADE,
+
+                                CONSTRAINT fk_ledger_entries_account
+                                    FOREIGN KEY (account_id)
+                                        REFERENCES business_accounts(id)
+                                        ON DELETE CASCADE
+);
+
+CREATE INDEX idx_ledger_entries_account_id ON ledger_entries(account_id);
+CREATE INDEX idx_ledger_entries_transaction_id ON ledger_entries(transaction_id);
+
+
+-- backfill: external accounts for every business / currency that already has accounts
+INSERT INTO business_accounts (business_id, name, currency, account_type)
+SELECT DISTINCT business_id, 'External', currency, 'external'::account_type_enum
+FROM business_accounts
+WHERE account_type = 'customer';
+
+-- backfill: journal lines for every transaction that already succeeded
+INSERT INTO ledger_entries (business_id, transaction_id, account_id, direction, amount, created_at)
+SELECT t.business_id, t.id, x.id, 'debit'::ledger_entry_direction_enum, t.amount, t.created_at
+FROM transactions t
+         JOIN business_accounts a ON a.id = t.to_account_id
+         JOIN business_accounts x ON x.business_id = t.business_id AND x.currency = a.currency AND x.account_type = 'external'
+WHERE t.type = 'credit' AND t.status = 'succeeded'
+UNION ALL
+SELECT t.business_id, t.id, t.to_account_id, 'credit'::ledger_entry_direction_enum, t.amount, t.created_at
+FROM transactions t
+WHERE t.type = 'credit' AND t.status = 'succeeded' AND t.to_account_id IS NOT NULL
+UNION ALL
+SELECT t.business_id, t.id, t.from_account_id, 'debit'::ledger_entry_direction_enum, t.amount, t.created_at
+FROM transactions t
+WHERE t.type = 'debit' AND t.status = 'succeeded' AND t.from_account_id IS NOT NULL
+UNION ALL
+SELECT t.business_id, t.id, x.id, 'credit'::ledger_entry_direction_enum, t.amount, t.created_at
+FROM transactions t
+         JOIN business_accounts a ON a.id = t.from_account_id
+         JOIN business_accounts x ON x.business_id = t.business_id AND x.currency = a.currency AND x.account_type = 'external'
+WHERE t.type = 'debit' AND t.status = 'succeeded'
+UNION ALL
+SELECT t.business_id, t.id, t.from_account_id, 'debit'::ledger_entry_direction_enum, t.amount, t.created_at
+FROM transactions t
+WHERE t.type = 'transfer' AND t.status = 'succeeded' AND t.from_account_id IS NOT NULL
+UNION ALL
+SELECT t.business_id, t.id, t.to_account_id, 'credit'::ledger_entry_direction_enum, t.amount, t.created_at
+FROM transactions t
+WHERE t.type = 'transfer' AND t.status = 'succeeded' AND t.to_account_id IS NOT NULL;
+
+UPDATE business_accounts x
+SET balance = COALESCE((
+    SELECT SUM(CASE WHEN e.direction = 'credit' THEN e.amount ELSE -e.amount END)
+    FROM ledger_entries e
+    WHERE e.account_id = x.id
+), 0)
+WHERE x.account_type = 'external';
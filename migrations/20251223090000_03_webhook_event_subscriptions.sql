@@ -0,0 +1,5 @@
+-- every endpoint carries the event types it wants, '*' subscribes to everything
+ALTER TABLE webhooks
+    ADD COLUMN event_types TEXT[] NOT NULL DEFAULT ARRAY['*']::TEXT[];
+
+CREATE INDEX idx_webhooks_event_types ON webhooks USING GIN (event_types);
@@ -0,0 +1,29 @@
+-- transactional outbox: every ledger event is recorded here in the same SQL transaction as the
+-- ledger write, webhook deliveries are materialised from it for the endpoints that exist
+CREATE TABLE domain_events (
+                               id BIGSERIAL PRIMARY KEY,
+                               business_id BIGINT NOT NULL,
+                               event_type TEXT NOT NULL,
+                               payload JSONB NOT NULL,
+                               dispatched_at TIMESTAMPTZ,
+                               created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                               CONSTRAINT fk_domain_events_business
+                                   FOREIGN KEY (business_id)
+                                       REFERENCES businesses(id)
+                                       ON DELETE CASCADE
+);
+
+CREATE INDEX idx_domain_events_business_id ON domain_events(business_id);
+CREATE INDEX idx_domain_events_undispatched ON domain_events(id) WHERE dispatched_at IS NULL;
+
+ALTER TABLE webhook_events
+    ADD COLUMN domain_event_id BIGINT,
+    ADD CONSTRAINT fk_webhook_events_domain_event
+        FOREIGN KEY (domain_event_id)
+            REFERENCES domain_events(id)
+            ON DELETE CASCADE;
+
+-- a domain event is materialised at most once per endpoint
+CREATE UNIQUE INDEX uq_webhook_events_domain_event_webhook
+    ON webhook_events(domain_event_id, webhook_id);
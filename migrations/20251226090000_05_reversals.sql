@@ -0,0 +1,11 @@
+ALTER TYPE transaction_type_enum ADD VALUE IF NOT EXISTS 'reversal';
+
+-- a reversal points at the transaction it (partially) undoes
+ALTER TABLE transactions
+    ADD COLUMN reversal_of BIGINT,
+    ADD CONSTRAINT fk_transactions_reversal_of
+        FOREIGN KEY (reversal_of)
+            REFERENCES transactions(id)
+            ON DELETE SET NULL;
+
+CREATE INDEX idx_transactions_reversal_of ON transactions(reversal_of) WHERE reversal_of IS NOT NULL;
@@ -0,0 +1,15 @@
+-- keyset pagination of GET /transaction walks (created_at, id) newest first per business
+CREATE INDEX idx_transactions_business_created_id
+    ON transactions(business_id, created_at DESC, id DESC);
+
+CREATE INDEX idx_transactions_from_account_created
+    ON transactions(from_account_id, created_at DESC, id DESC);
+CREATE INDEX idx_transactions_to_account_created
+    ON transactions(to_account_id, created_at DESC, id DESC);
+
+CREATE INDEX idx_transactions_business_reference_id
+    ON transactions(business_id, reference_id)
+    WHERE reference_id IS NOT NULL;
+
+-- the composite index above covers lookups by business_id alone
+DROP INDEX IF EXISTS idx_transactions_business_id;
@@ -0,0 +1,6 @@
+-- per-account history and point-in-time balances walk ledger entries in (created_at, id) order
+CREATE INDEX idx_ledger_entries_account_created_id
+    ON ledger_entries(account_id, created_at, id);
+
+-- the composite index above covers lookups by account_id alone
+DROP INDEX IF EXISTS idx_ledger_entries_account_id;
@@ -0,0 +1,94 @@
+-- ISO 4217 currencies and their minor units (decimal places), amounts are validated against these
+CREATE TABLE currencies (
+                            code CHAR(3) PRIMARY KEY,
+                            minor_units SMALLINT NOT NULL,
+
+                            CONSTRAINT chk_currencies_minor_units
+                                CHECK (minor_units BETWEEN 0 AND 4)
+);
+
+INSERT INTO currencies (code, minor_units) VALUES
+    ('AED', 2), ('AFN', 2), ('ALL', 2), ('AMD', 2), ('ANG', 2), ('AOA', 2), ('ARS', 2), ('AUD', 2),
+    ('AWG', 2), ('AZN', 2), ('BAM', 2), ('BBD', 2), ('BDT', 2), ('BGN', 2), ('BHD', 3), ('BIF', 0),
+    ('BMD', 2), ('BND', 2), ('BOB', 2), ('BOV', 2), ('BRL', 2), ('BSD', 2), ('BTN', 2), ('BWP', 2),
+    ('BYN', 2), ('BZD', 2), ('CAD', 2), ('CDF', 2), ('CHE', 2), ('CHF', 2), ('CHW', 2), ('CLF', 4),
+    ('CLP', 0), ('CNY', 2), ('COP', 2), ('COU', 2), ('CRC', 2), ('CUP', 2), ('CVE', 2), ('CZK', 2),
+    ('DJF', 0), ('DKK', 2), ('DOP', 2), ('DZD', 2), ('EGP', 2), ('ERN', 2), ('ETB', 2), ('EUR', 2),
+    ('FJD', 2), ('FKP', 2), ('GBP', 2), ('GEL', 2), ('GHS', 2), ('GIP', 2), ('GMD', 2), ('GNF', 0),
+    ('GTQ', 2), ('GYD', 2), ('HKD', 2), ('HNL', 2), ('HTG', 2), ('HUF', 2), ('IDR', 2), ('ILS', 2),
+    ('INR', 2), ('IQD', 3), ('IRR', 2), ('ISK', 0), ('JMD', 2), ('JOD', 3), ('JPY', 0), ('KES', 2),
+    ('KGS', 2), ('KHR', 2), ('KMF', 0), ('KPW', 2), ('KRW', 0), ('KWD', 3), ('KYD', 2), ('KZT', 2),
+    ('LAK', 2), ('LBP', 2), ('LKR', 2), ('LRD', 2), ('LSL', 2), ('LYD', 3), ('MAD', 2), ('MDL', 2),
+    ('MGA', 2), ('MKD', 2), ('MMK', 2), ('MNT', 2), ('MOP', 2), ('MRU', 2), ('MUR', 2), ('MVR', 2),
+    ('MWK', 2), ('MXN', 2), ('MXV', 2), ('MYR', 2), ('MZN', 2), ('NAD', 2), ('NGN', 2), ('NIO', 2),
+    ('NOK', 2), ('NPR', 2), ('NZD', 2), ('OMR', 3), ('PAB', 2), ('PEN', 2), ('PGK', 2), ('PHP', 2),
+    ('PKR', 2), ('PLN', 2), ('PYG', 0), ('QAR', 2), ('RON', 2), ('RSD', 2), ('RUB', 2), ('RWF', 0),
+    ('SAR', 2), ('SBD', 2), ('SCR', 2), ('SDG', 2), ('SEK', 2), ('SGD', 2), ('SHP', 2), ('SLE', 2),
+    ('SOS', 2), ('SRD', 2), ('SSP', 2), ('STN', 2), ('SVC', 2), ('SYP', 2), ('SZL', 2), ('THB', 2),
+    ('TJS', 2), ('TMT', 2), ('TND', 3), ('TOP', 2), ('TRY', 2), ('TTD', 2), ('TWD', 2), ('TZS', 2),
+    ('UAH', 2), ('UGX', 0), ('USD', 2), ('USN', 2), ('UYI', 0), ('UYU', 2), ('UYW', 4), ('UZS', 2),
+    ('VED', 2), ('VES', 2), ('VND', 0), ('VUV', 0), ('WST', 2), ('XAF', 0), ('XCD', 2), ('XCG', 2),
+    ('XOF', 0), ('XPF', 0), ('YER', 2), ('ZAR', 2), ('ZMW', 2), ('ZWG', 2);
+
+-- accounts created before validation may hold any three characters. Lowercase codes are
+-- uppercased, folding the external accounts of both spellings into one first, and any code
+-- that still isn't ISO 4217 stops the migration: money movements look accounts up through
+-- currencies and would no longer find them.
+UPDATE ledger_entries e
+SET account_id = keep.id
+FROM business_accounts dup
+         JOIN business_accounts keep
+              ON keep.business_id = dup.business_id AND keep.account_type = 'external' AND keep.currency = upper(dup.currency)
+WHERE e.account_id = dup.id AND dup.account_type = 'external' AND dup.currency <> upper(dup.currency);
+
+UPDATE business_accounts keep
+SET balance = keep.balance + dup.balance
+FROM business_accounts dup
+WHERE keep.business_id = dup.business_id AND keep.account_type = 'external' AND keep.currency = upper(dup.currency)
+  AND dup.account_type = 'external' AND dup.currency <> upper(dup.currency);
+
+DELETE FROM business_accounts dup
+    USING business_accounts keep
+WHERE keep.business_id = dup.business_id AND keep.account_type = 'external' AND keep.currency = upper(dup.currency)
+  AND dup.account_type = 'external' AND dup.currency <> upper(dup.currency);
+
+UPDATE business_accounts SET currency = upper(currency) WHERE currency <> upper(currency);
+
+DO $$
+DECLARE
+    unknown TEXT;
+BEGIN
+    SELECT string_agg(DISTINCT a.currency, ', ') INTO unknown
+    FROM business_accounts a
+    WHERE NOT EXISTS (SELECT 1 FROM currencies c WHERE c.code = a.currency);
+    IF unknown IS NOT NULL THEN
+        RAISE EXCEPTION 'business_accounts hold currency codes that are not ISO 4217: %', unknown
+            USING HINT = 'update those accounts to an ISO 4217 code and run the migrations again';
+    END IF;
+END
+$$;
+
+ALTER TABLE business_accounts
+    ADD CONSTRAINT fk_business_accounts_currency
+        FOREIGN KEY (currency)
+            REFERENCES currencies(code)
+        NOT VALID;
+
+ALTER TABLE business_accounts VALIDATE CONSTRAINT fk_business_accounts_currency;
+
+-- the scale of every amount now follows its currency instead of a fixed two decimals
+ALTER TABLE business_accounts ALTER COLUMN balance TYPE NUMERIC;
+ALTER TABLE transactions ALTER COLUMN amount TYPE NUMERIC;
+ALTER TABLE ledger_entries ALTER COLUMN amount TYPE NUMERIC;
+
+ALTER TABLE transactions ADD COLUMN currency CHAR(3);
+
+UPDATE transactions t
+SET currency = a.currency
+FROM business_accounts a
+WHERE a.id = COALESCE(t.to_account_id, t.from_account_id);
+
+-- XXX is the ISO code for "no currency", used where both accounts have since been deleted
+UPDATE transactions SET currency = 'XXX' WHERE currency IS NULL;
+
+ALTER TABLE transactions ALTER COLUMN currency SET NOT NULL;
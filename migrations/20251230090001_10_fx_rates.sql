@@ -0,0 +1,66 @@
+CREATE UNIQUE INDEX uq_business_accounts_fx
+    ON business_accounts(business_id, currency)
+    WHERE account_type = 'fx';
+
+-- admin managed, 1 base_currency = rate quote_currency while now() is inside [valid_from, valid_to)
+CREATE TABLE fx_rates (
+                          id BIGSERIAL PRIMARY KEY,
+                          base_currency CHAR(3) NOT NULL,
+                          quote_currency CHAR(3) NOT NULL,
+                          rate NUMERIC NOT NULL,
+                          valid_from TIMESTAMPTZ NOT NULL DEFAULT now(),
+                          valid_to TIMESTAMPTZ,
+                          created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                          CONSTRAINT chk_fx_rates_rate_positive
+                              CHECK (rate > 0),
+
+                          CONSTRAINT chk_fx_rates_window
+                              CHECK (valid_to IS NULL OR valid_to > valid_from),
+
+                          CONSTRAINT fk_fx_rates_base_currency
+                              FOREIGN KEY (base_currency)
+                                  REFERENCES currencies(code),
+
+                          CONSTRAINT fk_fx_rates_quote_currency
+                              FOREIGN KEY (quote_currency)
+                                  REFERENCES currencies(code)
+);
+
+CREATE INDEX idx_fx_rates_pair_valid_from ON fx_rates(base_currency, quote_currency, valid_from DESC);
+
+-- a rate locked for one business until expires_at, consumed by the transfer that uses it
+CREATE TABLE fx_quotes (
+                           id BIGSERIAL PRIMARY KEY,
+                           business_id BIGINT NOT NULL,
+                           fx_rate_id BIGINT NOT NULL,
+                           from_currency CHAR(3) NOT NULL,
+                           to_currency CHAR(3) NOT NULL,
+                           rate NUMERIC NOT NULL,
+                           expires_at TIMESTAMPTZ NOT NULL,
+                           transaction_id BIGINT,
+                           created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                           CONSTRAINT fk_fx_quotes_business
+                               FOREIGN KEY (business_id)
+                                   REFERENCES businesses(id)
+                                   ON DELETE CASCADE,
+
+                           CONSTRAINT fk_fx_quotes_rate
+                               FOREIGN KEY (fx_rate_id)
+                                   REFERENCES fx_rates(id),
+
+                           CONSTRAINT fk_fx_quotes_transaction
+                               FOREIGN KEY (transaction_id)
+                                   REFERENCES transactions(id)
+                                   ON DELETE SET NULL
+);
+
+CREATE INDEX idx_fx_quotes_business_id ON fx_quotes(business_id);
+
+-- cross-currency transfers: `amount` / `currency` are the source side
+ALTER TABLE transactions
+    ADD COLUMN destination_amount NUMERIC,
+    ADD COLUMN destination_currency CHAR(3),
+    ADD COLUMN fx_rate NUMERIC,
+    ADD COLUMN fx_quote_id BIGINT;
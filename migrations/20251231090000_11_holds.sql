@@ -0,0 +1,48 @@
+CREATE TYPE hold_status_enum AS ENUM ('active', 'captured', 'voided', 'expired');
+
+-- sum of the account's active holds, kept under the account row lock next to `balance`:
+-- available balance = balance - held_balance, the ledger only sees captures
+ALTER TABLE business_accounts
+    ADD COLUMN held_balance NUMERIC NOT NULL DEFAULT 0,
+    ADD CONSTRAINT chk_business_accounts_held_balance
+        CHECK (held_balance >= 0);
+
+CREATE TABLE holds (
+                       id BIGSERIAL PRIMARY KEY,
+                       business_id BIGINT NOT NULL,
+                       account_id BIGINT NOT NULL,
+                       amount NUMERIC NOT NULL,
+                       currency CHAR(3) NOT NULL,
+                       status hold_status_enum NOT NULL DEFAULT 'active',
+                       captured_amount NUMERIC,
+                       capture_transaction_id BIGINT,
+                       reference_id TEXT,
+                       idempotency_key TEXT NOT NULL,
+                       expires_at TIMESTAMPTZ NOT NULL,
+                       created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                       resolved_at TIMESTAMPTZ,
+
+                       CONSTRAINT chk_holds_amount_positive
+                           CHECK (amount > 0),
+
+                       CONSTRAINT fk_holds_business
+                           FOREIGN KEY (business_id)
+                               REFERENCES businesses(id)
+                               ON DELETE CASCADE,
+
+                       CONSTRAINT fk_holds_account
+                           FOREIGN KEY (account_id)
+                               REFERENCES business_accounts(id)
+                               ON DELETE CASCADE,
+
+                       CONSTRAINT fk_holds_capture_transaction
+                           FOREIGN KEY (capture_transaction_id)
+                               REFERENCES transactions(id)
+                               ON DELETE SET NULL,
+
+                       CONSTRAINT uq_holds_idempotency
+                           UNIQUE (business_id, idempotency_key)
+);
+
+CREATE INDEX idx_holds_account_id ON holds(account_id);
+CREATE INDEX idx_holds_active_expires_at ON holds(expires_at) WHERE status = 'active';
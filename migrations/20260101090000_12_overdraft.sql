@@ -0,0 +1,7 @@
+-- spending may take the available balance down to -overdraft_limit,
+-- allow_negative lifts the floor entirely (e.g. settlement or fee accounts)
+ALTER TABLE business_accounts
+    ADD COLUMN overdraft_limit NUMERIC NOT NULL DEFAULT 0,
+    ADD COLUMN allow_negative BOOLEAN NOT NULL DEFAULT false,
+    ADD CONSTRAINT chk_business_accounts_overdraft_limit
+        CHECK (overdraft_limit >= 0);
@@ -0,0 +1,26 @@
+ALTER TYPE account_status_enum ADD VALUE IF NOT EXISTS 'closed';
+
+-- append-only trail of account status changes and who made them
+CREATE TABLE account_status_changes (
+                                        id BIGSERIAL PRIMARY KEY,
+                                        business_id BIGINT NOT NULL,
+                                        account_id BIGINT NOT NULL,
+                                        from_status account_status_enum NOT NULL,
+                                        to_status account_status_enum NOT NULL,
+                                        reason TEXT NOT NULL,
+                                        actor_type TEXT NOT NULL, -- api_key | admin | system
+                                        actor_id BIGINT,
+                                        created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                                        CONSTRAINT fk_account_status_changes_business
+                                            FOREIGN KEY (business_id)
+                                                REFERENCES businesses(id)
+                                                ON DELETE CASCADE,
+
+                                        CONSTRAINT fk_account_status_changes_account
+                                            FOREIGN KEY (account_id)
+                                                REFERENCES business_accounts(id)
+                                                ON DELETE CASCADE
+);
+
+CREATE INDEX idx_account_status_changes_account_id ON account_status_changes(account_id, created_at DESC);
@@ -0,0 +1,21 @@
+-- append-only trail of business suspensions and reinstatements
+CREATE TABLE business_status_changes (
+                                         id BIGSERIAL PRIMARY KEY,
+                                         business_id BIGINT NOT NULL,
+                                         from_status business_status_enum NOT NULL,
+                                         to_status business_status_enum NOT NULL,
+                                         reason TEXT NOT NULL,
+                                         admin_id BIGINT NOT NULL,
+                                         created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                                         CONSTRAINT fk_business_status_changes_business
+                                             FOREIGN KEY (business_id)
+                                                 REFERENCES businesses(id)
+                                                 ON DELETE CASCADE,
+
+                                         CONSTRAINT fk_business_status_changes_admin
+                                             FOREIGN KEY (admin_id)
+                                                 REFERENCES admins(id)
+);
+
+CREATE INDEX idx_business_status_changes_business_id ON business_status_changes(business_id, created_at DESC);
@@ -0,0 +1,7 @@
+-- non-secret start of the raw key so owners can tell their keys apart, unknown for keys issued before
+ALTER TABLE api_keys ADD COLUMN key_prefix TEXT;
+
+-- '*' grants every scope, existing keys keep full access
+ALTER TABLE api_keys ADD COLUMN scopes TEXT[] NOT NULL DEFAULT '{*}';
+
+CREATE UNIQUE INDEX uq_api_keys_key_hash ON api_keys(key_hash);
@@ -0,0 +1,4 @@
+-- set once the business has been told a rotated key is about to stop working
+ALTER TABLE api_keys ADD COLUMN expiry_warned_at TIMESTAMPTZ;
+
+CREATE INDEX idx_api_keys_expiring ON api_keys(expires_at) WHERE status = 'expiring';
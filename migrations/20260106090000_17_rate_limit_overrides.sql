@@ -0,0 +1,24 @@
+-- replaces the default token bucket budgets for a whole business, or for one of its keys
+CREATE TABLE rate_limit_overrides (
+                                      id BIGSERIAL PRIMARY KEY,
+                                      business_id BIGINT NOT NULL,
+                                      api_key_id BIGINT,
+                                      read_burst INT NOT NULL CHECK (read_burst > 0),
+                                      read_per_minute INT NOT NULL CHECK (read_per_minute > 0),
+                                      write_burst INT NOT NULL CHECK (write_burst > 0),
+                                      write_per_minute INT NOT NULL CHECK (write_per_minute > 0),
+                                      updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                                      CONSTRAINT fk_rate_limit_overrides_business
+                                          FOREIGN KEY (business_id)
+                                              REFERENCES businesses(id)
+                                              ON DELETE CASCADE,
+
+                                      CONSTRAINT fk_rate_limit_overrides_api_key
+                                          FOREIGN KEY (api_key_id)
+                                              REFERENCES api_keys(id)
+                                              ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX uq_rate_limit_overrides_business ON rate_limit_overrides(business_id) WHERE api_key_id IS NULL;
+CREATE UNIQUE INDEX uq_rate_limit_overrides_api_key ON rate_limit_overrides(api_key_id) WHERE api_key_id IS NOT NULL;
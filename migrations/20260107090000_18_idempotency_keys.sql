@@ -0,0 +1,44 @@
+-- every idempotency key a business used, with what the request looked like and what it got back
+CREATE TABLE idempotency_keys (
+                                  id BIGSERIAL PRIMARY KEY,
+                                  business_id BIGINT NOT NULL,
+                                  scope TEXT NOT NULL, -- keys of different endpoint families don't collide
+                                  idempotency_key TEXT NOT NULL,
+                                  request_fingerprint TEXT, -- NULL for keys used before fingerprints were recorded
+                                  response_status SMALLINT, -- NULL until the first request finished
+                                  response_body JSONB,
+                                  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                                  expires_at TIMESTAMPTZ NOT NULL,
+
+                                  CONSTRAINT fk_idempotency_keys_business
+                                      FOREIGN KEY (business_id)
+                                          REFERENCES businesses(id)
+                                          ON DELETE CASCADE,
+
+                                  CONSTRAINT uq_idempotency_keys
+                                      UNIQUE (business_id, scope, idempotency_key)
+);
+
+CREATE INDEX idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
+
+-- keys still inside the default ttl keep replaying what they created
+INSERT INTO idempotency_keys (business_id, scope, idempotency_key, response_status, response_body, created_at, expires_at)
+SELECT business_id, 'transactions', idempotency_key, 201,
+       jsonb_build_object('success', true, 'data', id, 'error', NULL),
+       created_at, created_at + INTERVAL '1 day'
+FROM transactions
+WHERE created_at > now() - INTERVAL '1 day';
+
+INSERT INTO idempotency_keys (business_id, scope, idempotency_key, response_status, response_body, created_at, expires_at)
+SELECT business_id, 'holds', idempotency_key, 201,
+       jsonb_build_object('success', true, 'data', id, 'error', NULL),
+       created_at, created_at + INTERVAL '1 day'
+FROM holds
+WHERE created_at > now() - INTERVAL '1 day';
+
+-- uniqueness moves to idempotency_keys, an expired key may be used again
+ALTER TABLE transactions DROP CONSTRAINT uq_transactions_idempotency;
+CREATE INDEX idx_transactions_idempotency ON transactions(business_id, idempotency_key);
+
+ALTER TABLE holds DROP CONSTRAINT uq_holds_idempotency;
+CREATE INDEX idx_holds_idempotency ON holds(business_id, idempotency_key);
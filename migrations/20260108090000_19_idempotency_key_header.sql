@@ -0,0 +1,17 @@
+-- keys sent in the Idempotency-Key header of admin requests belong to the admin
+ALTER TABLE idempotency_keys ALTER COLUMN business_id DROP NOT NULL;
+ALTER TABLE idempotency_keys ADD COLUMN admin_id BIGINT;
+
+ALTER TABLE idempotency_keys
+    ADD CONSTRAINT fk_idempotency_keys_admin
+        FOREIGN KEY (admin_id)
+            REFERENCES admins(id)
+            ON DELETE CASCADE;
+
+ALTER TABLE idempotency_keys
+    ADD CONSTRAINT chk_idempotency_keys_owner
+        CHECK ((business_id IS NULL) <> (admin_id IS NULL));
+
+CREATE UNIQUE INDEX uq_idempotency_keys_admin
+    ON idempotency_keys(admin_id, scope, idempotency_key)
+    WHERE admin_id IS NOT NULL;
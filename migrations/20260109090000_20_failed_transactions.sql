@@ -0,0 +1,9 @@
+-- rejected movements are kept as failed transactions, with why they were rejected
+ALTER TABLE transactions ADD COLUMN failure_code TEXT;
+ALTER TABLE transactions ADD COLUMN failure_reason TEXT;
+
+UPDATE transactions SET failure_code = 'unknown' WHERE status = 'failed';
+
+ALTER TABLE transactions
+    ADD CONSTRAINT chk_transactions_failure
+        CHECK ((status = 'failed') = (failure_code IS NOT NULL));
@@ -0,0 +1,58 @@
+-- Row level security as a second line of tenant isolation, under the business_id filters of the queries.
+-- Business requests run as ledger_tenant with app.business_id set to their business, admin requests
+-- run as ledger_admin. Background workers keep the login role, which owns the tables and so bypasses
+-- the policies. Creating the roles needs CREATEROLE; roles are cluster wide and may already exist.
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'ledger_tenant') THEN
+        CREATE ROLE ledger_tenant NOLOGIN;
+    END IF;
+    IF NOT EXISTS (SELECT 1 FROM pg_roles WHERE rolname = 'ledger_admin') THEN
+        CREATE ROLE ledger_admin NOLOGIN;
+    END IF;
+END
+$$;
+
+-- the login role switches to them with SET ROLE
+GRANT ledger_tenant TO CURRENT_USER;
+GRANT ledger_admin TO CURRENT_USER;
+
+GRANT USAGE ON SCHEMA public TO ledger_tenant, ledger_admin;
+GRANT SELECT, INSERT, UPDATE, DELETE ON ALL TABLES IN SCHEMA public TO ledger_tenant, ledger_admin;
+GRANT USAGE, SELECT ON ALL SEQUENCES IN SCHEMA public TO ledger_tenant, ledger_admin;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public
+    GRANT SELECT, INSERT, UPDATE, DELETE ON TABLES TO ledger_tenant, ledger_admin;
+ALTER DEFAULT PRIVILEGES IN SCHEMA public
+    GRANT USAGE, SELECT ON SEQUENCES TO ledger_tenant, ledger_admin;
+
+-- the business of the request, NULL when none is set so tenant policies match nothing
+CREATE FUNCTION current_business_id() RETURNS BIGINT
+    LANGUAGE sql STABLE
+    AS $$ SELECT NULLIF(current_setting('app.business_id', true), '')::BIGINT $$;
+
+ALTER TABLE business_accounts ENABLE ROW LEVEL SECURITY;
+ALTER TABLE transactions ENABLE ROW LEVEL SECURITY;
+ALTER TABLE webhooks ENABLE ROW LEVEL SECURITY;
+ALTER TABLE webhook_events ENABLE ROW LEVEL SECURITY;
+
+CREATE POLICY tenant_isolation ON business_accounts TO ledger_tenant
+    USING (business_id = current_business_id())
+    WITH CHECK (business_id = current_business_id());
+
+CREATE POLICY tenant_isolation ON transactions TO ledger_tenant
+    USING (business_id = current_business_id())
+    WITH CHECK (business_id = current_business_id());
+
+CREATE POLICY tenant_isolation ON webhooks TO ledger_tenant
+    USING (business_id = current_business_id())
+    WITH CHECK (business_id = current_business_id());
+
+-- deliveries belong to the business of their webhook
+CREATE POLICY tenant_isolation ON webhook_events TO ledger_tenant
+    USING (EXISTS (SELECT 1 FROM webhooks w WHERE w.id = webhook_id AND w.business_id = current_business_id()))
+    WITH CHECK (EXISTS (SELECT 1 FROM webhooks w WHERE w.id = webhook_id AND w.business_id = current_business_id()));
+
+CREATE POLICY admin_bypass ON business_accounts TO ledger_admin USING (true) WITH CHECK (true);
+CREATE POLICY admin_bypass ON transactions TO ledger_admin USING (true) WITH CHECK (true);
+CREATE POLICY admin_bypass ON webhooks TO ledger_admin USING (true) WITH CHECK (true);
+CREATE POLICY admin_bypass ON webhook_events TO ledger_admin USING (true) WITH CHECK (true);
@@ -0,0 +1,15 @@
+-- the account's balance right after each entry, written when the entry is posted so a history
+-- page reads its running balances instead of summing the account's whole ledger
+ALTER TABLE ledger_entries ADD COLUMN balance_after NUMERIC;
+
+UPDATE ledger_entries e
+SET balance_after = h.running_balance
+FROM (
+    SELECT id,
+           SUM(CASE WHEN direction = 'credit' THEN amount ELSE -amount END)
+               OVER (PARTITION BY account_id ORDER BY created_at, id) AS running_balance
+    FROM ledger_entries
+) h
+WHERE h.id = e.id;
+
+ALTER TABLE ledger_entries ALTER COLUMN balance_after SET NOT NULL;
@@ -0,0 +1,3 @@
+-- per-business, per-currency account holding the FX gains and losses realised by quoted
+-- conversions, added on its own because a new enum value can't be used in the migration that adds it
+ALTER TYPE account_type_enum ADD VALUE IF NOT EXISTS 'fx_gain_loss';
@@ -0,0 +1,7 @@
+CREATE UNIQUE INDEX uq_business_accounts_fx_gain_loss
+    ON business_accounts(business_id, currency)
+    WHERE account_type = 'fx_gain_loss';
+
+-- the gap between a quote's rate and the pair's rate when the transfer ran, in the destination
+-- currency, positive for a gain and negative for a loss
+ALTER TABLE transactions ADD COLUMN fx_gain_loss NUMERIC;
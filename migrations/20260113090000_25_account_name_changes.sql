@@ -0,0 +1,24 @@
+-- append-only trail of account renames and who made them, next to account_status_changes
+CREATE TABLE account_name_changes (
+                                      id BIGSERIAL PRIMARY KEY,
+                                      business_id BIGINT NOT NULL,
+                                      account_id BIGINT NOT NULL,
+                                      from_name TEXT NOT NULL,
+                                      to_name TEXT NOT NULL,
+                                      reason TEXT NOT NULL,
+                                      actor_type TEXT NOT NULL, -- api_key | admin | system
+                                      actor_id BIGINT,
+                                      created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+
+                                      CONSTRAINT fk_account_name_changes_business
+                                          FOREIGN KEY (business_id)
+                                              REFERENCES businesses(id)
+                                              ON DELETE CASCADE,
+
+                                      CONSTRAINT fk_account_name_changes_account
+                                          FOREIGN KEY (account_id)
+                                              REFERENCES business_accounts(id)
+                                              ON DELETE CASCADE
+);
+
+CREATE INDEX idx_account_name_changes_account_id ON account_name_changes(account_id, created_at DESC);
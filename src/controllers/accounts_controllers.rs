@@ -1,12 +1,16 @@
 
 use std::sync::Arc;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::{Extension, Json};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use crate::AppState;
-use crate::models::accounts_models::{Account, NewAccount};
-use crate::models::common::{AccountId, ApiResponse};
+use crate::error::{AppError, ErrorCode};
+use crate::models::accounts_models::{Account, AccountBalance, AccountHistoryPage, AccountHistoryQuery, AccountReconciliation, AccountStatusChange, BalanceQuery, LedgerEntry, NewAccount, UpdateAccountRequest, ACCOUNT_STATUSES};
+use rust_decimal::Decimal;
+use crate::models::transaction_models::decode_cursor;
+use crate::models::common::{AccountId, ApiKeyId, ApiResponse};
+use crate::services::db_operations::DbOperations;
 
 pub async fn get_accounts(
     State(app_state): State<Arc<AppState>>,
@@ -23,10 +27,7 @@ pub async fn get_accounts(
         ),
         Err(e) => {
             tracing::error!("error occurred while getting accounts {}", e) ;
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<Vec<Account>>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<Vec<Account>>()
         },
     }
 }
@@ -36,6 +37,7 @@ pub async fn get_account_balance(
     State(app_state): State<Arc<AppState>>,
     Extension(business): Extension<AccountId>,
     Path(account_id): Path<i64>,
+    Query(query): Query<BalanceQuery>,
 ) -> impl IntoResponse {
 
     tracing::info!("getting balance for account {}", account_id) ;
@@ -45,23 +47,21 @@ pub async fn get_account_balance(
         .unwrap_or(false)
     {
         tracing::warn!("unauthorized account tried to get balance for account {}", account_id) ;
-        return (
-            StatusCode::FORBIDDEN,
-            Json(ApiResponse::<String>::error("Unauthorized account".into())),
-        );
+        return AppError::new(ErrorCode::AccountAccessDenied, "Unauthorized account").into_api::<AccountBalance>();
     }
     tracing::info!("getting balance") ;
-    match app_state.database_connector.get_account_balance(account_id).await {
+    let balance = match query.as_of {
+        Some(as_of) => app_state.database_connector.get_account_balance_as_of(account_id, as_of).await,
+        None => app_state.database_connector.get_account_balance(account_id).await,
+    };
+    match balance {
         Ok(balance) => (
             StatusCode::OK,
-            Json(ApiResponse::success(balance.to_string())),
+            Json(ApiResponse::success(balance)),
         ),
         Err(e) => {
             tracing::error!("got an error while getting balance {}", e) ;
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<AccountBalance>()
         },
     }
 }
@@ -79,10 +79,7 @@ pub async fn get_account_details(
         .unwrap_or(false)
     {
         tracing::warn!("unauthorized account tried to get account details for account {}", account_id) ;
-        return (
-            StatusCode::FORBIDDEN,
-            Json(ApiResponse::<Account>::error("Unauthorized account".into())),
-        );
+        return AppError::new(ErrorCode::AccountAccessDenied, "Unauthorized account").into_api::<Account>();
     }
     tracing::info!("going to get account details") ;
     match app_state.database_connector.get_account_details(account_id).await {
@@ -92,10 +89,7 @@ pub async fn get_account_details(
         ),
         Err(e) => {
             tracing::error!("got an error while getting account details {}", e) ;
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<Account>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<Account>()
         },
     }
 }
@@ -104,23 +98,29 @@ pub async fn get_account_details(
 pub async fn create_account(
     State(app_state): State<Arc<AppState>>,
     Extension(business): Extension<AccountId>,
-    Json(new_account): Json<NewAccount>,
+    Json(mut new_account): Json<NewAccount>,
 ) -> impl IntoResponse {
     tracing::info!("creating new account for business {}", business.account_id) ;
     if new_account.name.trim().is_empty() {
         tracing::warn!("account name is empty") ;
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::<i64>::error("Account name is required".into())),
-        );
+        return AppError::new(ErrorCode::ValidationFailed, "Account name is required").into_api::<i64>();
     }
 
-    if new_account.currency.len() != 3 {
-        tracing::warn!("invalid currency") ;
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::<i64>::error("Invalid currency code".into())),
-        );
+    new_account.currency = new_account.currency.trim().to_ascii_uppercase();
+    match app_state.database_connector.get_currency_minor_units(&new_account.currency).await {
+        Ok(Some(minor_units)) => {
+            if let Err(message) = validate_overdraft_limit(new_account.overdraft_limit, minor_units as u32) {
+                return AppError::new(ErrorCode::ValidationFailed, message).into_api::<i64>();
+            }
+        },
+        Ok(None) => {
+            tracing::warn!("invalid currency {}", new_account.currency) ;
+            return AppError::new(ErrorCode::InvalidCurrency, "Invalid currency code, expected an ISO 4217 code such as USD").into_api::<i64>();
+        },
+        Err(e) => {
+            tracing::error!("error while checking the currency {}", e) ;
+            return AppError::from(e).into_api::<i64>();
+        }
     }
     tracing::info!("creating a new account") ;
     match app_state.database_connector
@@ -133,10 +133,308 @@ pub async fn create_account(
         ),
         Err(e) => {
             tracing::error!("error while creating a new account {}", e) ;
+            AppError::from(e).into_api::<i64>()
+        },
+    }
+}
+
+
+pub async fn update_account(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business): Extension<AccountId>,
+    Extension(api_key): Extension<ApiKeyId>,
+    Path(account_id): Path<i64>,
+    Json(req): Json<UpdateAccountRequest>,
+) -> impl IntoResponse {
+    tracing::info!("updating account {}", account_id) ;
+    if !app_state.database_connector
+        .validate_account_ownership(business.account_id, account_id)
+        .await
+        .unwrap_or(false)
+    {
+        tracing::warn!("unauthorized account tried to update account {}", account_id) ;
+        return AppError::new(ErrorCode::AccountAccessDenied, "Unauthorized account").into_api::<Account>();
+    }
+
+    if let Some(status) = &req.status
+        && !ACCOUNT_STATUSES.contains(&status.as_str())
+    {
+        return AppError::new(ErrorCode::ValidationFailed, format!("unknown account status {}", status)).into_api::<Account>();
+    }
+    if req.name.as_ref().is_some_and(|name| name.trim().is_empty()) {
+        return AppError::new(ErrorCode::ValidationFailed, "name must not be empty").into_api::<Account>();
+    }
+    // renames and status changes are audited, so they have to say why
+    if (req.name.is_some() || req.status.is_some())
+        && req.reason.as_ref().is_none_or(|reason| reason.trim().is_empty())
+    {
+        return AppError::new(ErrorCode::ValidationFailed, "reason is required to rename an account or change its status").into_api::<Account>();
+    }
+
+    match execute_account_update(&app_state, business.account_id, api_key.key_id, account_id, &req).await {
+        Ok(response) => response,
+        Err(e) => e.into_api::<Account>(),
+    }
+}
+
+
+async fn execute_account_update(
+    app_state: &Arc<AppState>,
+    business_id: i64,
+    key_id: i64,
+    account_id: i64,
+    req: &UpdateAccountRequest,
+) -> Result<(StatusCode, Json<ApiResponse<Account>>), AppError> {
+
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    // locked so a status change can't race a posting or a hold on the same account
+    let account = DbOperations::lock_account(&mut tx, business_id, account_id)
+        .await
+        .map_err(AppError::missing(ErrorCode::AccountNotFound, "Account not found"))?;
+
+    if account.status == "closed" {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::AccountClosed, "Account is closed"));
+    }
+
+    if let Some(overdraft_limit) = req.overdraft_limit
+        && let Err(message) = validate_overdraft_limit(overdraft_limit, account.minor_units)
+    {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::ValidationFailed, message));
+    }
+
+    if let Some(status) = req.status.as_deref() {
+        if status == account.status {
+            tx.rollback().await.ok();
+            return Err(AppError::new(ErrorCode::StatusUnchanged, format!("Account is already {}", status)));
+        }
+        // closing is final, nothing may be left on the account or reserved against it
+        if status == "closed" && (account.balance != Decimal::ZERO || account.held_balance != Decimal::ZERO) {
+            tx.rollback().await.ok();
+            return Err(AppError::new(
+                ErrorCode::AccountNotEmpty,
+                "Only an account with a zero balance and no active holds can be closed",
+            ));
+        }
+    }
+
+    let reason = req.reason.as_deref().unwrap_or_default().trim();
+    if let Some(name) = req.name.as_deref() {
+        DbOperations::insert_account_name_change(
+            &mut tx,
+            business_id,
+            account_id,
+            name.trim(),
+            reason,
+            "api_key",
+            Some(key_id),
+        )
+            .await?;
+    }
+
+    DbOperations::update_account_settings(
+        &mut tx,
+        account_id,
+        req.name.as_deref().map(str::trim),
+        req.status.as_deref(),
+        req.overdraft_limit,
+        req.allow_negative,
+    )
+        .await?;
+
+    let mut queue_messages = Vec::new();
+    if let Some(status) = req.status.as_deref() {
+        let change_id = DbOperations::insert_account_status_change(
+            &mut tx,
+            business_id,
+            account_id,
+            &account.status,
+            status,
+            reason,
+            "api_key",
+            Some(key_id),
+        )
+            .await?;
+
+        // -------- DOMAIN EVENT --------
+
+        let payload = serde_json::json!({
+            "event": "account.status_changed",
+            "data": {
+                "account_id": account_id,
+                "business_id": business_id,
+                "from_status": account.status,
+                "to_status": status,
+                "reason": reason,
+                "status_change_id": change_id,
+                "actor": { "type": "api_key", "id": key_id }
+            }
+        });
+        queue_messages.extend(
+            app_state
+                .database_connector
+                .publish_domain_event_txn(&mut tx, business_id, "account.status_changed", payload)
+                .await?,
+        );
+        if status == "frozen" {
+            let payload = serde_json::json!({
+                "event": "account.frozen",
+                "data": {
+                    "account_id": account_id,
+                    "business_id": business_id,
+                    "reason": reason
+                }
+            });
+            queue_messages.extend(
+                app_state
+                    .database_connector
+                    .publish_domain_event_txn(&mut tx, business_id, "account.frozen", payload)
+                    .await?,
+            );
+        }
+    }
+
+    tx.commit().await?;
+
+    // adding every delivery to the unbounded queue for the webhook worker
+    for message in queue_messages {
+        app_state
+            .event_queue
+            .send(message)
+            .expect("Unable to add Event Id to the Queue");
+    }
+
+    let updated = app_state.database_connector.get_account_details(account_id).await?;
+    Ok((
+        StatusCode::OK,
+        Json(ApiResponse::success(updated)),
+    ))
+}
+
+
+pub async fn get_account_status_history(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business): Extension<AccountId>,
+    Path(account_id): Path<i64>,
+) -> impl IntoResponse {
+    if !app_state.database_connector
+        .validate_account_ownership(business.account_id, account_id)
+        .await
+        .unwrap_or(false)
+    {
+        return AppError::new(ErrorCode::AccountAccessDenied, "Unauthorized account").into_api::<Vec<AccountStatusChange>>();
+    }
+
+    match app_state.database_connector.get_account_status_changes(account_id).await {
+        Ok(changes) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(changes)),
+        ),
+        Err(e) => AppError::from(e).into_api::<Vec<AccountStatusChange>>(),
+    }
+}
+
+
+fn validate_overdraft_limit(overdraft_limit: Decimal, minor_units: u32) -> Result<(), String> {
+    if overdraft_limit < Decimal::ZERO || overdraft_limit.normalize().scale() > minor_units {
+        return Err(format!("overdraft_limit must be zero or positive with at most {} decimal places", minor_units));
+    }
+    Ok(())
+}
+
+
+pub async fn get_account_ledger_entries(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business): Extension<AccountId>,
+    Path(account_id): Path<i64>,
+) -> impl IntoResponse {
+    tracing::info!("getting ledger entries for account {}", account_id) ;
+    if !app_state.database_connector
+        .validate_account_ownership(business.account_id, account_id)
+        .await
+        .unwrap_or(false)
+    {
+        tracing::warn!("unauthorized account tried to get ledger entries for account {}", account_id) ;
+        return AppError::new(ErrorCode::AccountAccessDenied, "Unauthorized account").into_api::<Vec<LedgerEntry>>();
+    }
+    match app_state.database_connector.get_ledger_entries(account_id).await {
+        Ok(entries) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(entries)),
+        ),
+        Err(e) => {
+            tracing::error!("got an error while getting ledger entries {}", e) ;
+            AppError::from(e).into_api::<Vec<LedgerEntry>>()
+        },
+    }
+}
+
+
+pub async fn get_account_transactions(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business): Extension<AccountId>,
+    Path(account_id): Path<i64>,
+    Query(query): Query<AccountHistoryQuery>,
+) -> impl IntoResponse {
+    tracing::info!("getting transaction history for account {}", account_id) ;
+    if !app_state.database_connector
+        .validate_account_ownership(business.account_id, account_id)
+        .await
+        .unwrap_or(false)
+    {
+        tracing::warn!("unauthorized account tried to get transaction history for account {}", account_id) ;
+        return AppError::new(ErrorCode::AccountAccessDenied, "Unauthorized account").into_api::<AccountHistoryPage>();
+    }
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let cursor = match query.cursor.as_deref().map(decode_cursor) {
+        None => None,
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => {
+            return AppError::new(ErrorCode::InvalidCursor, "Invalid cursor").into_api::<AccountHistoryPage>();
+        }
+    };
+    match app_state.database_connector.get_account_history(account_id, cursor, limit).await {
+        Ok(page) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(page)),
+        ),
+        Err(e) => {
+            tracing::error!("got an error while getting transaction history {}", e) ;
+            AppError::from(e).into_api::<AccountHistoryPage>()
+        },
+    }
+}
+
+
+pub async fn reconcile_account(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business): Extension<AccountId>,
+    Path(account_id): Path<i64>,
+) -> impl IntoResponse {
+    tracing::info!("reconciling account {} against its ledger entries", account_id) ;
+    if !app_state.database_connector
+        .validate_account_ownership(business.account_id, account_id)
+        .await
+        .unwrap_or(false)
+    {
+        tracing::warn!("unauthorized account tried to reconcile account {}", account_id) ;
+        return AppError::new(ErrorCode::AccountAccessDenied, "Unauthorized account").into_api::<AccountReconciliation>();
+    }
+    match app_state.database_connector.reconcile_account(account_id).await {
+        Ok(reconciliation) => {
+            if !reconciliation.in_balance {
+                tracing::error!("account {} balance {} does not match ledger balance {}", account_id, reconciliation.balance, reconciliation.ledger_balance) ;
+            }
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(e.to_string())),
+                StatusCode::OK,
+                Json(ApiResponse::success(reconciliation)),
             )
         },
+        Err(e) => {
+            tracing::error!("got an error while reconciling account {}", e) ;
+            AppError::from(e).into_api::<AccountReconciliation>()
+        },
     }
 }
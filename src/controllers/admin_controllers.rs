@@ -1,11 +1,18 @@
 use std::sync::Arc;
-use axum::extract::State;
-use axum::{Form, Json};
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
+use rust_decimal::Decimal;
 use crate::{models, AppState};
-use crate::models::common::ApiResponse;
-use crate::services::other_services::{generate_api_key};
+use crate::error::{AppError, ErrorCode};
+use crate::models::bussiness_models::{validate_scopes, ApiKeyInfo, BusinessStatusRequest, NewApiKeyRequest, RotateApiKeyRequest, ALL_API_KEY_SCOPES, DEFAULT_ROTATION_GRACE_SECONDS, MAX_ROTATION_GRACE_SECONDS};
+use crate::models::common::{AccountId, ApiResponse};
+use crate::models::event_queue::WebhookQueueMessage;
+use crate::models::fx_models::{FxRate, NewFxRate};
+use crate::models::rate_limit_models::{RateLimitOverride, RateLimits};
+use crate::services::db_operations::DbOperations;
+use crate::services::other_services::{api_key_prefix, generate_api_key};
 
 pub async fn create_business(
     State(app_state): State<Arc<AppState>>,
@@ -17,10 +24,7 @@ pub async fn create_business(
             StatusCode::CREATED,
             Json(ApiResponse::success(id)),
         ),
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<i64>::error(err.to_string())),
-        ),
+        Err(err) => AppError::from(err).into_api::<i64>(),
     }
 }
 
@@ -34,12 +38,86 @@ pub async fn get_businesses(
             StatusCode::OK,
             Json(ApiResponse::success(businesses)),
         ),
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<Vec<models::bussiness_models::BusinessState>>::error(
-                err.to_string(),
-            )),
-        ),
+        Err(err) => AppError::from(err).into_api::<Vec<models::bussiness_models::BusinessState>>(),
+    }
+}
+
+
+
+pub async fn suspend_business(
+    State(app_state): State<Arc<AppState>>,
+    Extension(admin): Extension<AccountId>,
+    Path(business_id): Path<i64>,
+    Json(req): Json<BusinessStatusRequest>,
+) -> impl IntoResponse {
+    tracing::info!("suspending business {}", business_id) ;
+    change_business_status(&app_state, business_id, admin.account_id, "suspended", &req.reason).await
+}
+
+
+pub async fn reinstate_business(
+    State(app_state): State<Arc<AppState>>,
+    Extension(admin): Extension<AccountId>,
+    Path(business_id): Path<i64>,
+    Json(req): Json<BusinessStatusRequest>,
+) -> impl IntoResponse {
+    tracing::info!("reinstating business {}", business_id) ;
+    let response = change_business_status(&app_state, business_id, admin.account_id, "active", &req.reason).await;
+
+    if response.0 == StatusCode::OK {
+        // deliveries paused during the suspension go out again
+        match app_state.database_connector.get_pending_webhook_events_for_business(business_id).await {
+            Ok(events) => {
+                for (webhook_event_id, webhook_id) in events {
+                    let _ = app_state.event_queue.send(WebhookQueueMessage { webhook_event_id, webhook_id });
+                }
+            }
+            Err(e) => tracing::error!("unable to requeue webhooks of business {}: {}", business_id, e),
+        }
+    }
+    response
+}
+
+
+async fn change_business_status(
+    app_state: &Arc<AppState>,
+    business_id: i64,
+    admin_id: i64,
+    to_status: &str,
+    reason: &str,
+) -> (StatusCode, Json<ApiResponse<i64>>) {
+    if reason.trim().is_empty() {
+        return AppError::new(ErrorCode::ValidationFailed, "reason is required").into_api::<i64>();
+    }
+
+    let res = async {
+        let mut tx = app_state.database_connector.connector.begin().await?;
+
+        let from_status = match DbOperations::lock_business_status(&mut tx, business_id).await? {
+            Some(status) => status,
+            None => {
+                return Ok(AppError::new(ErrorCode::BusinessNotFound, "Business not found").into_api::<i64>());
+            }
+        };
+        if from_status == to_status {
+            return Ok(AppError::new(ErrorCode::StatusUnchanged, format!("Business is already {}", to_status)).into_api::<i64>());
+        }
+
+        DbOperations::update_business_status(&mut tx, business_id, &from_status, to_status, reason.trim(), admin_id).await?;
+        tx.commit().await?;
+
+        Ok::<_, sqlx::Error>((
+            StatusCode::OK,
+            Json(ApiResponse::success(business_id)),
+        ))
+    }.await;
+
+    match res {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("error while changing status of business {}: {}", business_id, e) ;
+            AppError::from(e).into_api::<i64>()
+        }
     }
 }
 
@@ -47,25 +125,30 @@ pub async fn get_businesses(
 
 pub async fn generate_api_keys(
     State(app_state): State<Arc<AppState>>,
-    Json(business_id): Json<i64>,
+    Json(req): Json<NewApiKeyRequest>,
 ) -> impl IntoResponse {
+    let (business_id, scopes) = match req {
+        NewApiKeyRequest::BusinessId(business_id) => (business_id, vec![ALL_API_KEY_SCOPES.to_string()]),
+        NewApiKeyRequest::Scoped { business_id, scopes } => (business_id, scopes),
+    };
     tracing::info!("generating api keys for business {}", business_id) ;
-    
+
+    if let Err(message) = validate_scopes(&scopes) {
+        return AppError::new(ErrorCode::ValidationFailed, message).into_api::<String>();
+    }
+
     if !app_state.database_connector
         .validate_business_id(business_id)
         .await
         .unwrap_or(false)
     {
         tracing::warn!("invalid business id {} passed", business_id) ;
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(ApiResponse::<String>::error("Invalid business".to_string())),
-        );
+        return AppError::new(ErrorCode::BusinessNotFound, "Business not found").into_api::<String>();
     }
 
     let (raw_key, hashed_key) = generate_api_key();
     tracing::info!("got the raw_key and the hashed_key {}, {}", raw_key, hashed_key) ;
-    match app_state.database_connector.store_api_key(business_id, &hashed_key).await {
+    match app_state.database_connector.store_api_key(business_id, &hashed_key, &api_key_prefix(&raw_key), &scopes).await {
         Ok(_) => {
             tracing::info!("created the new api key for the business sucessfully") ;
             
@@ -76,41 +159,144 @@ pub async fn generate_api_keys(
         },
         Err(e) => {
             tracing::error!("error occurred while creating api key {}", e) ; 
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<String>()
         },
     }
 }
 
 
+pub async fn get_business_api_keys(
+    State(app_state): State<Arc<AppState>>,
+    Path(business_id): Path<i64>,
+) -> impl IntoResponse {
+    tracing::info!("listing api keys of business {}", business_id) ;
+    match app_state.database_connector.get_api_keys(business_id).await {
+        Ok(keys) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(keys)),
+        ),
+        Err(e) => AppError::from(e).into_api::<Vec<ApiKeyInfo>>(),
+    }
+}
+
+
+pub async fn get_api_key(
+    State(app_state): State<Arc<AppState>>,
+    Path(key_id): Path<i64>,
+) -> impl IntoResponse {
+    match app_state.database_connector.get_api_key(None, key_id).await {
+        Ok(Some(key)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(key)),
+        ),
+        Ok(None) => AppError::new(ErrorCode::ApiKeyNotFound, "API key not found").into_api::<ApiKeyInfo>(),
+        Err(e) => AppError::from(e).into_api::<ApiKeyInfo>(),
+    }
+}
+
+
+pub async fn set_business_rate_limits(
+    State(app_state): State<Arc<AppState>>,
+    Path(business_id): Path<i64>,
+    Json(limits): Json<RateLimits>,
+) -> impl IntoResponse {
+    tracing::info!("setting rate limits of business {}", business_id) ;
+    if let Err(message) = limits.read.validate().and(limits.write.validate()) {
+        return AppError::new(ErrorCode::ValidationFailed, message).into_api::<RateLimitOverride>();
+    }
+    if let Err(sqlx::Error::RowNotFound) = app_state.database_connector.validate_business_id(business_id).await {
+        return AppError::new(ErrorCode::BusinessNotFound, "Business not found").into_api::<RateLimitOverride>();
+    }
+
+    match app_state.database_connector.upsert_rate_limit_override(business_id, None, &limits).await {
+        Ok(limits) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(limits)),
+        ),
+        Err(e) => AppError::from(e).into_api::<RateLimitOverride>(),
+    }
+}
+
+
+pub async fn set_api_key_rate_limits(
+    State(app_state): State<Arc<AppState>>,
+    Path(key_id): Path<i64>,
+    Json(limits): Json<RateLimits>,
+) -> impl IntoResponse {
+    tracing::info!("setting rate limits of api key {}", key_id) ;
+    if let Err(message) = limits.read.validate().and(limits.write.validate()) {
+        return AppError::new(ErrorCode::ValidationFailed, message).into_api::<RateLimitOverride>();
+    }
+    let business_id = match app_state.database_connector.get_api_key(None, key_id).await {
+        Ok(Some(key)) => key.business_id,
+        Ok(None) => {
+            return AppError::new(ErrorCode::ApiKeyNotFound, "API key not found").into_api::<RateLimitOverride>();
+        }
+        Err(e) => {
+            return AppError::from(e).into_api::<RateLimitOverride>();
+        }
+    };
+
+    match app_state.database_connector.upsert_rate_limit_override(business_id, Some(key_id), &limits).await {
+        Ok(limits) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(limits)),
+        ),
+        Err(e) => AppError::from(e).into_api::<RateLimitOverride>(),
+    }
+}
+
+
+pub async fn get_rate_limits(
+    State(app_state): State<Arc<AppState>>,
+    Path(business_id): Path<i64>,
+) -> impl IntoResponse {
+    match app_state.database_connector.get_rate_limit_overrides(business_id).await {
+        Ok(overrides) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(overrides)),
+        ),
+        Err(e) => AppError::from(e).into_api::<Vec<RateLimitOverride>>(),
+    }
+}
+
+
 pub async fn rotate_api_key(
     State(app_state): State<Arc<AppState>>,
-    Json((key_id, business_id)): Json<(i64, i64)>,
+    Json(req): Json<RotateApiKeyRequest>,
 ) -> impl IntoResponse {
+    let (key_id, business_id, grace_period_seconds) = match req {
+        RotateApiKeyRequest::KeyAndBusiness(key_id, business_id) => (key_id, business_id, None),
+        RotateApiKeyRequest::WithGracePeriod { key_id, business_id, grace_period_seconds } => {
+            (key_id, business_id, grace_period_seconds)
+        }
+    };
+    let grace_period_seconds = grace_period_seconds.unwrap_or(DEFAULT_ROTATION_GRACE_SECONDS);
+    if !(0..=MAX_ROTATION_GRACE_SECONDS).contains(&grace_period_seconds) {
+        return AppError::new(ErrorCode::ValidationFailed, format!(
+            "grace_period_seconds must be between 0 and {}",
+            MAX_ROTATION_GRACE_SECONDS
+        )).into_api::<String>();
+    }
     tracing::info!("rotating api key for business {}", business_id) ;
     let (raw_key, hashed_key) = generate_api_key();
-    let expires_at = chrono::Utc::now() + chrono::Duration::days(7);
-    tracing::warn!("expires at will be 7 days from the current date and time") ;
+    let expires_at = chrono::Utc::now() + chrono::Duration::seconds(grace_period_seconds);
+    tracing::warn!("the old key keeps working for {} seconds", grace_period_seconds) ;
     let mut tx = match app_state.database_connector.connector.begin().await {
         Ok(tx) => tx,
         Err(e) => {
             tracing::error!("got an error while creating a transaction to rotate the api key {}",e) ;
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            return AppError::from(e).into_api::<String>()
         }
     };
     tracing::info!("created a transaction to rotate the api key for business {}", business_id) ;
     let res = async {
-        app_state.database_connector
+        let scopes = app_state.database_connector
             .expire_api_key_txn(&mut tx, key_id, expires_at)
             .await?;
 
         app_state.database_connector
-            .store_api_key_txn(&mut tx, business_id, &hashed_key)
+            .store_api_key_txn(&mut tx, business_id, &hashed_key, &api_key_prefix(&raw_key), &scopes)
             .await?;
 
         Ok::<_, sqlx::Error>(())
@@ -122,7 +308,9 @@ pub async fn rotate_api_key(
     match res {
         Ok(_) => {
             tracing::info!("comitting the transaction as both db calls were sucessfull") ;
-            tx.commit().await.unwrap();
+            if let Err(e) = tx.commit().await {
+                return AppError::from(e).into_api::<String>();
+            }
             (
                 StatusCode::CREATED,
                 Json(ApiResponse::success(raw_key)),
@@ -130,11 +318,8 @@ pub async fn rotate_api_key(
         }
         Err(e) => {
             tracing::error!("rolling back due to an error {}", e) ;
-            tx.rollback().await.unwrap();
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            tx.rollback().await.ok();
+            AppError::from(e).into_api::<String>()
         }
     }
 }
@@ -151,16 +336,15 @@ pub async fn revoke_api_key(
         Ok(tx) => tx,
         Err(e) => {
             tracing::error!("got an error while creating a transaction to revoke the api key {}",e) ;
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            return AppError::from(e).into_api::<String>()
         }
     };
 
     match app_state.database_connector.revoke_api_key_txn(&mut tx, key_id).await {
         Ok(_) => {
-            tx.commit().await.unwrap();
+            if let Err(e) = tx.commit().await {
+                return AppError::from(e).into_api::<String>();
+            }
             (
                 StatusCode::OK,
                 Json(ApiResponse::success("revoked".to_string())),
@@ -168,11 +352,8 @@ pub async fn revoke_api_key(
         }
         Err(e) => {
             tracing::error!("rolling back due to an error {}", e) ;
-            tx.rollback().await.unwrap();
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            tx.rollback().await.ok();
+            AppError::from(e).into_api::<String>()
         }
     }
 }
@@ -193,10 +374,7 @@ pub async fn generate_admin_api_keys(
         ),
         Err(e) => {
             tracing::error!("error occurred while creating admin api key {}", e) ; 
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<String>()
         }
     }
 }
@@ -215,10 +393,7 @@ pub async fn revoke_admin_api_key(
         ),
         Err(e) => {
             tracing::error!("error occurred while revoking admin api key {}", e) ;
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<String>()
         },
     }
 }
@@ -236,10 +411,63 @@ pub async fn create_bootstraped_admin(
             StatusCode::CREATED,
             Json(ApiResponse::success(id)),
         ),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<i64>::error(e.to_string())),
+        Err(e) => AppError::from(e).into_api::<i64>(),
+    }
+}
+
+
+
+pub async fn create_fx_rate(
+    State(app_state): State<Arc<AppState>>,
+    Json(mut new_rate): Json<NewFxRate>,
+) -> impl IntoResponse {
+    new_rate.base_currency = new_rate.base_currency.trim().to_ascii_uppercase();
+    new_rate.quote_currency = new_rate.quote_currency.trim().to_ascii_uppercase();
+    tracing::info!("creating fx rate {}/{}", new_rate.base_currency, new_rate.quote_currency) ;
+
+    if new_rate.base_currency == new_rate.quote_currency {
+        return AppError::new(ErrorCode::ValidationFailed, "Base and quote currency must differ").into_api::<i64>();
+    }
+    if new_rate.rate <= Decimal::ZERO {
+        return AppError::new(ErrorCode::ValidationFailed, "Rate must be positive").into_api::<i64>();
+    }
+    if let (Some(valid_from), Some(valid_to)) = (new_rate.valid_from, new_rate.valid_to) && valid_to <= valid_from {
+        return AppError::new(ErrorCode::ValidationFailed, "valid_to must be after valid_from").into_api::<i64>();
+    }
+    for currency in [&new_rate.base_currency, &new_rate.quote_currency] {
+        match app_state.database_connector.get_currency_minor_units(currency).await {
+            Ok(Some(_)) => {},
+            Ok(None) => {
+                return AppError::new(ErrorCode::InvalidCurrency, format!("Invalid currency code {}", currency)).into_api::<i64>();
+            },
+            Err(err) => {
+                return AppError::from(err).into_api::<i64>();
+            }
+        }
+    }
+
+    match app_state.database_connector.create_fx_rate(&new_rate).await {
+        Ok(id) => (
+            StatusCode::CREATED,
+            Json(ApiResponse::success(id)),
         ),
+        Err(err) => {
+            tracing::error!("error occurred while creating fx rate {}", err) ;
+            AppError::from(err).into_api::<i64>()
+        },
     }
 }
 
+
+pub async fn get_fx_rates(
+    State(app_state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    tracing::info!("getting all fx rates") ;
+    match app_state.database_connector.get_fx_rates().await {
+        Ok(rates) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(rates)),
+        ),
+        Err(err) => AppError::from(err).into_api::<Vec<FxRate>>(),
+    }
+}
@@ -1,10 +1,11 @@
 use std::sync::Arc;
 use axum::{Extension, Json};
-use axum::extract::State;
+use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use crate::AppState;
-use crate::models::bussiness_models::BusinessState;
+use crate::error::{AppError, ErrorCode};
+use crate::models::bussiness_models::{ApiKeyInfo, BusinessState};
 use crate::models::common::{AccountId, ApiResponse};
 
 pub async fn get_business_details(State(app_state): State<Arc<AppState>>, Extension(business_account): Extension<AccountId>) -> impl IntoResponse {
@@ -20,10 +21,37 @@ pub async fn get_business_details(State(app_state): State<Arc<AppState>>, Extens
         },
         Err(e) => {
             tracing::error!("error was {}", e) ;
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<BusinessState>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<BusinessState>()
         }
     }
-}
\ No newline at end of file
+}
+
+
+pub async fn get_own_api_keys(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business_account): Extension<AccountId>,
+) -> impl IntoResponse {
+    match app_state.database_connector.get_api_keys(business_account.account_id).await {
+        Ok(keys) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(keys)),
+        ),
+        Err(e) => AppError::from(e).into_api::<Vec<ApiKeyInfo>>(),
+    }
+}
+
+
+pub async fn get_own_api_key(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business_account): Extension<AccountId>,
+    Path(key_id): Path<i64>,
+) -> impl IntoResponse {
+    match app_state.database_connector.get_api_key(Some(business_account.account_id), key_id).await {
+        Ok(Some(key)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(key)),
+        ),
+        Ok(None) => AppError::new(ErrorCode::ApiKeyNotFound, "API key not found").into_api::<ApiKeyInfo>(),
+        Err(e) => AppError::from(e).into_api::<ApiKeyInfo>(),
+    }
+}
@@ -0,0 +1,311 @@
+use std::sync::Arc;
+use axum::{Extension, Json};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use chrono::{Duration, Utc};
+use crate::AppState;
+use crate::error::{AppError, ErrorCode};
+use crate::controllers::transaction_controllers::{check_active, check_can_spend, check_currency, idempotent_response};
+use crate::models::common::{AccountId, ApiResponse};
+use crate::models::holds_models::{CaptureRequest, Hold, HoldRequest};
+use crate::models::idempotency_models::{IDEMPOTENCY_SCOPE_HOLDS, IDEMPOTENCY_SCOPE_TRANSACTIONS};
+use crate::models::transaction_models::{to_currency_precision, TransactionStatus, TransactionType};
+use crate::services::db_operations::DbOperations;
+use crate::services::holds_executor::release_hold;
+use crate::services::idempotency_executor::{idempotency_key_ttl_secs, request_fingerprint};
+
+pub async fn create_hold(
+    State(app_state): State<Arc<AppState>>,
+    Extension(account): Extension<AccountId>,
+    Json(req): Json<HoldRequest>,
+) -> impl IntoResponse {
+
+    let expires_at = req.expires_at.unwrap_or_else(|| Utc::now() + Duration::seconds(hold_default_ttl_secs()));
+    if expires_at <= Utc::now() {
+        return AppError::new(ErrorCode::ValidationFailed, "expires_at must be in the future").into_api::<i64>();
+    }
+
+    match execute_create_hold(&app_state, account.account_id, &req, expires_at).await {
+        Ok(response) => response,
+        Err(err) => err.into_api::<i64>(),
+    }
+}
+
+
+fn hold_default_ttl_secs() -> i64 {
+    std::env::var("HOLD_DEFAULT_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(7 * 24 * 60 * 60)
+}
+
+
+async fn execute_create_hold(
+    app_state: &Arc<AppState>,
+    business_id: i64,
+    req: &HoldRequest,
+    expires_at: chrono::DateTime<Utc>,
+) -> Result<(StatusCode, Json<ApiResponse<i64>>), AppError> {
+
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    let fingerprint = request_fingerprint("hold", req);
+    let claim = DbOperations::claim_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_HOLDS,
+        &req.idempotency_key,
+        &fingerprint,
+        idempotency_key_ttl_secs(),
+    )
+        .await?;
+    if let Some(response) = idempotent_response(claim) {
+        tx.rollback().await.ok();
+        return Ok(response);
+    }
+
+    let account = DbOperations::lock_account(&mut tx, business_id, req.account_id)
+        .await
+        .map_err(AppError::missing(ErrorCode::AccountNotFound, "Account not found"))?;
+
+    let checked = check_currency(&req.currency, req.amount, &account)
+        .and_then(|amount| check_can_spend(&account, amount, "Account").map(|_| amount));
+    let amount = match checked {
+        Ok(amount) => amount,
+        Err((code, message)) => {
+            tx.rollback().await.ok();
+            return Err(AppError::new(code, message));
+        }
+    };
+
+    // reserves the money: available balance drops, the ledger balance is untouched until capture
+    let hold_id = DbOperations::insert_hold(
+        &mut tx,
+        business_id,
+        req.account_id,
+        amount,
+        &account.currency,
+        expires_at,
+        req.reference_id.clone(),
+        &req.idempotency_key,
+    )
+        .await?;
+    DbOperations::adjust_held_balance(&mut tx, req.account_id, amount).await?;
+    DbOperations::complete_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_HOLDS,
+        &req.idempotency_key,
+        StatusCode::CREATED.as_u16(),
+        &serde_json::json!(ApiResponse::success(hold_id)),
+    )
+        .await?;
+
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::success(hold_id)),
+    ))
+}
+
+
+
+pub async fn capture_hold(
+    State(app_state): State<Arc<AppState>>,
+    Extension(account): Extension<AccountId>,
+    Path(hold_id): Path<i64>,
+    Json(req): Json<CaptureRequest>,
+) -> impl IntoResponse {
+
+    match execute_capture(&app_state, account.account_id, hold_id, &req).await {
+        Ok(response) => response,
+        Err(err) => err.into_api::<i64>(),
+    }
+}
+
+
+async fn execute_capture(
+    app_state: &Arc<AppState>,
+    business_id: i64,
+    hold_id: i64,
+    req: &CaptureRequest,
+) -> Result<(StatusCode, Json<ApiResponse<i64>>), AppError> {
+
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    let fingerprint = request_fingerprint("capture", &(hold_id, req));
+    let claim = DbOperations::claim_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        &fingerprint,
+        idempotency_key_ttl_secs(),
+    )
+        .await?;
+    if let Some(response) = idempotent_response(claim) {
+        tx.rollback().await.ok();
+        return Ok(response);
+    }
+
+    let account_id = match DbOperations::get_hold_account_id(&mut tx, business_id, hold_id).await? {
+        Some(account_id) => account_id,
+        None => {
+            tx.rollback().await.ok();
+            return Err(AppError::new(ErrorCode::HoldNotFound, "Hold not found"));
+        }
+    };
+    let account = DbOperations::lock_account(&mut tx, business_id, account_id).await?;
+    let hold = DbOperations::lock_hold(&mut tx, business_id, hold_id).await?;
+
+    if hold.status != "active" {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::HoldNotActive, format!("Hold is already {}", hold.status)));
+    }
+    if hold.expires_at <= Utc::now() {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::HoldExpired, "Hold has expired"));
+    }
+    if let Err((code, message)) = check_active(&account, "Account") {
+        tx.rollback().await.ok();
+        return Err(AppError::new(code, message));
+    }
+
+    let amount = match to_currency_precision(req.amount.unwrap_or(hold.amount), account.minor_units) {
+        Some(amount) if amount <= hold.amount => amount,
+        _ => {
+            tx.rollback().await.ok();
+            return Err(AppError::new(ErrorCode::InvalidAmount, format!(
+                "Capture must be positive, at most the held {} with at most {} decimal places",
+                hold.amount, account.minor_units
+            )));
+        }
+    };
+
+    let external_account_id = DbOperations::get_external_account(&mut tx, business_id, &account.currency).await?;
+
+    // the whole hold is released, whatever isn't captured becomes available again
+    DbOperations::adjust_held_balance(&mut tx, account_id, -hold.amount).await?;
+
+    let reference_id = req.reference_id.clone().or(hold.reference_id.clone());
+    let txn_id = DbOperations::insert_transaction(
+        &mut tx,
+        business_id,
+        Some(account_id),
+        None,
+        TransactionType::Debit,
+        amount,
+        &account.currency,
+        reference_id.clone(),
+        &req.idempotency_key,
+        TransactionStatus::Pending,
+    )
+        .await?;
+
+    // a capture is a debit: debit the customer account, credit external
+    DbOperations::post_ledger_entries(
+        &mut tx,
+        business_id,
+        txn_id,
+        account_id,
+        external_account_id,
+        amount,
+    )
+        .await?;
+
+    DbOperations::mark_transaction_status(&mut tx, txn_id, TransactionStatus::Succeeded).await?;
+    DbOperations::resolve_hold(&mut tx, hold.id, "captured", Some(amount), Some(txn_id)).await?;
+
+    // -------- DOMAIN EVENT --------
+
+    let payload = serde_json::json!({
+        "event": "transaction.succeeded",
+        "data": {
+            "transaction_id": txn_id,
+            "type": "debit",
+            "amount": amount,
+            "currency": account.currency,
+            "from_account_id": account_id,
+            "hold_id": hold.id,
+            "business_id": business_id,
+            "reference_id": reference_id
+        }
+    });
+
+    // durable domain event and its webhook deliveries, committed together with the ledger write
+    let mut queue_messages = app_state
+        .database_connector
+        .publish_domain_event_txn(&mut tx, business_id, "transaction.succeeded", payload)
+        .await?;
+    queue_messages.extend(
+        app_state
+            .database_connector
+            .publish_balance_events_txn(&mut tx, business_id, account_id, account.balance, txn_id)
+            .await?,
+    );
+    DbOperations::complete_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        StatusCode::CREATED.as_u16(),
+        &serde_json::json!(ApiResponse::success(txn_id)),
+    )
+        .await?;
+
+    tx.commit().await?;
+
+    // adding every delivery to the unbounded queue for the webhook worker
+    for message in queue_messages {
+        app_state
+            .event_queue
+            .send(message)
+            .expect("Unable to add Event Id to the Queue");
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::success(txn_id)),
+    ))
+}
+
+
+
+pub async fn void_hold(
+    State(app_state): State<Arc<AppState>>,
+    Extension(account): Extension<AccountId>,
+    Path(hold_id): Path<i64>,
+) -> impl IntoResponse {
+
+    match release_hold(&app_state, account.account_id, hold_id, "voided").await {
+        Ok(Some(status)) if status == "active" => (
+            StatusCode::OK,
+            Json(ApiResponse::success(hold_id)),
+        ),
+        Ok(Some(status)) => AppError::new(ErrorCode::HoldNotActive, format!("Hold is already {}", status)).into_api::<i64>(),
+        Ok(None) => AppError::new(ErrorCode::HoldNotFound, "Hold not found").into_api::<i64>(),
+        Err(err) => {
+            tracing::error!("void of hold {} failed: {}", hold_id, err);
+            AppError::from(err).into_api::<i64>()
+        }
+    }
+}
+
+
+pub async fn get_hold(
+    State(app_state): State<Arc<AppState>>,
+    Extension(account): Extension<AccountId>,
+    Path(hold_id): Path<i64>,
+) -> impl IntoResponse {
+
+    match app_state.database_connector.get_hold(account.account_id, hold_id).await {
+        Ok(Some(hold)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(hold)),
+        ),
+        Ok(None) => AppError::new(ErrorCode::HoldNotFound, "Hold not found").into_api::<Hold>(),
+        Err(e) => AppError::from(e).into_api::<Hold>(),
+    }
+}
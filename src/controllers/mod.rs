@@ -3,4 +3,5 @@ pub mod admin_controllers;
 pub mod accounts_controllers;
 pub mod transaction_controllers;
 pub mod business_controllers;
-pub mod webhooks_controllers;
\ No newline at end of file
+pub mod webhooks_controllers;
+pub mod holds_controllers;
@@ -1,13 +1,21 @@
 use std::sync::Arc;
+use std::time::Duration;
 use axum::{Extension, Json};
-use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::extract::{Path, Query, State};
 use axum::response::IntoResponse;
-use sqlx::Row;
 use crate::AppState;
+use crate::error::{AppError, ErrorCode};
 use crate::models::common::{AccountId, ApiResponse};
-use crate::models::event_queue::WebhookQueueMessage;
-use crate::models::transaction_models::{CreditRequest, DebitRequest, Transaction, TransactionStatus, TransactionType, TransferRequest};
-use crate::services::db_operations::DbOperations;
+use crate::models::accounts_models::LockedAccount;
+use crate::models::fx_models::{FxQuote, FxQuoteRequest};
+use crate::models::idempotency_models::{IdempotencyClaim, IDEMPOTENCY_SCOPE_TRANSACTIONS};
+use crate::models::transaction_models::{decode_cursor, to_currency_precision, CreditRequest, DebitRequest, FailedTransaction, ReverseRequest, Transaction, TransactionFilters, TransactionPage, TransactionStatus, TransactionType, TransferRequest};
+use rust_decimal::{Decimal, RoundingStrategy};
+use chrono::Utc;
+use rand::Rng;
+use crate::services::db_operations::{is_retryable_error, DbOperations};
+use crate::services::idempotency_executor::{idempotency_key_ttl_secs, request_fingerprint};
 
 pub async fn credit_money(
     State(app_state): State<Arc<AppState>>,
@@ -18,126 +26,178 @@ pub async fn credit_money(
     let mut tx = match app_state.database_connector.connector.begin().await {
         Ok(tx) => tx,
         Err(e) => {
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(e.to_string())),
-            );
+            return AppError::from(e).into_api::<i64>();
         }
     };
 
-    if let Some(txn_id) =
-        DbOperations::check_idempotency(&mut tx, account.account_id, &req.idempotency_key)
-            .await
-            .unwrap()
+    let fingerprint = request_fingerprint("credit", &req);
+    match DbOperations::claim_idempotency_key(
+        &mut tx,
+        account.account_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        &fingerprint,
+        idempotency_key_ttl_secs(),
+    )
+        .await
     {
-        tx.rollback().await.ok();
-        return (
-            axum::http::StatusCode::OK,
-            Json(ApiResponse::success(txn_id)),
-        );
+        Ok(claim) => {
+            if let Some(response) = idempotent_response(claim) {
+                tx.rollback().await.ok();
+                return response;
+            }
+        }
+        Err(e) => {
+            tx.rollback().await.ok();
+            return AppError::from(e).into_api::<i64>();
+        }
     }
 
-    let (balance, status) =
-        match DbOperations::lock_account(&mut tx, req.to_account_id).await {
+    let to_account =
+        match DbOperations::lock_account(&mut tx, account.account_id, req.to_account_id)
+            .await
+            .map_err(AppError::missing(ErrorCode::AccountNotFound, "Account not found"))
+        {
             Ok(v) => v,
             Err(e) => {
                 tx.rollback().await.ok();
-                return (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<i64>::error(e.to_string())),
-                );
+                return e.into_api::<i64>();
             }
         };
 
-    if status != "active" {
-        tx.rollback().await.ok();
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            Json(ApiResponse::<i64>::error("Account is frozen".into())),
-        );
-    }
+    let checked = check_currency(&req.currency, req.amount, &to_account)
+        .and_then(|amount| check_active(&to_account, "Account").map(|_| amount));
+    let amount = match checked {
+        Ok(amount) => amount,
+        Err((failure_code, failure_reason)) => {
+            let failed = FailedTransaction {
+                from_account_id: None,
+                to_account_id: Some(req.to_account_id),
+                txn_type: TransactionType::Credit,
+                amount: req.amount,
+                currency: &req.currency,
+                reference_id: req.reference_id.clone(),
+                idempotency_key: &req.idempotency_key,
+                failure_code,
+                failure_reason,
+            };
+            return record_failed_transaction(&app_state, tx, account.account_id, failed)
+                .await
+                .unwrap_or_else(|e| e.into_api::<i64>());
+        }
+    };
 
-    let txn_id = DbOperations::insert_transaction(
+    let external_account_id =
+        match DbOperations::get_external_account(&mut tx, account.account_id, &to_account.currency).await {
+            Ok(id) => id,
+            Err(e) => {
+                tx.rollback().await.ok();
+                return AppError::from(e).into_api::<i64>();
+            }
+        };
+
+    let txn_id = match DbOperations::insert_transaction(
         &mut tx,
         account.account_id,
         None,
         Some(req.to_account_id),
         TransactionType::Credit,
-        req.amount,
+        amount,
+        &to_account.currency,
         req.reference_id.clone(),
         &req.idempotency_key,
         TransactionStatus::Pending,
     )
         .await
-        .unwrap();
+    {
+        Ok(id) => id,
+        Err(e) => {
+            tx.rollback().await.ok();
+            return AppError::from(e).into_api::<i64>();
+        }
+    };
 
-    match DbOperations::update_balance(&mut tx, req.to_account_id, balance + req.amount)
+    // money comes in from outside: debit external, credit the customer account
+    match DbOperations::post_ledger_entries(
+        &mut tx,
+        account.account_id,
+        txn_id,
+        external_account_id,
+        req.to_account_id,
+        amount,
+    )
         .await {
         Ok(_) => {
             match DbOperations::mark_transaction_status(&mut tx, txn_id, TransactionStatus::Succeeded)
                 .await {
                 Ok(_) => {
 
-                    // complete the logic of adding an event to the webhook_events
-                    //  Build webhook payload
+                    // recording the event in the outbox, webhooks are materialised from it
+                    // Build event payload
                     let payload = serde_json::json!({
                         "event": "transaction.succeeded",
                         "data": {
                             "transaction_id": txn_id,
                             "type": "credit",
-                            "amount": req.amount,
+                            "amount": amount,
+                            "currency": to_account.currency,
                             "to_account_id": req.to_account_id,
                             "business_id": account.account_id,
                             "reference_id": req.reference_id
                         }
                     });
-                    tracing::info!("business_id was {}", account.account_id) ;
-                    let webhook_id = match app_state.database_connector.get_webhook(account.account_id).await  {
-                        Ok(webhook_id) => webhook_id,
-                        Err(err) => {
-                            tracing::error!("no webhook was registered to this business account") ;
-                            tracing::error!("error was {}", err) ;
+                    // durable domain event and its webhook deliveries, committed together with the ledger write
+                    let mut queue_messages = match app_state
+                        .database_connector
+                        .publish_domain_event_txn(&mut tx, account.account_id, "transaction.succeeded", payload)
+                        .await
+                    {
+                        Ok(messages) => messages,
+                        Err(e) => {
+                            tracing::error!("failed to record domain event {}", e);
                             tx.rollback().await.ok();
-                            return (
-                                axum::http::StatusCode::NOT_FOUND,
-                                Json(ApiResponse::<i64>::error("Register a Webhook First".to_string())),
-                            );
+                            return AppError::from(e).into_api::<i64>();
                         }
                     };
-                    //  Created webhook_event in DB (AFTER transaction logic, BEFORE commit)
-                    let webhook_event_id = match app_state
+
+                    match app_state
                         .database_connector
-                        .create_webhook_event(
-                            /* webhook_id */ webhook_id,
-                            "transaction.succeeded",
-                            payload,
-                        )
+                        .publish_balance_events_txn(&mut tx, account.account_id, req.to_account_id, to_account.balance, txn_id)
                         .await
                     {
-                        Ok(id) => id,
+                        Ok(messages) => queue_messages.extend(messages),
                         Err(e) => {
-                            tracing::error!("failed to create webhook event {}", e);
+                            tracing::error!("failed to record balance event {}", e);
                             tx.rollback().await.ok();
-                            return (
-                                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                Json(ApiResponse::<i64>::error(e.to_string())),
-                            );
+                            return AppError::from(e).into_api::<i64>();
                         }
                     };
 
+                    if let Err(e) = DbOperations::complete_idempotency_key(
+                        &mut tx,
+                        account.account_id,
+                        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+                        &req.idempotency_key,
+                        StatusCode::CREATED.as_u16(),
+                        &serde_json::json!(ApiResponse::success(txn_id)),
+                    )
+                        .await
+                    {
+                        tx.rollback().await.ok();
+                        return AppError::from(e).into_api::<i64>();
+                    }
 
+                    if let Err(e) = tx.commit().await {
+                        return AppError::from(e).into_api::<i64>();
+                    }
 
-
-
-
-                    tx.commit().await.unwrap();
-                    // WebhookQueueMessage, we are going to add to the message to the unbounded
-                    app_state.event_queue.send(
-                        WebhookQueueMessage {
-                            webhook_event_id,
-                            webhook_id
-                        }
-                    ).expect("Unable to add Event Id to the Queue");
+                    // adding every delivery to the unbounded queue for the webhook worker
+                    for message in queue_messages {
+                        app_state
+                            .event_queue
+                            .send(message)
+                            .expect("Unable to add Event Id to the Queue");
+                    }
                     (
                         axum::http::StatusCode::CREATED,
                         Json(ApiResponse::success(txn_id)),
@@ -146,19 +206,13 @@ pub async fn credit_money(
                 Err(err) => {
                     tracing::error!("Failed to mark transaction status: {:?}", err);
                     tx.rollback().await.ok();
-                    return (
-                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ApiResponse::<i64>::error(err.to_string()))
-                    ) ;
+                    AppError::from(err).into_api::<i64>()
                 }
             }
         },
         Err(err) => {
             tx.rollback().await.ok();
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(err.to_string()))
-                ) ;
+            AppError::from(err).into_api::<i64>()
         }
     }
 
@@ -176,62 +230,106 @@ pub async fn debit_money(
     let mut tx = match app_state.database_connector.connector.begin().await {
         Ok(tx) => tx,
         Err(e) => {
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(e.to_string())),
-            );
+            return AppError::from(e).into_api::<i64>();
         }
     };
 
-    if let Some(txn_id) =
-        DbOperations::check_idempotency(&mut tx, account.account_id, &req.idempotency_key)
-            .await
-            .unwrap()
+    let fingerprint = request_fingerprint("debit", &req);
+    match DbOperations::claim_idempotency_key(
+        &mut tx,
+        account.account_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        &fingerprint,
+        idempotency_key_ttl_secs(),
+    )
+        .await
     {
-        tx.rollback().await.ok();
-        return (
-            axum::http::StatusCode::OK,
-            Json(ApiResponse::success(txn_id)),
-        );
+        Ok(claim) => {
+            if let Some(response) = idempotent_response(claim) {
+                tx.rollback().await.ok();
+                return response;
+            }
+        }
+        Err(e) => {
+            tx.rollback().await.ok();
+            return AppError::from(e).into_api::<i64>();
+        }
     }
 
-    let (balance, status) =
-        match DbOperations::lock_account(&mut tx, req.from_account_id).await {
+    let from_account =
+        match DbOperations::lock_account(&mut tx, account.account_id, req.from_account_id)
+            .await
+            .map_err(AppError::missing(ErrorCode::AccountNotFound, "Account not found"))
+        {
             Ok(v) => v,
             Err(e) => {
                 tx.rollback().await.ok();
-                return (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<i64>::error(e.to_string())),
-                );
+                return e.into_api::<i64>();
             }
         };
 
-    if status != "active" || balance < req.amount {
-        tx.rollback().await.ok();
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            Json(ApiResponse::<i64>::error(
-                "Insufficient balance or frozen account".into(),
-            )),
-        );
-    }
+    let checked = check_currency(&req.currency, req.amount, &from_account)
+        .and_then(|amount| check_can_spend(&from_account, amount, "Account").map(|_| amount));
+    let amount = match checked {
+        Ok(amount) => amount,
+        Err((failure_code, failure_reason)) => {
+            let failed = FailedTransaction {
+                from_account_id: Some(req.from_account_id),
+                to_account_id: None,
+                txn_type: TransactionType::Debit,
+                amount: req.amount,
+                currency: &req.currency,
+                reference_id: req.reference_id.clone(),
+                idempotency_key: &req.idempotency_key,
+                failure_code,
+                failure_reason,
+            };
+            return record_failed_transaction(&app_state, tx, account.account_id, failed)
+                .await
+                .unwrap_or_else(|e| e.into_api::<i64>());
+        }
+    };
 
-    let txn_id = DbOperations::insert_transaction(
+    let external_account_id =
+        match DbOperations::get_external_account(&mut tx, account.account_id, &from_account.currency).await {
+            Ok(id) => id,
+            Err(e) => {
+                tx.rollback().await.ok();
+                return AppError::from(e).into_api::<i64>();
+            }
+        };
+
+    let txn_id = match DbOperations::insert_transaction(
         &mut tx,
         account.account_id,
         Some(req.from_account_id),
         None,
         TransactionType::Debit,
-        req.amount,
+        amount,
+        &from_account.currency,
         req.reference_id.clone(),
         &req.idempotency_key,
         TransactionStatus::Pending,
     )
         .await
-        .unwrap();
+    {
+        Ok(id) => id,
+        Err(e) => {
+            tx.rollback().await.ok();
+            return AppError::from(e).into_api::<i64>();
+        }
+    };
 
-    match DbOperations::update_balance(&mut tx, req.from_account_id, balance - req.amount)
+    // money leaves the system: debit the customer account, credit external
+    match DbOperations::post_ledger_entries(
+        &mut tx,
+        account.account_id,
+        txn_id,
+        req.from_account_id,
+        external_account_id,
+        amount,
+    )
         .await
     {
         Ok(_) => {
@@ -244,63 +342,73 @@ pub async fn debit_money(
             {
                 Ok(_) => {
 
-                    // -------- WEBHOOK EVENT LOGIC (same as credit) --------
+                    // -------- DOMAIN EVENT (same as credit) --------
 
-                    // Build webhook payload
+                    // Build event payload
                     let payload = serde_json::json!({
                         "event": "transaction.succeeded",
                         "data": {
                             "transaction_id": txn_id,
                             "type": "debit",
-                            "amount": req.amount,
+                            "amount": amount,
+                            "currency": from_account.currency,
                             "from_account_id": req.from_account_id,
                             "business_id": account.account_id,
                             "reference_id": req.reference_id
                         }
                     });
-                    let webhook_id = match app_state.database_connector.get_webhook(account.account_id).await  {
-                        Ok(webhook_id) => webhook_id,
-                        Err(err) => {
-                            tracing::error!("no webhook was registered to this business account") ;
-                            tracing::error!("error was {}", err) ;
+                    // durable domain event and its webhook deliveries, committed together with the ledger write
+                    let mut queue_messages = match app_state
+                        .database_connector
+                        .publish_domain_event_txn(&mut tx, account.account_id, "transaction.succeeded", payload)
+                        .await
+                    {
+                        Ok(messages) => messages,
+                        Err(e) => {
+                            tracing::error!("failed to record domain event {}", e);
                             tx.rollback().await.ok();
-                            return (
-                                axum::http::StatusCode::NOT_FOUND,
-                                Json(ApiResponse::<i64>::error("Register a Webhook First".to_string())),
-                            );
+                            return AppError::from(e).into_api::<i64>();
                         }
                     };
 
-                    // Create webhook_event in DB (AFTER transaction logic, BEFORE commit)
-                    let webhook_event_id = match app_state
+                    match app_state
                         .database_connector
-                        .create_webhook_event(
-                            /* webhook_id */ webhook_id, // same assumption as credit
-                            "transaction.succeeded",
-                            payload,
-                        )
+                        .publish_balance_events_txn(&mut tx, account.account_id, req.from_account_id, from_account.balance, txn_id)
                         .await
                     {
-                        Ok(id) => id,
+                        Ok(messages) => queue_messages.extend(messages),
                         Err(e) => {
-                            tracing::error!("failed to create webhook event {}", e);
+                            tracing::error!("failed to record balance event {}", e);
                             tx.rollback().await.ok();
-                            return (
-                                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                Json(ApiResponse::<i64>::error(e.to_string())),
-                            );
+                            return AppError::from(e).into_api::<i64>();
                         }
                     };
 
-                    // ------------------------------------------------------
+                    if let Err(e) = DbOperations::complete_idempotency_key(
+                        &mut tx,
+                        account.account_id,
+                        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+                        &req.idempotency_key,
+                        StatusCode::CREATED.as_u16(),
+                        &serde_json::json!(ApiResponse::success(txn_id)),
+                    )
+                        .await
+                    {
+                        tx.rollback().await.ok();
+                        return AppError::from(e).into_api::<i64>();
+                    }
 
-                    tx.commit().await.unwrap();
+                    if let Err(e) = tx.commit().await {
+                        return AppError::from(e).into_api::<i64>();
+                    }
 
-                    // Push webhook_event_id to unbounded channel
-                    app_state
-                        .event_queue
-                        .send(WebhookQueueMessage { webhook_event_id, webhook_id })
-                        .expect("Unable to add Event Id to the Queue");
+                    // adding every delivery to the unbounded queue for the webhook worker
+                    for message in queue_messages {
+                        app_state
+                            .event_queue
+                            .send(message)
+                            .expect("Unable to add Event Id to the Queue");
+                    }
 
                     (
                         axum::http::StatusCode::CREATED,
@@ -310,19 +418,13 @@ pub async fn debit_money(
                 Err(err) => {
                     tracing::error!("Failed to mark transaction status: {:?}", err);
                     tx.rollback().await.ok();
-                    return (
-                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ApiResponse::<i64>::error(err.to_string())),
-                    );
+                    AppError::from(err).into_api::<i64>()
                 }
             }
         }
         Err(err) => {
             tx.rollback().await.ok();
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(err.to_string())),
-            );
+            AppError::from(err).into_api::<i64>()
         }
     }
 }
@@ -336,193 +438,701 @@ pub async fn transfer_money(
     Json(req): Json<TransferRequest>,
 ) -> impl IntoResponse {
 
-    let mut tx = match app_state.database_connector.connector.begin().await {
-        Ok(tx) => tx,
-        Err(e) => {
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(e.to_string())),
-            );
+    if req.from_account_id == req.to_account_id {
+        return AppError::new(ErrorCode::ValidationFailed, "Cannot transfer to the same account").into_api::<i64>();
+    }
+
+    // deadlocks / serialization failures are transient, the whole attempt is safe to run again
+    let max_retries = transfer_max_retries();
+    let mut attempt = 0;
+    loop {
+        match execute_transfer(&app_state, account.account_id, &req).await {
+            Ok(response) => return response,
+            Err(AppError::Database(err)) if is_retryable_error(&err) && attempt < max_retries => {
+                attempt += 1;
+                tracing::warn!("transfer {} hit a retryable error, retry {} of {}: {}", req.idempotency_key, attempt, max_retries, err);
+                tokio::time::sleep(transfer_backoff(attempt)).await;
+            }
+            Err(err) => return err.into_api::<i64>(),
+        }
+    }
+}
+
+
+// the answer for a key that was used before, None when the key was just claimed and the request runs
+pub fn idempotent_response(claim: IdempotencyClaim) -> Option<(StatusCode, Json<ApiResponse<i64>>)> {
+    match claim {
+        IdempotencyClaim::Claimed => None,
+        IdempotencyClaim::Replay { status, body } => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+            match serde_json::from_value::<ApiResponse<i64>>(body) {
+                Ok(body) => Some((status, Json(body))),
+                Err(e) => {
+                    tracing::error!("unable to replay the stored response: {}", e);
+                    Some(AppError::new(ErrorCode::InternalError, "Unable to replay the stored response").into_api())
+                }
+            }
+        }
+        IdempotencyClaim::Mismatch => Some(
+            AppError::new(ErrorCode::IdempotencyKeyReused, "Idempotency key was already used with a different request")
+                .into_api(),
+        ),
+        IdempotencyClaim::InProgress => Some(
+            AppError::new(ErrorCode::IdempotencyKeyInProgress, "A request with this idempotency key is still in progress")
+                .into_api(),
+        ),
+    }
+}
+
+
+// the amount must fit the account's minor units and the request must name its currency,
+// returns the amount scaled to the currency. The amount is checked first, before anything
+// else about the account, since a malformed one is never recorded
+pub fn check_currency(currency: &str, amount: Decimal, account: &LockedAccount) -> Result<Decimal, (ErrorCode, String)> {
+    let amount = to_currency_precision(amount, account.minor_units).ok_or_else(|| (
+        ErrorCode::InvalidAmount,
+        format!("Amount must be positive with at most {} decimal places for {}", account.minor_units, account.currency),
+    ))?;
+    if !currency.eq_ignore_ascii_case(&account.currency) {
+        return Err((
+            ErrorCode::CurrencyMismatch,
+            format!("Currency mismatch, {} was requested but the account holds {}", currency, account.currency),
+        ));
+    }
+    Ok(amount)
+}
+
+
+// money only moves in and out of active accounts, `label` names the account in the reason
+pub fn check_active(account: &LockedAccount, label: &str) -> Result<(), (ErrorCode, String)> {
+    match account.status.as_str() {
+        "active" => Ok(()),
+        "closed" => Err((ErrorCode::AccountClosed, format!("{} is closed", label))),
+        status => Err((ErrorCode::AccountFrozen, format!("{} is {}", label, status))),
+    }
+}
+
+
+pub fn check_can_spend(account: &LockedAccount, amount: Decimal, label: &str) -> Result<(), (ErrorCode, String)> {
+    check_active(account, label)?;
+    if !account.can_spend(amount) {
+        return Err((ErrorCode::InsufficientFunds, "Insufficient balance".to_string()));
+    }
+    Ok(())
+}
+
+
+/// Records a rejected credit, debit or transfer as a failed transaction, without ledger entries,
+/// and emits `transaction.failed`. The rejection is stored under the idempotency key, so a retry
+/// with the same key gets it back and trying again once the cause is fixed takes a new key.
+/// A malformed amount or currency isn't an attempted movement, it is rejected without a record.
+async fn record_failed_transaction(
+    app_state: &Arc<AppState>,
+    mut tx: sqlx::Transaction<'_, sqlx::Postgres>,
+    business_id: i64,
+    failed: FailedTransaction<'_>,
+) -> Result<(StatusCode, Json<ApiResponse<i64>>), AppError> {
+
+    if failed.failure_code == ErrorCode::InvalidAmount {
+        tx.rollback().await.ok();
+        return Err(AppError::new(failed.failure_code, failed.failure_reason));
+    }
+    let currency = failed.currency.trim().to_ascii_uppercase();
+    if app_state.database_connector.get_currency_minor_units(&currency).await?.is_none() {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::InvalidCurrency, "Invalid currency code, expected an ISO 4217 code such as USD"));
+    }
+    let failed = FailedTransaction { currency: &currency, ..failed };
+
+    let txn_id = DbOperations::insert_failed_transaction(&mut tx, business_id, &failed).await?;
+
+    let txn_type = match failed.txn_type {
+        TransactionType::Credit => "credit",
+        TransactionType::Debit => "debit",
+        TransactionType::Transfer => "transfer",
+        TransactionType::Reversal => "reversal",
+    };
+    let payload = serde_json::json!({
+        "event": "transaction.failed",
+        "data": {
+            "transaction_id": txn_id,
+            "type": txn_type,
+            "amount": failed.amount,
+            "currency": failed.currency,
+            "from_account_id": failed.from_account_id,
+            "to_account_id": failed.to_account_id,
+            "business_id": business_id,
+            "reference_id": failed.reference_id,
+            "failure_code": failed.failure_code.as_str(),
+            "failure_reason": failed.failure_reason
         }
+    });
+    let queue_messages = app_state
+        .database_connector
+        .publish_domain_event_txn(&mut tx, business_id, "transaction.failed", payload)
+        .await?;
+
+    // the failed transaction's id comes back with the rejection
+    let status = failed.failure_code.status();
+    let response = ApiResponse {
+        data: Some(txn_id),
+        ..ApiResponse::error(failed.failure_code, failed.failure_reason.clone())
     };
+    DbOperations::complete_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        failed.idempotency_key,
+        status.as_u16(),
+        &serde_json::json!(response),
+    )
+        .await?;
+
+    tx.commit().await?;
+    tracing::info!("transaction {} of business {} failed: {}", txn_id, business_id, failed.failure_code.as_str());
+
+    for message in queue_messages {
+        app_state
+            .event_queue
+            .send(message)
+            .expect("Unable to add Event Id to the Queue");
+    }
+
+    Ok((
+        status,
+        Json(response),
+    ))
+}
+
+
+// retries of a transfer and the longest wait before one, whatever TRANSFER_MAX_RETRIES says
+const MAX_TRANSFER_RETRIES: u32 = 10;
+const MAX_TRANSFER_BACKOFF_MS: u64 = 1_000;
+
+fn transfer_max_retries() -> u32 {
+    std::env::var("TRANSFER_MAX_RETRIES")
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .unwrap_or(3)
+        .min(MAX_TRANSFER_RETRIES)
+}
+
+// doubles from 20ms with a little jitter so retrying transfers don't collide again
+fn transfer_backoff(attempt: u32) -> Duration {
+    let backoff = 10u64.saturating_mul(2u64.saturating_pow(attempt)).min(MAX_TRANSFER_BACKOFF_MS);
+    Duration::from_millis(backoff + rand::thread_rng().gen_range(0..10))
+}
+
+
+// destination side of a cross-currency transfer
+struct FxConversion {
+    rate: Decimal,
+    quote_id: Option<i64>,
+    destination_amount: Decimal,
+    // quoted conversions only: what the pair's current rate would have paid out, less what the quote did
+    gain_loss: Option<Decimal>,
+}
+
+
+// one attempt of a transfer, database errors are returned so the caller can decide to retry
+async fn execute_transfer(
+    app_state: &Arc<AppState>,
+    business_id: i64,
+    req: &TransferRequest,
+) -> Result<(StatusCode, Json<ApiResponse<i64>>), AppError> {
 
-    if let Some(txn_id) =
-        DbOperations::check_idempotency(&mut tx, account.account_id, &req.idempotency_key)
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    let fingerprint = request_fingerprint("transfer", req);
+    let claim = DbOperations::claim_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        &fingerprint,
+        idempotency_key_ttl_secs(),
+    )
+        .await?;
+    if let Some(response) = idempotent_response(claim) {
+        tx.rollback().await.ok();
+        return Ok(response);
+    }
+
+    // locked in ascending id order, so A->B and B->A running together can't deadlock
+    let (from_account, to_account) =
+        DbOperations::lock_account_pair(&mut tx, business_id, req.from_account_id, req.to_account_id)
             .await
-            .unwrap()
-    {
+            .map_err(AppError::missing(ErrorCode::AccountNotFound, "Account not found"))?;
+
+    let cross_currency = from_account.currency != to_account.currency;
+    if req.quote_id.is_some() && !cross_currency {
         tx.rollback().await.ok();
-        return (
-            axum::http::StatusCode::OK,
-            Json(ApiResponse::success(txn_id)),
-        );
+        return Err(AppError::new(ErrorCode::ValidationFailed, "FX quotes only apply to cross-currency transfers"));
     }
 
-    let (from_balance, from_status) =
-        match DbOperations::lock_account(&mut tx, req.from_account_id).await {
-            Ok(v) => v,
-            Err(e) => {
-                tx.rollback().await.ok();
-                return (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<i64>::error(e.to_string())),
-                );
-            }
-        };
+    let checked = check_currency(&req.currency, req.amount, &from_account).and_then(|amount| {
+        check_can_spend(&from_account, amount, "Sending account")?;
+        check_active(&to_account, "Receiving account")?;
+        Ok(amount)
+    });
+    let amount = match checked {
+        Ok(amount) => amount,
+        Err((failure_code, failure_reason)) => {
+            let failed = FailedTransaction {
+                from_account_id: Some(req.from_account_id),
+                to_account_id: Some(req.to_account_id),
+                txn_type: TransactionType::Transfer,
+                amount: req.amount,
+                currency: &req.currency,
+                reference_id: req.reference_id.clone(),
+                idempotency_key: &req.idempotency_key,
+                failure_code,
+                failure_reason,
+            };
+            return record_failed_transaction(app_state, tx, business_id, failed).await;
+        }
+    };
 
-    let (to_balance, to_status) =
-        match DbOperations::lock_account(&mut tx, req.to_account_id).await {
-            Ok(v) => v,
-            Err(e) => {
+    // converted at the quoted rate, or at the pair's current rate when no quote is given
+    let conversion = if cross_currency {
+        let rate = match req.quote_id {
+            Some(quote_id) => match DbOperations::lock_fx_quote(&mut tx, business_id, quote_id).await? {
+                None => Err(AppError::new(ErrorCode::FxQuoteNotFound, "FX quote not found")),
+                Some(quote) if quote.transaction_id.is_some() => {
+                    Err(AppError::new(ErrorCode::FxQuoteUsed, "FX quote has already been used"))
+                }
+                Some(quote) if quote.expires_at <= Utc::now() => {
+                    Err(AppError::new(ErrorCode::FxQuoteExpired, "FX quote has expired"))
+                }
+                Some(quote) if quote.from_currency != from_account.currency || quote.to_currency != to_account.currency => {
+                    Err(AppError::new(ErrorCode::FxQuoteMismatch, format!(
+                        "FX quote is for {}/{}, not {}/{}",
+                        quote.from_currency, quote.to_currency, from_account.currency, to_account.currency
+                    )))
+                }
+                Some(quote) => Ok((quote.rate, Some(quote.id))),
+            },
+            None => match DbOperations::get_current_fx_rate(&mut tx, &from_account.currency, &to_account.currency).await? {
+                Some(rate) => Ok((rate, None)),
+                None => Err(AppError::new(ErrorCode::FxRateUnavailable, format!(
+                    "No current FX rate for {}/{}", from_account.currency, to_account.currency
+                ))),
+            },
+        };
+        let (rate, quote_id) = match rate {
+            Ok(rate) => rate,
+            Err(err) => {
                 tx.rollback().await.ok();
-                return (
-                    axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ApiResponse::<i64>::error(e.to_string())),
-                );
+                return Err(err);
             }
         };
 
-    if from_status != "active" || to_status != "active" || from_balance < req.amount {
-        tx.rollback().await.ok();
-        return (
-            axum::http::StatusCode::BAD_REQUEST,
-            Json(ApiResponse::<i64>::error(
-                "Insufficient balance or frozen account".into(),
-            )),
-        );
-    }
+        let convert = |rate: Decimal| {
+            (amount * rate).round_dp_with_strategy(to_account.minor_units, RoundingStrategy::MidpointAwayFromZero)
+        };
+        let destination_amount = convert(rate);
+        if destination_amount <= Decimal::ZERO {
+            tx.rollback().await.ok();
+            return Err(AppError::new(ErrorCode::InvalidAmount, "Amount is too small to convert"));
+        }
+        // a quote realises the gap to the current rate, there is none to measure once the pair has no rate
+        let gain_loss = match quote_id {
+            Some(_) => DbOperations::get_current_fx_rate(&mut tx, &from_account.currency, &to_account.currency)
+                .await?
+                .map(|current_rate| convert(current_rate) - destination_amount),
+            None => None,
+        };
+        Some(FxConversion { rate, quote_id, destination_amount, gain_loss })
+    } else {
+        None
+    };
 
     let txn_id = DbOperations::insert_transaction(
         &mut tx,
-        account.account_id,
+        business_id,
         Some(req.from_account_id),
         Some(req.to_account_id),
         TransactionType::Transfer,
-        req.amount,
+        amount,
+        &from_account.currency,
         req.reference_id.clone(),
         &req.idempotency_key,
         TransactionStatus::Pending,
     )
-        .await
-        .unwrap();
+        .await?;
 
-    match DbOperations::update_balance(
-        &mut tx,
-        req.from_account_id,
-        from_balance - req.amount,
-    )
-        .await
-    {
-        Ok(_) => {
-            match DbOperations::update_balance(
+    match &conversion {
+        // both sides are customer accounts: debit the sender, credit the receiver
+        None => {
+            DbOperations::post_ledger_entries(
                 &mut tx,
+                business_id,
+                txn_id,
+                req.from_account_id,
                 req.to_account_id,
-                to_balance + req.amount,
+                amount,
             )
-                .await
+                .await?;
+        }
+        // the sender pays into the fx account of its currency and the receiver is paid out of
+        // the fx account of theirs, so each currency balances on its own and the fx accounts
+        // carry the position of the business
+        Some(conversion) => {
+            let fx_source = DbOperations::get_fx_account(&mut tx, business_id, &from_account.currency).await?;
+            let fx_destination = DbOperations::get_fx_account(&mut tx, business_id, &to_account.currency).await?;
+            let source_leg = (req.from_account_id, fx_source, amount);
+            let destination_leg = (fx_destination, req.to_account_id, conversion.destination_amount);
+            // fx accounts aren't locked up front, posting in id order keeps opposite conversions deadlock free
+            let legs = if fx_source < fx_destination {
+                [source_leg, destination_leg]
+            } else {
+                [destination_leg, source_leg]
+            };
+            for (debit_account_id, credit_account_id, leg_amount) in legs {
+                DbOperations::post_ledger_entries(
+                    &mut tx,
+                    business_id,
+                    txn_id,
+                    debit_account_id,
+                    credit_account_id,
+                    leg_amount,
+                )
+                    .await?;
+            }
+
+            // the fx account is left holding the destination amount at the current rate, and the
+            // difference to the quoted one moves to the gain/loss account of that currency
+            if let Some(gain_loss) = conversion.gain_loss
+                && !gain_loss.is_zero()
             {
-                Ok(_) => {
-                    match DbOperations::mark_transaction_status(
-                        &mut tx,
-                        txn_id,
-                        TransactionStatus::Succeeded,
-                    )
-                        .await
-                    {
-                        Ok(_) => {
-
-                            // -------- WEBHOOK EVENT LOGIC --------
-
-                            let payload = serde_json::json!({
-                                "event": "transaction.succeeded",
-                                "data": {
-                                    "transaction_id": txn_id,
-                                    "type": "transfer",
-                                    "amount": req.amount,
-                                    "from_account_id": req.from_account_id,
-                                    "to_account_id": req.to_account_id,
-                                    "business_id": account.account_id,
-                                    "reference_id": req.reference_id
-                                }
-                            });
-
-                            let webhook_id = match app_state.database_connector.get_webhook(account.account_id).await  {
-                                Ok(webhook_id) => webhook_id,
-                                Err(err) => {
-                                    tracing::error!("no webhook was registered to this business account") ;
-                                    tracing::error!("error was {}", err) ;
-                                    tx.rollback().await.ok();
-                                    return (
-                                        axum::http::StatusCode::NOT_FOUND,
-                                        Json(ApiResponse::<i64>::error("Register a Webhook First".to_string())),
-                                    );
-                                }
-                            };
-
-                            let webhook_event_id = match app_state
-                                .database_connector
-                                .create_webhook_event(
-                                    /* webhook_id */ webhook_id,
-                                    "transaction.succeeded",
-                                    payload,
-                                )
-                                .await
-                            {
-                                Ok(id) => id,
-                                Err(e) => {
-                                    tracing::error!("failed to create webhook event {}", e);
-                                    tx.rollback().await.ok();
-                                    return (
-                                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                        Json(ApiResponse::<i64>::error(e.to_string())),
-                                    );
-                                }
-                            };
-
-                            // -----------------------------------
-
-                            tx.commit().await.unwrap();
-
-                            app_state
-                                .event_queue
-                                .send(WebhookQueueMessage { webhook_event_id, webhook_id })
-                                .expect("Unable to add Event Id to the Queue");
-
-                            (
-                                axum::http::StatusCode::CREATED,
-                                Json(ApiResponse::success(txn_id)),
-                            )
-                        }
-                        Err(err) => {
-                            tracing::error!(
-                                "Failed to mark transaction status: {:?}",
-                                err
-                            );
-                            tx.rollback().await.ok();
-                            return (
-                                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                                Json(ApiResponse::<i64>::error(err.to_string())),
-                            );
-                        }
-                    }
-                }
-                Err(err) => {
-                    tx.rollback().await.ok();
-                    return (
-                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ApiResponse::<i64>::error(err.to_string())),
-                    );
-                }
+                let fx_gain_loss = DbOperations::get_fx_gain_loss_account(&mut tx, business_id, &to_account.currency).await?;
+                let (debit_account_id, credit_account_id) = if gain_loss > Decimal::ZERO {
+                    (fx_destination, fx_gain_loss)
+                } else {
+                    (fx_gain_loss, fx_destination)
+                };
+                DbOperations::post_ledger_entries(
+                    &mut tx,
+                    business_id,
+                    txn_id,
+                    debit_account_id,
+                    credit_account_id,
+                    gain_loss.abs(),
+                )
+                    .await?;
             }
+
+            DbOperations::record_fx_conversion(
+                &mut tx,
+                txn_id,
+                conversion.destination_amount,
+                &to_account.currency,
+                conversion.rate,
+                conversion.quote_id,
+                conversion.gain_loss,
+            )
+                .await?;
         }
-        Err(err) => {
+    }
+
+    DbOperations::mark_transaction_status(&mut tx, txn_id, TransactionStatus::Succeeded).await?;
+
+    // -------- DOMAIN EVENT --------
+
+    let payload = serde_json::json!({
+        "event": "transaction.succeeded",
+        "data": {
+            "transaction_id": txn_id,
+            "type": "transfer",
+            "amount": amount,
+            "currency": from_account.currency,
+            "destination_amount": conversion.as_ref().map(|c| c.destination_amount),
+            "destination_currency": conversion.as_ref().map(|_| &to_account.currency),
+            "fx_rate": conversion.as_ref().map(|c| c.rate),
+            "fx_quote_id": conversion.as_ref().and_then(|c| c.quote_id),
+            "fx_gain_loss": conversion.as_ref().and_then(|c| c.gain_loss),
+            "from_account_id": req.from_account_id,
+            "to_account_id": req.to_account_id,
+            "business_id": business_id,
+            "reference_id": req.reference_id
+        }
+    });
+
+    // durable domain event and its webhook deliveries, committed together with the ledger write
+    let mut queue_messages = app_state
+        .database_connector
+        .publish_domain_event_txn(&mut tx, business_id, "transaction.succeeded", payload)
+        .await?;
+    for (account_id, locked) in [(req.from_account_id, &from_account), (req.to_account_id, &to_account)] {
+        queue_messages.extend(
+            app_state
+                .database_connector
+                .publish_balance_events_txn(&mut tx, business_id, account_id, locked.balance, txn_id)
+                .await?,
+        );
+    }
+
+    DbOperations::complete_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        StatusCode::CREATED.as_u16(),
+        &serde_json::json!(ApiResponse::success(txn_id)),
+    )
+        .await?;
+
+    tx.commit().await?;
+
+    // adding every delivery to the unbounded queue for the webhook worker
+    for message in queue_messages {
+        app_state
+            .event_queue
+            .send(message)
+            .expect("Unable to add Event Id to the Queue");
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::success(txn_id)),
+    ))
+}
+
+
+
+
+pub async fn create_fx_quote(
+    State(app_state): State<Arc<AppState>>,
+    Extension(account): Extension<AccountId>,
+    Json(req): Json<FxQuoteRequest>,
+) -> impl IntoResponse {
+
+    let from_currency = req.from_currency.trim().to_ascii_uppercase();
+    let to_currency = req.to_currency.trim().to_ascii_uppercase();
+    match app_state
+        .database_connector
+        .create_fx_quote(account.account_id, &from_currency, &to_currency, fx_quote_ttl_secs())
+        .await
+    {
+        Ok(Some(quote)) => (
+            StatusCode::CREATED,
+            Json(ApiResponse::success(quote)),
+        ),
+        Ok(None) => AppError::new(ErrorCode::FxRateUnavailable, format!("No current FX rate for {}/{}", from_currency, to_currency)).into_api::<FxQuote>(),
+        Err(e) => {
+            tracing::error!("error creating fx quote {}", e);
+            AppError::from(e).into_api::<FxQuote>()
+        }
+    }
+}
+
+
+fn fx_quote_ttl_secs() -> i64 {
+    std::env::var("FX_QUOTE_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(60)
+}
+
+
+
+
+pub async fn reverse_transaction(
+    State(app_state): State<Arc<AppState>>,
+    Extension(account): Extension<AccountId>,
+    Path(transaction_id): Path<i64>,
+    Json(req): Json<ReverseRequest>,
+) -> impl IntoResponse {
+
+    if req.amount.is_some_and(|amount| amount <= Decimal::ZERO) {
+        return AppError::new(ErrorCode::InvalidAmount, "Reversal amount must be positive").into_api::<i64>();
+    }
+
+    match execute_reversal(&app_state, account.account_id, transaction_id, &req).await {
+        Ok(response) => response,
+        Err(err) => err.into_api::<i64>(),
+    }
+}
+
+
+async fn execute_reversal(
+    app_state: &Arc<AppState>,
+    business_id: i64,
+    original_txn_id: i64,
+    req: &ReverseRequest,
+) -> Result<(StatusCode, Json<ApiResponse<i64>>), AppError> {
+
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    let fingerprint = request_fingerprint("reversal", &(original_txn_id, req));
+    let claim = DbOperations::claim_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        &fingerprint,
+        idempotency_key_ttl_secs(),
+    )
+        .await?;
+    if let Some(response) = idempotent_response(claim) {
+        tx.rollback().await.ok();
+        return Ok(response);
+    }
+
+    // locking the original first, concurrent reversals of it queue up here
+    let original = match DbOperations::lock_transaction(&mut tx, business_id, original_txn_id).await? {
+        Some(original) => original,
+        None => {
+            tx.rollback().await.ok();
+            return Err(AppError::new(ErrorCode::TransactionNotFound, "Transaction not found"));
+        }
+    };
+
+    if original.status != TransactionStatus::Succeeded || original.txn_type == TransactionType::Reversal {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::ReversalNotAllowed, "Only succeeded credits, debits and transfers can be reversed"));
+    }
+
+    let remaining = original.amount - DbOperations::get_reversed_amount(&mut tx, original.id).await?;
+    let amount = req.amount.unwrap_or(remaining);
+    if amount <= Decimal::ZERO || amount > remaining {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::ReversalExceedsRemaining, format!("Reversal exceeds the {} left to reverse", remaining)));
+    }
+
+    // money flows back the other way: whatever was credited is debited and vice versa
+    let (from_account_id, to_account_id) = (original.to_account_id, original.from_account_id);
+    let (from_account, to_account) = match (from_account_id, to_account_id) {
+        (Some(from_id), Some(to_id)) => {
+            let (from, to) = DbOperations::lock_account_pair(&mut tx, business_id, from_id, to_id).await?;
+            (Some(from), Some(to))
+        }
+        (Some(from_id), None) => (Some(DbOperations::lock_account(&mut tx, business_id, from_id).await?), None),
+        (None, Some(to_id)) => (None, Some(DbOperations::lock_account(&mut tx, business_id, to_id).await?)),
+        (None, None) => {
+            tx.rollback().await.ok();
+            return Err(AppError::new(ErrorCode::ReversalNotAllowed, "Accounts of the transaction no longer exist"));
+        }
+    };
+
+    if let (Some(from), Some(to)) = (&from_account, &to_account) && from.currency != to.currency {
+        tx.rollback().await.ok();
+        return Err(AppError::new(ErrorCode::ReversalNotAllowed, "Cross-currency transfers can't be reversed, transfer the money back instead"));
+    }
+
+    let checked = from_account
+        .as_ref()
+        .map_or(Ok(()), |from| check_can_spend(from, amount, "Account to debit"))
+        .and_then(|_| to_account.as_ref().map_or(Ok(()), |to| check_active(to, "Account to credit")));
+    if let Err((code, message)) = checked {
+        tx.rollback().await.ok();
+        return Err(AppError::new(code, message));
+    }
+
+    let minor_units = from_account.as_ref().or(to_account.as_ref()).map_or(0, |a| a.minor_units);
+    let amount = match to_currency_precision(amount, minor_units) {
+        Some(amount) => amount,
+        None => {
             tx.rollback().await.ok();
-            return (
-                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(err.to_string())),
+            return Err(AppError::new(ErrorCode::InvalidAmount, format!(
+                "Amount must have at most {} decimal places for {}", minor_units, original.currency
+            )));
+        }
+    };
+
+    // credits and debits were posted against the external account, so is their reversal
+    let currency = original.currency;
+    let debit_account_id = match from_account_id {
+        Some(id) => id,
+        None => DbOperations::get_external_account(&mut tx, business_id, &currency).await?,
+    };
+    let credit_account_id = match to_account_id {
+        Some(id) => id,
+        None => DbOperations::get_external_account(&mut tx, business_id, &currency).await?,
+    };
+
+    let txn_id = DbOperations::insert_reversal(
+        &mut tx,
+        business_id,
+        original.id,
+        from_account_id,
+        to_account_id,
+        amount,
+        &currency,
+        req.reference_id.clone(),
+        &req.idempotency_key,
+    )
+        .await?;
+
+    DbOperations::post_ledger_entries(
+        &mut tx,
+        business_id,
+        txn_id,
+        debit_account_id,
+        credit_account_id,
+        amount,
+    )
+        .await?;
+
+    DbOperations::mark_transaction_status(&mut tx, txn_id, TransactionStatus::Succeeded).await?;
+
+    // -------- DOMAIN EVENT --------
+
+    let payload = serde_json::json!({
+        "event": "transaction.reversed",
+        "data": {
+            "transaction_id": txn_id,
+            "type": "reversal",
+            "reversal_of": original.id,
+            "amount": amount,
+            "currency": currency,
+            "remaining_reversible": remaining - amount,
+            "from_account_id": from_account_id,
+            "to_account_id": to_account_id,
+            "business_id": business_id,
+            "reference_id": req.reference_id
+        }
+    });
+
+    let mut queue_messages = app_state
+        .database_connector
+        .publish_domain_event_txn(&mut tx, business_id, "transaction.reversed", payload)
+        .await?;
+    let touched = [(from_account_id, &from_account), (to_account_id, &to_account)];
+    for (account_id, locked) in touched {
+        if let (Some(account_id), Some(locked)) = (account_id, locked) {
+            queue_messages.extend(
+                app_state
+                    .database_connector
+                    .publish_balance_events_txn(&mut tx, business_id, account_id, locked.balance, txn_id)
+                    .await?,
             );
         }
     }
+
+    DbOperations::complete_idempotency_key(
+        &mut tx,
+        business_id,
+        IDEMPOTENCY_SCOPE_TRANSACTIONS,
+        &req.idempotency_key,
+        StatusCode::CREATED.as_u16(),
+        &serde_json::json!(ApiResponse::success(txn_id)),
+    )
+        .await?;
+
+    tx.commit().await?;
+
+    for message in queue_messages {
+        app_state
+            .event_queue
+            .send(message)
+            .expect("Unable to add Event Id to the Queue");
+    }
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ApiResponse::success(txn_id)),
+    ))
 }
 
 
@@ -531,47 +1141,31 @@ pub async fn transfer_money(
 pub async fn get_all_transactions(
     State(app_state): State<Arc<AppState>>,
     Extension(account): Extension<AccountId>,
+    Query(filters): Query<TransactionFilters>,
 ) -> impl IntoResponse {
 
-    let rows = sqlx::query(
-        "SELECT id, business_id, from_account_id, to_account_id,
-                type::TEXT, amount, status::TEXT, reference_id,
-                idempotency_key, created_at
-         FROM transactions
-         WHERE business_id = $1
-         ORDER BY created_at DESC"
-    )
-        .bind(account.account_id)
-        .fetch_all(&app_state.database_connector.connector)
-        .await;
-
-    match rows {
-        Ok(rows) => {
-            let txns = rows
-                .into_iter()
-                .map(|r| Transaction {
-                    id: r.get("id"),
-                    business_id: r.get("business_id"),
-                    from_account_id: r.get("from_account_id"),
-                    to_account_id: r.get("to_account_id"),
-                    txn_type: r.get("type"),
-                    amount: r.get("amount"),
-                    status: r.get("status"),
-                    reference_id: r.get("reference_id"),
-                    idempotency_key: r.get("idempotency_key"),
-                    created_at: r.get("created_at"),
-                })
-                .collect::<Vec<_>>();
-
-            (
-                axum::http::StatusCode::OK,
-                Json(ApiResponse::success(txns)),
-            )
+    let limit = filters.limit.unwrap_or(50).clamp(1, 200);
+    let cursor = match filters.cursor.as_deref().map(decode_cursor) {
+        None => None,
+        Some(Some(cursor)) => Some(cursor),
+        Some(None) => {
+            return AppError::new(ErrorCode::InvalidCursor, "Invalid cursor").into_api::<TransactionPage>();
         }
-        Err(e) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<Vec<Transaction>>::error(e.to_string())),
+    };
+
+    match app_state
+        .database_connector
+        .get_transactions(account.account_id, &filters, cursor, limit)
+        .await
+    {
+        Ok(page) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(page)),
         ),
+        Err(e) => {
+            tracing::error!("error getting transactions {}", e);
+            AppError::from(e).into_api::<TransactionPage>()
+        }
     }
 }
 
@@ -583,42 +1177,303 @@ pub async fn get_transaction_details(
     Path(transaction_id): Path<i64>,
 ) -> impl IntoResponse {
 
-    let row = sqlx::query(
-        "SELECT id, business_id, from_account_id, to_account_id,
-                type::TEXT, amount, status::TEXT, reference_id,
-                idempotency_key, created_at
-         FROM transactions
-         WHERE id = $1 AND business_id = $2"
-    )
-        .bind(transaction_id)
-        .bind(account.account_id)
-        .fetch_optional(&app_state.database_connector.connector)
-        .await;
-
-    match row {
-        Ok(Some(r)) => (
-            axum::http::StatusCode::OK,
-            Json(ApiResponse::success(Transaction {
-                id: r.get("id"),
-                business_id: r.get("business_id"),
-                from_account_id: r.get("from_account_id"),
-                to_account_id: r.get("to_account_id"),
-                txn_type: r.get("type"),
-                amount: r.get("amount"),
-                status: r.get("status"),
-                reference_id: r.get("reference_id"),
-                idempotency_key: r.get("idempotency_key"),
-                created_at: r.get("created_at"),
-            })),
-        ),
-        Ok(None) => (
-            axum::http::StatusCode::NOT_FOUND,
-            Json(ApiResponse::<Transaction>::error("Transaction not found".into())),
-        ),
-        Err(e) => (
-            axum::http::StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ApiResponse::<Transaction>::error(e.to_string())),
+    match app_state
+        .database_connector
+        .get_transaction(account.account_id, transaction_id)
+        .await
+    {
+        Ok(Some(transaction)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(transaction)),
         ),
+        Ok(None) => AppError::new(ErrorCode::TransactionNotFound, "Transaction not found").into_api::<Transaction>(),
+        Err(e) => AppError::from(e).into_api::<Transaction>(),
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use tokio::sync::mpsc::UnboundedReceiver;
+    use crate::models::accounts_models::NewAccount;
+    use crate::models::event_queue::WebhookQueueMessage;
+    use crate::models::fx_models::NewFxRate;
+    use crate::middlewares::rate_limit_middleware::RateLimiter;
+    use crate::test_support::test_db;
+
+    async fn test_state() -> (Arc<AppState>, UnboundedReceiver<WebhookQueueMessage>) {
+        crate::test_support::test_state(test_db(20).await, RateLimiter::from_env())
+    }
+
+    #[test]
+    fn transfer_backoff_is_capped() {
+        assert!(transfer_backoff(1) < Duration::from_millis(30));
+        for attempt in [10, 64, u32::MAX] {
+            assert!(transfer_backoff(attempt) < Duration::from_millis(MAX_TRANSFER_BACKOFF_MS + 10));
+        }
+    }
+
+    async fn funded_account(state: &Arc<AppState>, business_id: i64, amount: Decimal) -> i64 {
+        let account_id = state.database_connector
+            .create_account(business_id, NewAccount { name: "wallet".into(), currency: "USD".into(), overdraft_limit: Decimal::ZERO, allow_negative: false })
+            .await
+            .unwrap();
+        let response = credit_money(
+            State(state.clone()),
+            Extension(AccountId { account_id: business_id }),
+            Json(CreditRequest {
+                to_account_id: account_id,
+                amount,
+                currency: "USD".into(),
+                reference_id: None,
+                idempotency_key: format!("fund-{}", account_id),
+            }),
+        ).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        account_id
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn opposite_transfers_do_not_deadlock() {
+        let (state, _rx) = test_state().await;
+        let business_id = state.database_connector.create_new_business("concurrency").await.unwrap();
+        let a = funded_account(&state, business_id, Decimal::new(100000, 2)).await;
+        let b = funded_account(&state, business_id, Decimal::new(100000, 2)).await;
+
+        let mut handles = Vec::new();
+        for i in 0..50 {
+            for (from, to) in [(a, b), (b, a)] {
+                let state = state.clone();
+                handles.push(tokio::spawn(async move {
+                    transfer_money(
+                        State(state),
+                        Extension(AccountId { account_id: business_id }),
+                        Json(TransferRequest {
+                            from_account_id: from,
+                            to_account_id: to,
+                            amount: Decimal::new(100, 2),
+                            currency: "USD".into(),
+                            quote_id: None,
+                            reference_id: None,
+                            idempotency_key: format!("hammer-{}-{}-{}", from, to, i),
+                        }),
+                    ).await.into_response().status()
+                }));
+            }
+        }
+        for handle in handles {
+            assert_eq!(handle.await.unwrap(), StatusCode::CREATED);
+        }
+
+        for account_id in [a, b] {
+            let reconciliation = state.database_connector.reconcile_account(account_id).await.unwrap();
+            assert_eq!(reconciliation.balance, Decimal::new(100000, 2));
+            assert!(reconciliation.in_balance);
+            assert_history_adds_up(&state, account_id, reconciliation.balance).await;
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_quoted_transfer_books_the_fx_gain_against_the_current_rate() {
+        let (state, _rx) = test_state().await;
+        let db = &state.database_connector;
+        let business_id = db.create_new_business("fx").await.unwrap();
+        let from = funded_account(&state, business_id, Decimal::new(10000, 2)).await;
+        let to = db
+            .create_account(business_id, NewAccount { name: "euros".into(), currency: "EUR".into(), overdraft_limit: Decimal::ZERO, allow_negative: false })
+            .await
+            .unwrap();
+        let rate = |rate: Decimal, valid_from| NewFxRate {
+            base_currency: "USD".into(),
+            quote_currency: "EUR".into(),
+            rate,
+            valid_from,
+            valid_to: None,
+        };
+
+        // quoted at 0.90, the rate has moved to 0.95 by the time the transfer runs
+        db.create_fx_rate(&rate(Decimal::new(90, 2), Some(Utc::now() - chrono::Duration::seconds(1)))).await.unwrap();
+        let quote = db.create_fx_quote(business_id, "USD", "EUR", 60).await.unwrap().unwrap();
+        db.create_fx_rate(&rate(Decimal::new(95, 2), None)).await.unwrap();
+
+        let response = transfer_money(
+            State(state.clone()),
+            Extension(AccountId { account_id: business_id }),
+            Json(TransferRequest {
+                from_account_id: from,
+                to_account_id: to,
+                amount: Decimal::new(1000, 2),
+                currency: "USD".into(),
+                quote_id: Some(quote.id),
+                reference_id: None,
+                idempotency_key: format!("fx-gain-{}", quote.id),
+            }),
+        ).await.into_response();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body: serde_json::Value =
+            serde_json::from_slice(&axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap()).unwrap();
+        let txn = db.get_transaction(business_id, body["data"].as_i64().unwrap()).await.unwrap().unwrap();
+        assert_eq!(txn.destination_amount, Some(Decimal::new(900, 2)));
+        assert_eq!(txn.fx_gain_loss, Some(Decimal::new(50, 2)));
+
+        // the euro fx account is short 9.50 as if converted at the current rate, the 0.50 sits on gain/loss
+        let balances: Vec<(String, Decimal)> = sqlx::query_as(
+            "SELECT account_type::TEXT, balance FROM business_accounts
+             WHERE business_id = $1 AND currency = 'EUR' ORDER BY account_type::TEXT"
+        )
+            .bind(business_id)
+            .fetch_all(&db.connector)
+            .await
+            .unwrap();
+        assert_eq!(balances, vec![
+            ("customer".to_string(), Decimal::new(900, 2)),
+            ("fx".to_string(), Decimal::new(-950, 2)),
+            ("fx_gain_loss".to_string(), Decimal::new(50, 2)),
+        ]);
+    }
+
+    async fn json_body(response: axum::response::Response) -> (StatusCode, serde_json::Value) {
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    fn debit(from_account_id: i64, amount: Decimal, currency: &str, idempotency_key: &str) -> Json<DebitRequest> {
+        Json(DebitRequest {
+            from_account_id,
+            amount,
+            currency: currency.into(),
+            reference_id: None,
+            idempotency_key: idempotency_key.into(),
+        })
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_rejected_debit_is_kept_as_failed_and_announced() {
+        let (state, mut rx) = test_state().await;
+        let db = &state.database_connector;
+        let business_id = db.create_new_business("failures").await.unwrap();
+        let account_id = funded_account(&state, business_id, Decimal::new(1000, 2)).await;
+        let webhook_id = db.create_webhook(business_id, "http://127.0.0.1:1/hook", &["transaction.failed".to_string()]).await.unwrap();
+        let business = || Extension(AccountId { account_id: business_id });
+
+        let response = debit_money(State(state.clone()), business(), debit(account_id, Decimal::new(2500, 2), "usd", "too-much")).await;
+        let (status, body) = json_body(response.into_response()).await;
+        assert_eq!(status, ErrorCode::InsufficientFunds.status());
+        assert_eq!(body["error_code"], "insufficient_funds");
+        let txn = db.get_transaction(business_id, body["data"].as_i64().unwrap()).await.unwrap().unwrap();
+        assert_eq!(txn.status, "failed");
+        assert_eq!(txn.failure_code.as_deref(), Some("insufficient_funds"));
+        assert_eq!((txn.amount, txn.currency.as_str()), (Decimal::new(2500, 2), "USD"));
+        let entries: i64 = sqlx::query_scalar("SELECT count(*) FROM ledger_entries WHERE transaction_id = $1")
+            .bind(txn.id)
+            .fetch_one(&db.connector)
+            .await
+            .unwrap();
+        assert_eq!(entries, 0);
+
+        let message = rx.try_recv().expect("transaction.failed is queued for the webhook");
+        assert_eq!(message.webhook_id, webhook_id);
+        let event = db.get_webhook_event(message.webhook_event_id).await.unwrap().unwrap();
+        assert_eq!(event.event_type, "transaction.failed");
+        assert_eq!(event.payload["data"]["transaction_id"], txn.id);
+        assert_eq!(event.payload["data"]["failure_code"], "insufficient_funds");
+
+        // the rejection is what a retry with the same key gets back
+        let response = debit_money(State(state.clone()), business(), debit(account_id, Decimal::new(2500, 2), "usd", "too-much")).await;
+        assert_eq!(json_body(response.into_response()).await.1, body);
+
+        // a debit in another currency keeps the currency it asked for
+        let response = debit_money(State(state.clone()), business(), debit(account_id, Decimal::ONE, "eur", "wrong-currency")).await;
+        let (_, body) = json_body(response.into_response()).await;
+        assert_eq!(body["error_code"], "currency_mismatch");
+        let txn = db.get_transaction(business_id, body["data"].as_i64().unwrap()).await.unwrap().unwrap();
+        assert_eq!((txn.status.as_str(), txn.currency.as_str()), ("failed", "EUR"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_malformed_amount_or_currency_is_rejected_without_a_record() {
+        let (state, mut rx) = test_state().await;
+        let db = &state.database_connector;
+        let business_id = db.create_new_business("malformed").await.unwrap();
+        let account_id = funded_account(&state, business_id, Decimal::new(1000, 2)).await;
+        db.create_webhook(business_id, "http://127.0.0.1:1/hook", &["transaction.failed".to_string()]).await.unwrap();
+        let business = || Extension(AccountId { account_id: business_id });
+
+        let rejections = [
+            (Decimal::new(-500, 2), "USD", "invalid_amount"),
+            (Decimal::ZERO, "USD", "invalid_amount"),
+            (Decimal::new(1001, 3), "USD", "invalid_amount"),
+            (Decimal::ONE, "DOLLARS", "invalid_currency"),
+        ];
+        for (amount, currency, code) in rejections {
+            let response = debit_money(State(state.clone()), business(), debit(account_id, amount, currency, "malformed")).await;
+            let (status, body) = json_body(response.into_response()).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{} {}", amount, currency);
+            assert_eq!(body["error_code"], code);
+            assert!(body["data"].is_null());
+        }
+
+        // the amount is checked before the account's status and currency, which would be recorded
+        let frozen = funded_account(&state, business_id, Decimal::new(1000, 2)).await;
+        sqlx::query("UPDATE business_accounts SET status = 'frozen' WHERE id = $1")
+            .bind(frozen)
+            .execute(&db.connector)
+            .await
+            .unwrap();
+        let credit = |to_account_id: i64, currency: &str| Json(CreditRequest {
+            to_account_id,
+            amount: Decimal::new(-500, 2),
+            currency: currency.into(),
+            reference_id: None,
+            idempotency_key: "malformed".into(),
+        });
+        let responses = [
+            credit_money(State(state.clone()), business(), credit(frozen, "USD")).await.into_response(),
+            credit_money(State(state.clone()), business(), credit(account_id, "EUR")).await.into_response(),
+            debit_money(State(state.clone()), business(), debit(frozen, Decimal::new(-500, 2), "USD", "malformed")).await.into_response(),
+            debit_money(State(state.clone()), business(), debit(account_id, Decimal::new(-500, 2), "EUR", "malformed")).await.into_response(),
+        ];
+        for response in responses {
+            let (status, body) = json_body(response).await;
+            assert_eq!((status, &body["error_code"]), (StatusCode::BAD_REQUEST, &serde_json::json!("invalid_amount")));
+        }
+
+        let recorded: i64 = sqlx::query_scalar("SELECT count(*) FROM transactions WHERE business_id = $1 AND status = 'failed'")
+            .bind(business_id)
+            .fetch_one(&db.connector)
+            .await
+            .unwrap();
+        assert_eq!(recorded, 0);
+        assert!(rx.try_recv().is_err());
+
+        // none of them used up the key
+        let response = debit_money(State(state.clone()), business(), debit(account_id, Decimal::ONE, "USD", "malformed")).await;
+        assert_eq!(response.into_response().status(), StatusCode::CREATED);
+    }
+
+    // walks the account's history page by page, newest first, and checks every running balance
+    // against the entry before it
+    async fn assert_history_adds_up(state: &Arc<AppState>, account_id: i64, balance: Decimal) {
+        let mut expected = balance;
+        let mut cursor = None;
+        loop {
+            let page = state.database_connector.get_account_history(account_id, cursor, 7).await.unwrap();
+            for entry in &page.entries {
+                assert_eq!(entry.running_balance, expected, "entry {}", entry.entry_id);
+                expected -= if entry.direction == "credit" { entry.amount } else { -entry.amount };
+            }
+            match page.next_cursor {
+                Some(next) => cursor = decode_cursor(&next),
+                None => break,
+            }
+        }
+        assert_eq!(expected, Decimal::ZERO);
+    }
+}
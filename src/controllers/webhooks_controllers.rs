@@ -4,9 +4,9 @@ use axum::{Extension, Json};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use crate::AppState;
-use crate::models::bussiness_models::Business;
+use crate::error::{AppError, ErrorCode};
 use crate::models::common::{AccountId, ApiResponse};
-use crate::models::webhooks_models::{CreateWebhookRequest, UpdateWebhookRequest, WebhookResponse};
+use crate::models::webhooks_models::{validate_event_types, CreateWebhookRequest, UpdateWebhookRequest, WebhookResponse, ALL_WEBHOOK_EVENTS};
 
 pub async fn get_webhooks(
     State(app_state): State<Arc<AppState>>,
@@ -28,10 +28,7 @@ pub async fn get_webhooks(
         ),
         Err(e) => {
             tracing::error!("error fetching webhooks {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<Vec<WebhookResponse>>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<Vec<WebhookResponse>>()
         }
     }
 }
@@ -48,9 +45,17 @@ pub async fn register_webhook(
         business_account.account_id
     );
 
+    let event_types = req
+        .event_types
+        .unwrap_or_else(|| vec![ALL_WEBHOOK_EVENTS.to_string()]);
+    if let Err(e) = validate_event_types(&event_types) {
+        tracing::warn!("invalid event types {:?}", event_types);
+        return AppError::new(ErrorCode::ValidationFailed, e).into_api::<i64>();
+    }
+
     match app_state
         .database_connector
-        .create_webhook(business_account.account_id, &req.url)
+        .create_webhook(business_account.account_id, &req.url, &event_types)
         .await
     {
         Ok(id) => (
@@ -59,10 +64,7 @@ pub async fn register_webhook(
         ),
         Err(e) => {
             tracing::error!("error creating webhook {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<i64>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<i64>()
         }
     }
 }
@@ -85,20 +87,14 @@ pub async fn delete_webhook(
         .disable_webhook(business_account.account_id, webhook_id)
         .await
     {
-        Ok(0) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::<String>::error("webhook not found".into())),
-        ),
+        Ok(0) => AppError::new(ErrorCode::WebhookNotFound, "webhook not found").into_api::<String>(),
         Ok(_) => (
             StatusCode::OK,
             Json(ApiResponse::success("webhook disabled".to_string())),
         ),
         Err(e) => {
             tracing::error!("error disabling webhook {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<String>()
         }
     }
 }
@@ -116,6 +112,13 @@ pub async fn update_webhook(
         business_account.account_id
     );
 
+    if let Some(event_types) = &req.event_types
+        && let Err(e) = validate_event_types(event_types)
+    {
+        tracing::warn!("invalid event types {:?}", event_types);
+        return AppError::new(ErrorCode::ValidationFailed, e).into_api::<String>();
+    }
+
     match app_state
         .database_connector
         .update_webhook(
@@ -123,23 +126,76 @@ pub async fn update_webhook(
             webhook_id,
             req.url,
             req.status,
+            req.event_types,
         )
         .await
     {
-        Ok(0) => (
-            StatusCode::NOT_FOUND,
-            Json(ApiResponse::<String>::error("webhook not found".into())),
-        ),
+        Ok(0) => AppError::new(ErrorCode::WebhookNotFound, "webhook not found").into_api::<String>(),
         Ok(_) => (
             StatusCode::OK,
             Json(ApiResponse::success("webhook updated".to_string())),
         ),
         Err(e) => {
             tracing::error!("error updating webhook {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ApiResponse::<String>::error(e.to_string())),
-            )
+            AppError::from(e).into_api::<String>()
+        }
+    }
+}
+
+
+pub async fn get_webhook_secret(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business_account): Extension<AccountId>,
+    Path(webhook_id): Path<i64>,
+) -> impl IntoResponse {
+    tracing::info!(
+        "revealing secret of webhook {} for business {}",
+        webhook_id,
+        business_account.account_id
+    );
+
+    match app_state
+        .database_connector
+        .get_webhook_secret(business_account.account_id, webhook_id)
+        .await
+    {
+        Ok(None) => AppError::new(ErrorCode::WebhookNotFound, "webhook not found").into_api::<String>(),
+        Ok(Some(secret)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(secret)),
+        ),
+        Err(e) => {
+            tracing::error!("error getting webhook secret {}", e);
+            AppError::from(e).into_api::<String>()
+        }
+    }
+}
+
+
+pub async fn rotate_webhook_secret(
+    State(app_state): State<Arc<AppState>>,
+    Extension(business_account): Extension<AccountId>,
+    Path(webhook_id): Path<i64>,
+) -> impl IntoResponse {
+    tracing::info!(
+        "rotating secret of webhook {} for business {}",
+        webhook_id,
+        business_account.account_id
+    );
+
+    match app_state
+        .database_connector
+        .rotate_webhook_secret(business_account.account_id, webhook_id)
+        .await
+    {
+        Ok(None) => AppError::new(ErrorCode::WebhookNotFound, "webhook not found").into_api::<String>(),
+        Ok(Some(secret)) => (
+            StatusCode::OK,
+            Json(ApiResponse::success(secret)),
+        ),
+        Err(e) => {
+            tracing::error!("error rotating webhook secret {}", e);
+            AppError::from(e).into_api::<String>()
         }
     }
 }
@@ -0,0 +1,305 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use crate::models::common::ApiResponse;
+
+/// Machine-readable codes sent as `error_code`. Clients branch on them, so a code is never
+/// renamed or given another meaning. Every code is listed in `ErrorCode::ALL` and in API.md.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    ValidationFailed,
+    InvalidCurrency,
+    InvalidAmount,
+    InvalidCursor,
+    InvalidIdempotencyKey,
+    Unauthorized,
+    BusinessSuspended,
+    InsufficientScope,
+    AccountAccessDenied,
+    NotFound,
+    AccountNotFound,
+    BusinessNotFound,
+    ApiKeyNotFound,
+    HoldNotFound,
+    TransactionNotFound,
+    WebhookNotFound,
+    FxQuoteNotFound,
+    Conflict,
+    StatusUnchanged,
+    AccountFrozen,
+    AccountClosed,
+    AccountNotEmpty,
+    HoldNotActive,
+    HoldExpired,
+    FxQuoteUsed,
+    IdempotencyKeyInProgress,
+    SecretAlreadyShown,
+    PayloadTooLarge,
+    InsufficientFunds,
+    CurrencyMismatch,
+    FxRateUnavailable,
+    FxQuoteExpired,
+    FxQuoteMismatch,
+    ReversalNotAllowed,
+    ReversalExceedsRemaining,
+    IdempotencyKeyReused,
+    InvalidReference,
+    RateLimited,
+    InternalError,
+    RateLimiterUnavailable,
+}
+
+impl ErrorCode {
+    pub const ALL: [ErrorCode; 40] = [
+        ErrorCode::ValidationFailed,
+        ErrorCode::InvalidCurrency,
+        ErrorCode::InvalidAmount,
+        ErrorCode::InvalidCursor,
+        ErrorCode::InvalidIdempotencyKey,
+        ErrorCode::Unauthorized,
+        ErrorCode::BusinessSuspended,
+        ErrorCode::InsufficientScope,
+        ErrorCode::AccountAccessDenied,
+        ErrorCode::NotFound,
+        ErrorCode::AccountNotFound,
+        ErrorCode::BusinessNotFound,
+        ErrorCode::ApiKeyNotFound,
+        ErrorCode::HoldNotFound,
+        ErrorCode::TransactionNotFound,
+        ErrorCode::WebhookNotFound,
+        ErrorCode::FxQuoteNotFound,
+        ErrorCode::Conflict,
+        ErrorCode::StatusUnchanged,
+        ErrorCode::AccountFrozen,
+        ErrorCode::AccountClosed,
+        ErrorCode::AccountNotEmpty,
+        ErrorCode::HoldNotActive,
+        ErrorCode::HoldExpired,
+        ErrorCode::FxQuoteUsed,
+        ErrorCode::IdempotencyKeyInProgress,
+        ErrorCode::SecretAlreadyShown,
+        ErrorCode::PayloadTooLarge,
+        ErrorCode::InsufficientFunds,
+        ErrorCode::CurrencyMismatch,
+        ErrorCode::FxRateUnavailable,
+        ErrorCode::FxQuoteExpired,
+        ErrorCode::FxQuoteMismatch,
+        ErrorCode::ReversalNotAllowed,
+        ErrorCode::ReversalExceedsRemaining,
+        ErrorCode::IdempotencyKeyReused,
+        ErrorCode::InvalidReference,
+        ErrorCode::RateLimited,
+        ErrorCode::InternalError,
+        ErrorCode::RateLimiterUnavailable,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::ValidationFailed => "validation_failed",
+            ErrorCode::InvalidCurrency => "invalid_currency",
+            ErrorCode::InvalidAmount => "invalid_amount",
+            ErrorCode::InvalidCursor => "invalid_cursor",
+            ErrorCode::InvalidIdempotencyKey => "invalid_idempotency_key",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::BusinessSuspended => "business_suspended",
+            ErrorCode::InsufficientScope => "insufficient_scope",
+            ErrorCode::AccountAccessDenied => "account_access_denied",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::AccountNotFound => "account_not_found",
+            ErrorCode::BusinessNotFound => "business_not_found",
+            ErrorCode::ApiKeyNotFound => "api_key_not_found",
+            ErrorCode::HoldNotFound => "hold_not_found",
+            ErrorCode::TransactionNotFound => "transaction_not_found",
+            ErrorCode::WebhookNotFound => "webhook_not_found",
+            ErrorCode::FxQuoteNotFound => "fx_quote_not_found",
+            ErrorCode::Conflict => "conflict",
+            ErrorCode::StatusUnchanged => "status_unchanged",
+            ErrorCode::AccountFrozen => "account_frozen",
+            ErrorCode::AccountClosed => "account_closed",
+            ErrorCode::AccountNotEmpty => "account_not_empty",
+            ErrorCode::HoldNotActive => "hold_not_active",
+            ErrorCode::HoldExpired => "hold_expired",
+            ErrorCode::FxQuoteUsed => "fx_quote_used",
+            ErrorCode::IdempotencyKeyInProgress => "idempotency_key_in_progress",
+            ErrorCode::SecretAlreadyShown => "secret_already_shown",
+            ErrorCode::PayloadTooLarge => "payload_too_large",
+            ErrorCode::InsufficientFunds => "insufficient_funds",
+            ErrorCode::CurrencyMismatch => "currency_mismatch",
+            ErrorCode::FxRateUnavailable => "fx_rate_unavailable",
+            ErrorCode::FxQuoteExpired => "fx_quote_expired",
+            ErrorCode::FxQuoteMismatch => "fx_quote_mismatch",
+            ErrorCode::ReversalNotAllowed => "reversal_not_allowed",
+            ErrorCode::ReversalExceedsRemaining => "reversal_exceeds_remaining",
+            ErrorCode::IdempotencyKeyReused => "idempotency_key_reused",
+            ErrorCode::InvalidReference => "invalid_reference",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::InternalError => "internal_error",
+            ErrorCode::RateLimiterUnavailable => "rate_limiter_unavailable",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorCode::ValidationFailed
+            | ErrorCode::InvalidCurrency
+            | ErrorCode::InvalidAmount
+            | ErrorCode::InvalidCursor
+            | ErrorCode::InvalidIdempotencyKey => StatusCode::BAD_REQUEST,
+            ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorCode::BusinessSuspended
+            | ErrorCode::InsufficientScope
+            | ErrorCode::AccountAccessDenied => StatusCode::FORBIDDEN,
+            ErrorCode::NotFound
+            | ErrorCode::AccountNotFound
+            | ErrorCode::BusinessNotFound
+            | ErrorCode::ApiKeyNotFound
+            | ErrorCode::HoldNotFound
+            | ErrorCode::TransactionNotFound
+            | ErrorCode::WebhookNotFound
+            | ErrorCode::FxQuoteNotFound => StatusCode::NOT_FOUND,
+            ErrorCode::Conflict
+            | ErrorCode::StatusUnchanged
+            | ErrorCode::AccountFrozen
+            | ErrorCode::AccountClosed
+            | ErrorCode::AccountNotEmpty
+            | ErrorCode::HoldNotActive
+            | ErrorCode::HoldExpired
+            | ErrorCode::FxQuoteUsed
+            | ErrorCode::IdempotencyKeyInProgress
+            | ErrorCode::SecretAlreadyShown => StatusCode::CONFLICT,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::InsufficientFunds
+            | ErrorCode::CurrencyMismatch
+            | ErrorCode::FxRateUnavailable
+            | ErrorCode::FxQuoteExpired
+            | ErrorCode::FxQuoteMismatch
+            | ErrorCode::ReversalNotAllowed
+            | ErrorCode::ReversalExceedsRemaining
+            | ErrorCode::IdempotencyKeyReused
+            | ErrorCode::InvalidReference => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorCode::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::InternalError => StatusCode::INTERNAL_SERVER_ERROR,
+            ErrorCode::RateLimiterUnavailable => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+
+/// Error of a handler, its status comes from the error code. Database errors are mapped to a
+/// code and never shown to the client as they are.
+#[derive(Debug)]
+pub enum AppError {
+    Api(ErrorCode, String),
+    Database(sqlx::Error),
+}
+
+impl AppError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        AppError::Api(code, message.into())
+    }
+
+    // for `map_err` on a query whose row has to exist, e.g. an account named in the request
+    pub fn missing(code: ErrorCode, message: &'static str) -> impl FnOnce(sqlx::Error) -> AppError {
+        move |err| match err {
+            sqlx::Error::RowNotFound => AppError::new(code, message),
+            err => AppError::Database(err),
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Api(code, _) => *code,
+            AppError::Database(sqlx::Error::RowNotFound) => ErrorCode::NotFound,
+            AppError::Database(sqlx::Error::Database(db_err)) => match db_err.code().as_deref() {
+                Some("23505") => ErrorCode::Conflict,
+                Some("23503") => ErrorCode::InvalidReference,
+                _ => ErrorCode::InternalError,
+            },
+            AppError::Database(_) => ErrorCode::InternalError,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match (self, self.code()) {
+            (AppError::Api(_, message), _) => message.clone(),
+            (_, ErrorCode::NotFound) => "Resource not found".to_string(),
+            (_, ErrorCode::Conflict) => "Resource already exists".to_string(),
+            (_, ErrorCode::InvalidReference) => "Referenced resource does not exist".to_string(),
+            _ => "Internal server error".to_string(),
+        }
+    }
+
+    /// The status and body of the error, for handlers that answer with a plain tuple.
+    pub fn into_api<T>(self) -> (StatusCode, Json<ApiResponse<T>>) {
+        let code = self.code();
+        if let AppError::Database(err) = &self {
+            if code == ErrorCode::InternalError {
+                tracing::error!("database error: {}", err);
+            } else {
+                tracing::warn!("database error mapped to {}: {}", code.as_str(), err);
+            }
+        }
+        (code.status(), Json(ApiResponse::error(code, self.message())))
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(err: sqlx::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        self.into_api::<()>().into_response()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn error_codes_are_unique_snake_case_and_documented() {
+        let api_docs = include_str!("../API.md");
+        let mut seen = HashSet::new();
+        for code in ErrorCode::ALL {
+            let name = code.as_str();
+            assert!(seen.insert(name), "{} is listed twice", name);
+            assert!(
+                name.chars().all(|c| c.is_ascii_lowercase() || c == '_'),
+                "{} is not snake_case",
+                name
+            );
+            let row = format!("| `{}` | {} |", name, code.status().as_u16());
+            assert!(api_docs.contains(&row), "API.md has no `{}` row for {}", row, name);
+        }
+    }
+
+    #[test]
+    fn error_statuses_are_client_or_server_errors() {
+        for code in ErrorCode::ALL {
+            let status = code.status();
+            assert!(status.is_client_error() || status.is_server_error(), "{} maps to {}", code.as_str(), status);
+        }
+    }
+
+    #[test]
+    fn missing_rows_are_not_found_and_database_messages_stay_hidden() {
+        let (status, Json(body)) = AppError::from(sqlx::Error::RowNotFound).into_api::<()>();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body.error_code.as_deref(), Some("not_found"));
+
+        let missing_account = AppError::missing(ErrorCode::AccountNotFound, "Account not found");
+        let (status, Json(body)) = missing_account(sqlx::Error::RowNotFound).into_api::<()>();
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body.error_code.as_deref(), Some("account_not_found"));
+
+        let (status, Json(body)) = AppError::from(sqlx::Error::PoolTimedOut).into_api::<()>();
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body.error.as_deref(), Some("Internal server error"));
+        assert_eq!(body.error_code.as_deref(), Some("internal_error"));
+    }
+}
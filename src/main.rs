@@ -5,6 +5,9 @@ mod middlewares;
 mod models;
 mod services;
 mod controllers;
+mod error;
+#[cfg(test)]
+mod test_support;
 
 use std::sync::Arc;
 use axum::{middleware, Router};
@@ -19,17 +22,22 @@ use crate::controllers::business_controllers::get_business_details;
 use crate::models::event_queue::WebhookQueueMessage;
 use crate::routes::accounts_routes::accounts_routes;
 use crate::routes::admin_routes::admin_routes;
+use crate::routes::api_keys_routes::api_keys_routes;
 use crate::routes::transaction_routes::transaction_routes;
 use crate::routes::webhooks_routes::webhook_routes;
 use crate::services::db_operations::DbOperations;
 use crate::services::other_services::demo_listening_webhook;
-use crate::services::webhook_events_executor::{redis_expiry_subscriber, webhook_worker};
-use crate::middlewares::rate_limit_middleware::rate_limit_middleware;
+use crate::services::holds_executor::hold_expiry_sweeper;
+use crate::services::api_keys_executor::api_key_expiry_sweeper;
+use crate::services::idempotency_executor::idempotency_key_sweeper;
+use crate::services::webhook_events_executor::{outbox_sweeper, redis_expiry_subscriber, webhook_worker};
+use crate::middlewares::rate_limit_middleware::{rate_limit_middleware, RateLimiter};
 
 pub struct AppState {
    pub database_connector: DbOperations,
    pub event_queue: tokio::sync::mpsc::UnboundedSender<WebhookQueueMessage>,
     pub redis_client: redis::Client,
+    pub rate_limiter: RateLimiter,
 }
 static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 #[tokio::main]
@@ -50,7 +58,6 @@ async fn main() {
 
 async fn top_level_routes() -> Router {
     let database_connector = DbOperations::new().await ;
-    let is_local = std::env::var("is_local").unwrap_or("false".to_string()) ;
         tracing::info!("running migrations") ;
         MIGRATOR.run(&database_connector.connector).await.expect("migration error") ;
     let (event_tx, event_rx) = unbounded_channel::<WebhookQueueMessage>();
@@ -60,6 +67,7 @@ async fn top_level_routes() -> Router {
         database_connector,
         event_queue: event_tx,
         redis_client,
+        rate_limiter: RateLimiter::from_env(),
     }) ;
     tracing::info!("spawning webhook worker") ;
     tokio::spawn(webhook_worker(
@@ -67,6 +75,18 @@ async fn top_level_routes() -> Router {
         event_rx,
     ));
 
+    tracing::info!("spawning outbox sweeper") ;
+    tokio::spawn(outbox_sweeper(state.clone()));
+
+    tracing::info!("spawning hold expiry sweeper") ;
+    tokio::spawn(hold_expiry_sweeper(state.clone()));
+
+    tracing::info!("spawning api key expiry sweeper") ;
+    tokio::spawn(api_key_expiry_sweeper(state.clone()));
+
+    tracing::info!("spawning idempotency key sweeper") ;
+    tokio::spawn(idempotency_key_sweeper(state.clone()));
+
     tracing::info!("spawning redis expiry subscriber events listener") ;
     let state_ = state.clone() ;
     tokio::spawn(async move {
@@ -77,6 +97,12 @@ async fn top_level_routes() -> Router {
         }
     });
 
+    app(state).await
+}
+
+
+// every route of the service, the background workers are started by `top_level_routes`
+async fn app(state: Arc<AppState>) -> Router {
     Router::new()
         .route("/health",get(|| async {
             tracing::info!("Health check") ;
@@ -88,10 +114,11 @@ async fn top_level_routes() -> Router {
         .nest("/accounts", accounts_routes(state.clone()).await)
         .nest("/transaction", transaction_routes(state.clone()).await)
         .nest("/webhooks", webhook_routes(state.clone()).await)
-        .with_state(state.clone())
+        .nest("/api-keys", api_keys_routes(state.clone()).await)
         .route("/demo-webhook-listening", post(
             demo_listening_webhook
         )) // it is used for listening to the messages sent by the webhooks. just a demo type to listen to make
-    // sure everything working perfectly fine.
+    // sure everything working perfectly fine, it also verifies the delivery signature.
+        .with_state(state.clone())
         .layer(middleware::from_fn_with_state(state.clone(), rate_limit_middleware))
 }
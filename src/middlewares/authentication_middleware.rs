@@ -1,13 +1,15 @@
-use axum::extract::{Request, State};
+use axum::extract::{ConnectInfo, OriginalUri, Request, State};
 use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
 
+use std::net::SocketAddr;
 use std::sync::Arc;
 use crate::AppState;
 
 
 pub async fn auth_check(
     State(state): State<Arc<AppState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     mut req: Request,
     next: Next,
 ) -> Response {
@@ -23,11 +25,14 @@ pub async fn auth_check(
     let raw_key = match extract_bearer_token(headers) {
         Some(k) => k,
         None => {
-            return StatusCode::UNAUTHORIZED.into_response();
+            return AppError::new(ErrorCode::Unauthorized, "Missing or invalid API key").into_response();
         }
     };
     tracing::info!("Extracted bearer token") ;
 
+    if let Some(rejection) = failed_auth_rejection(&state, addr.ip()).await {
+        return rejection;
+    }
     let key_hash = hash_api_key(&raw_key);
     tracing::info!("the key hash is {}", key_hash);
     match state
@@ -35,11 +40,30 @@ pub async fn auth_check(
         .verify_business_api_key(&key_hash)
         .await
     {
-        Ok(business_id) => {
-            req.extensions_mut().insert(AccountId { account_id: business_id });
-            next.run(req).await
+        Ok(key) if key.business_status == "suspended" => {
+            tracing::warn!("api key {} used while business {} is suspended", key.key_id, key.business_id) ;
+            AppError::new(ErrorCode::BusinessSuspended, "Business is suspended").into_response()
+        }
+        Ok(key) => {
+            let path = req
+                .extensions()
+                .get::<OriginalUri>()
+                .map(|uri| uri.path().to_string())
+                .unwrap_or_else(|| req.uri().path().to_string());
+            let scope = required_scope(req.method(), &path);
+            if !key.has_scope(scope) {
+                tracing::warn!("api key {} lacks the {} scope for {}", key.key_id, scope, path) ;
+                return AppError::new(ErrorCode::InsufficientScope, format!("API key lacks the {} scope", scope))
+                    .into_response();
+            }
+            req.extensions_mut().insert(AccountId { account_id: key.business_id });
+            req.extensions_mut().insert(ApiKeyId { key_id: key.key_id });
+            with_db_scope(DbScope::Business(key.business_id), limit_tenant_request(&state, &key, req, next)).await
+        }
+        Err(_) => {
+            let unauthorized = AppError::new(ErrorCode::Unauthorized, "Missing or invalid API key").into_response();
+            charge_failed_auth(&state, addr.ip(), unauthorized).await
         }
-        Err(_) => StatusCode::UNAUTHORIZED.into_response(),
     }
 }
 
@@ -54,7 +78,7 @@ pub async fn admin_auth_check(
     let raw_key = match extract_bearer_token(headers) {
         Some(k) => k,
         None => {
-            return Err(StatusCode::UNAUTHORIZED.into_response());
+            return Err(AppError::new(ErrorCode::Unauthorized, "Missing or invalid API key").into_response());
         }
     };
 
@@ -70,13 +94,18 @@ pub async fn admin_auth_check(
                 account_id: admin_id,
             });
 
-            Ok(next.run(req).await)
+            Ok(with_db_scope(DbScope::Admin, next.run(req)).await)
         }
-        Err(_) => Err(StatusCode::UNAUTHORIZED.into_response()),
+        Err(_) => Err(AppError::new(ErrorCode::Unauthorized, "Missing or invalid API key").into_response()),
     }
 }
-use axum::http::{HeaderMap, StatusCode};
-use crate::models::common::AccountId;
+use axum::http::{HeaderMap, Method};
+use crate::error::{AppError, ErrorCode};
+use crate::models::bussiness_models::ALL_API_KEY_SCOPES;
+use crate::models::common::{AccountId, ApiKeyId};
+use crate::middlewares::rate_limit_middleware::{charge_failed_auth, failed_auth_rejection, limit_tenant_request, route_class};
+use crate::models::rate_limit_models::RouteClass;
+use crate::services::db_scope::{with_db_scope, DbScope};
 use crate::services::other_services::hash_api_key;
 
 fn extract_bearer_token(headers: &HeaderMap) -> Option<String> {
@@ -87,3 +116,116 @@ fn extract_bearer_token(headers: &HeaderMap) -> Option<String> {
         .map(|s| s.to_string())
 }
 
+
+// sections served behind `auth_check`
+const BUSINESS_SECTIONS: [&str; 5] = ["accounts", "get-business-account", "transaction", "webhooks", "api-keys"];
+
+fn section_of(path: &str) -> &str {
+    path.trim_start_matches('/').split('/').next().unwrap_or_default()
+}
+
+pub fn is_business_route(path: &str) -> bool {
+    BUSINESS_SECTIONS.contains(&section_of(path))
+}
+
+
+// the scope a business key needs for a route, matched on the full path since the
+// middleware also runs inside nested routers. A route missing here needs `*`, so a new
+// router stays closed to scoped keys until it is given a scope.
+fn required_scope(method: &Method, path: &str) -> &'static str {
+    let read = route_class(method) == RouteClass::Read;
+    match (section_of(path), read) {
+        ("accounts" | "get-business-account", true) => "accounts:read",
+        ("accounts", false) => "accounts:write",
+        ("transaction", true) => "transactions:read",
+        ("transaction", false) => "transactions:write",
+        ("webhooks", _) => "webhooks:manage",
+        ("api-keys", _) => "api_keys:read",
+        _ => ALL_API_KEY_SCOPES,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+    use crate::middlewares::rate_limit_middleware::RateLimiter;
+    use crate::models::bussiness_models::API_KEY_SCOPES;
+    use crate::test_support::{serve, test_db, test_state, Tenant};
+
+    #[test]
+    fn routes_need_the_scope_of_their_section_and_method() {
+        let cases = [
+            (Method::GET, "/accounts", "accounts:read"),
+            (Method::GET, "/accounts/7/balance", "accounts:read"),
+            (Method::GET, "/get-business-account", "accounts:read"),
+            (Method::POST, "/accounts", "accounts:write"),
+            (Method::PATCH, "/accounts/7", "accounts:write"),
+            (Method::GET, "/transaction/42", "transactions:read"),
+            (Method::POST, "/transaction/transfer", "transactions:write"),
+            (Method::POST, "/transaction/holds/3/capture", "transactions:write"),
+            (Method::GET, "/webhooks", "webhooks:manage"),
+            (Method::DELETE, "/webhooks/5", "webhooks:manage"),
+            (Method::GET, "/api-keys/9", "api_keys:read"),
+        ];
+        for (method, path, scope) in cases {
+            assert_eq!(required_scope(&method, path), scope, "{} {}", method, path);
+        }
+    }
+
+    #[test]
+    fn every_scope_opens_some_route() {
+        let methods = [Method::GET, Method::POST];
+        let sections = ["/accounts", "/transaction", "/webhooks", "/api-keys"];
+        for scope in API_KEY_SCOPES {
+            assert!(
+                sections.iter().any(|path| methods.iter().any(|method| required_scope(method, path) == scope)),
+                "{} opens nothing", scope
+            );
+        }
+    }
+
+    #[test]
+    fn unmapped_routes_need_every_scope() {
+        for path in ["/payouts", "/payouts/1", "/", "", "/accountsx", "/transactions"] {
+            assert_eq!(required_scope(&Method::GET, path), ALL_API_KEY_SCOPES, "{}", path);
+            assert_eq!(required_scope(&Method::POST, path), ALL_API_KEY_SCOPES, "{}", path);
+        }
+    }
+
+    #[test]
+    fn only_the_routes_behind_auth_check_are_business_routes() {
+        for path in ["/accounts", "/accounts/7", "/get-business-account", "/transaction/credit", "/webhooks", "/api-keys/1"] {
+            assert!(is_business_route(path), "{}", path);
+        }
+        for path in ["/health", "/_internal/bootstrap/admin", "/admin/businesses", "/demo-webhook-listening", "/accountsx", "/"] {
+            assert!(!is_business_route(path), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn guessing_keys_is_limited_per_ip() {
+        let (state, _rx) = test_state(test_db(5).await, RateLimiter::from_env());
+        let base = serve(crate::app(state.clone()).await).await;
+        let tenant = Tenant::new(&base, &state, "guessed").await;
+        let guesser = Tenant { key: "not-a-key".to_string(), ..Tenant::new(&base, &state, "guesser").await };
+
+        let mut unauthorized = 0;
+        let status = loop {
+            let (status, _) = guesser.call(Method::GET, "/accounts", None).await;
+            if status != StatusCode::UNAUTHORIZED {
+                break status;
+            }
+            unauthorized += 1;
+            assert!(unauthorized <= 1000, "bogus keys are never limited");
+        };
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+        // the IP is turned away before any key is looked up, valid ones included
+        let (status, body) = tenant.call(Method::GET, "/accounts", None).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(body["error_code"], "rate_limited");
+    }
+}
@@ -0,0 +1,363 @@
+use axum::{
+    body::{to_bytes, Body},
+    extract::{OriginalUri, Request, State},
+    http::{HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+use crate::AppState;
+use crate::error::{AppError, ErrorCode};
+use crate::models::common::{AccountId, ApiResponse};
+use crate::models::idempotency_models::{IdempotencyClaim, IdempotencyOwner};
+use crate::services::idempotency_executor::{idempotency_key_ttl_secs, request_fingerprint};
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+// same as the Json extractor's default limit
+const MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+// set on responses carrying a secret that is shown once, such as a new api key
+#[derive(Clone, Copy)]
+pub struct SecretResponse;
+
+pub async fn mark_secret_response(mut response: Response) -> Response {
+    response.extensions_mut().insert(SecretResponse);
+    response
+}
+
+fn idempotency_lock_timeout_secs() -> i64 {
+    std::env::var("IDEMPOTENCY_LOCK_TIMEOUT_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(60)
+}
+
+
+/// Honours `Idempotency-Key` on business POST/PUT/PATCH requests, runs after `auth_check`.
+pub async fn idempotency_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let owner = req
+        .extensions()
+        .get::<AccountId>()
+        .map(|business| IdempotencyOwner::Business(business.account_id));
+    with_idempotency(&state, owner, req, next).await
+}
+
+
+/// Same as `idempotency_middleware` for admin requests, runs after `admin_auth_check`.
+pub async fn admin_idempotency_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let owner = req
+        .extensions()
+        .get::<AccountId>()
+        .map(|admin| IdempotencyOwner::Admin(admin.account_id));
+    with_idempotency(&state, owner, req, next).await
+}
+
+
+/// For `POST /admin/admin-api-keys`, which runs without an admin key while bootstrapping.
+/// Its keys belong to the admin the body names.
+pub async fn admin_key_idempotency_middleware(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let (parts, body) = req.into_parts();
+    let bytes = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(_) => return error_response(ErrorCode::PayloadTooLarge, "Request body is too large"),
+    };
+    let owner = serde_json::from_slice::<i64>(&bytes).ok().map(IdempotencyOwner::Admin);
+    with_idempotency(&state, owner, Request::from_parts(parts, Body::from(bytes)), next).await
+}
+
+
+fn is_mutating(method: &Method) -> bool {
+    method == Method::POST || method == Method::PUT || method == Method::PATCH
+}
+
+
+// bodies are kept as JSON, an empty one as null and anything else as a string
+fn body_value(bytes: &[u8]) -> serde_json::Value {
+    if bytes.is_empty() {
+        return serde_json::Value::Null;
+    }
+    serde_json::from_slice(bytes).unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+
+fn error_response(code: ErrorCode, message: &str) -> Response {
+    AppError::new(code, message).into_response()
+}
+
+
+// The first request with a key takes an in-progress lock on it and runs. A successful response
+// is stored and unlocks the key, a `SecretResponse` is stored as a 409 without its body. Any other response gives the key up so the request can be
+// retried. Repeats of a stored request get its response back, with `Idempotent-Replayed: true`.
+async fn with_idempotency(
+    state: &Arc<AppState>,
+    owner: Option<IdempotencyOwner>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let key = req.headers().get(IDEMPOTENCY_KEY_HEADER).map(|h| h.to_str().map(|k| k.trim().to_string()));
+    let (owner, key) = match (owner, key) {
+        (Some(owner), Some(key)) if is_mutating(req.method()) => (owner, key),
+        _ => return next.run(req).await,
+    };
+    let key = match key {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_IDEMPOTENCY_KEY_LEN => key,
+        _ => {
+            return error_response(
+                ErrorCode::InvalidIdempotencyKey,
+                "Idempotency-Key must be between 1 and 255 visible characters",
+            );
+        }
+    };
+
+    // the body is read here to fingerprint it and handed on to the handler untouched
+    let path = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map(|uri| uri.to_string())
+        .unwrap_or_else(|| req.uri().to_string());
+    let method = req.method().to_string();
+    let (parts, body) = req.into_parts();
+    let bytes = match to_bytes(body, MAX_BODY_BYTES).await {
+        Ok(bytes) => bytes,
+        Err(_) => return error_response(ErrorCode::PayloadTooLarge, "Request body is too large"),
+    };
+    let fingerprint = request_fingerprint("request", &(&method, &path, &body_value(&bytes)));
+
+    let claim = state
+        .database_connector
+        .claim_request_idempotency_key(owner, &key, &fingerprint, idempotency_key_ttl_secs(), idempotency_lock_timeout_secs())
+        .await;
+    match claim {
+        Ok(IdempotencyClaim::Claimed) => {}
+        Ok(IdempotencyClaim::Replay { status, body }) => {
+            let status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+            let mut response = if body.is_null() { status.into_response() } else { (status, Json(body)).into_response() };
+            response.headers_mut().insert(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"));
+            return response;
+        }
+        Ok(IdempotencyClaim::Mismatch) => {
+            return error_response(
+                ErrorCode::IdempotencyKeyReused,
+                "Idempotency key was already used with a different request",
+            );
+        }
+        Ok(IdempotencyClaim::InProgress) => {
+            let mut response = error_response(
+                ErrorCode::IdempotencyKeyInProgress,
+                "A request with this idempotency key is still in progress",
+            );
+            response.headers_mut().insert("Retry-After", HeaderValue::from(1));
+            return response;
+        }
+        Err(e) => {
+            tracing::error!("unable to claim idempotency key {}: {}", key, e);
+            return AppError::from(e).into_response();
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+
+    let (parts, body) = response.into_parts();
+    let bytes = match to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("unable to read the response for idempotency key {}: {}", key, e);
+            if let Err(e) = state.database_connector.release_request_idempotency_key(owner, &key).await {
+                tracing::error!("unable to release idempotency key {}: {}", key, e);
+            }
+            return error_response(ErrorCode::InternalError, "Unable to read the response");
+        }
+    };
+
+    // secrets are never written down, a repeat learns the request went through but not the secret
+    let stored = if parts.status.is_success() && parts.extensions.get::<SecretResponse>().is_some() {
+        let code = ErrorCode::SecretAlreadyShown;
+        let body = serde_json::json!(ApiResponse::<()>::error(
+            code,
+            "The request already succeeded, its response held a secret that is only shown once".into(),
+        ));
+        state
+            .database_connector
+            .complete_request_idempotency_key(owner, &key, code.status().as_u16(), &body)
+            .await
+    } else if parts.status.is_success() {
+        state
+            .database_connector
+            .complete_request_idempotency_key(owner, &key, parts.status.as_u16(), &body_value(&bytes))
+            .await
+    } else {
+        state.database_connector.release_request_idempotency_key(owner, &key).await
+    };
+    // the request already ran, a key that wasn't updated frees itself after the lock timeout
+    if let Err(e) = stored {
+        tracing::error!("unable to record the response for idempotency key {}: {}", key, e);
+    }
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use axum::routing::post;
+    use axum::{middleware, Extension, Router};
+    use serde_json::{json, Value};
+    use tokio::sync::Notify;
+    use crate::middlewares::rate_limit_middleware::RateLimiter;
+    use crate::test_support::{serve, test_db, test_state};
+
+    // what the handlers behind the middleware saw
+    #[derive(Default)]
+    struct Calls {
+        echo: AtomicUsize,
+        flaky: AtomicUsize,
+        slow_started: Notify,
+        slow_release: Notify,
+    }
+
+    // the middleware in front of a few handlers, for a business as `auth_check` would leave it
+    async fn serve_idempotent() -> (String, Arc<Calls>) {
+        let (state, _rx) = test_state(test_db(5).await, RateLimiter::from_env());
+        let business_id = state.database_connector.create_new_business("idempotent").await.unwrap();
+        let calls = Arc::new(Calls::default());
+
+        let echo = calls.clone();
+        let flaky = calls.clone();
+        let slow = calls.clone();
+        let app = Router::new()
+            .route("/echo", post(move |Json(body): Json<Value>| async move {
+                let call = echo.echo.fetch_add(1, Ordering::SeqCst) + 1;
+                (StatusCode::CREATED, Json(json!({ "call": call, "body": body })))
+            }))
+            .route("/flaky", post(move || async move {
+                if flaky.flaky.fetch_add(1, Ordering::SeqCst) == 0 {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                } else {
+                    StatusCode::CREATED
+                }
+            }))
+            .route("/slow", post(move || async move {
+                slow.slow_started.notify_one();
+                slow.slow_release.notified().await;
+                StatusCode::CREATED
+            }))
+            .route("/secret", post(|| async { (StatusCode::CREATED, Json(json!({ "key": "sk_shown_once" }))) })
+                .layer(middleware::map_response(mark_secret_response)))
+            .layer(middleware::from_fn_with_state(state.clone(), idempotency_middleware))
+            .layer(Extension(AccountId { account_id: business_id }))
+            .with_state(state);
+        (serve(app).await, calls)
+    }
+
+    async fn send(base: &str, path: &str, key: &str, body: Value) -> (StatusCode, bool, Value) {
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", base, path))
+            .header(IDEMPOTENCY_KEY_HEADER, key)
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+        let status = response.status();
+        let replayed = response.headers().contains_key(IDEMPOTENT_REPLAYED_HEADER);
+        (status, replayed, response.json().await.unwrap_or(Value::Null))
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_repeat_gets_the_stored_response_and_another_body_gets_422() {
+        let (base, calls) = serve_idempotent().await;
+
+        let first = send(&base, "/echo", "k-replay", json!({ "amount": 1 })).await;
+        assert_eq!((first.0, first.1), (StatusCode::CREATED, false));
+        let repeat = send(&base, "/echo", "k-replay", json!({ "amount": 1 })).await;
+        assert_eq!(repeat, (StatusCode::CREATED, true, first.2));
+        assert_eq!(calls.echo.load(Ordering::SeqCst), 1);
+
+        let (status, _, body) = send(&base, "/echo", "k-replay", json!({ "amount": 2 })).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body["error_code"], "idempotency_key_reused");
+        assert_eq!(calls.echo.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_repeat_while_the_first_runs_gets_409() {
+        let (base, calls) = serve_idempotent().await;
+
+        let started = calls.slow_started.notified();
+        let first = tokio::spawn({
+            let base = base.clone();
+            async move { send(&base, "/slow", "k-slow", Value::Null).await }
+        });
+        started.await;
+
+        let (status, _, body) = send(&base, "/slow", "k-slow", Value::Null).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["error_code"], "idempotency_key_in_progress");
+
+        calls.slow_release.notify_one();
+        assert_eq!(first.await.unwrap().0, StatusCode::CREATED);
+        let (status, replayed, _) = send(&base, "/slow", "k-slow", Value::Null).await;
+        assert_eq!((status, replayed), (StatusCode::CREATED, true));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_failed_request_gives_its_key_up() {
+        let (base, calls) = serve_idempotent().await;
+
+        let (status, _, _) = send(&base, "/flaky", "k-flaky", Value::Null).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        let (status, replayed, _) = send(&base, "/flaky", "k-flaky", Value::Null).await;
+        assert_eq!((status, replayed), (StatusCode::CREATED, false));
+        assert_eq!(calls.flaky.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_secret_is_shown_once_and_repeats_get_409() {
+        let (base, _) = serve_idempotent().await;
+
+        let (status, _, body) = send(&base, "/secret", "k-secret", Value::Null).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["key"], "sk_shown_once");
+
+        let (status, replayed, body) = send(&base, "/secret", "k-secret", Value::Null).await;
+        assert_eq!((status, replayed), (StatusCode::CONFLICT, true));
+        assert_eq!(body["error_code"], "secret_already_shown");
+        assert!(!body.to_string().contains("sk_shown_once"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn admin_key_generation_honours_the_header() {
+        let (state, _rx) = test_state(test_db(5).await, RateLimiter::from_env());
+        let base = serve(crate::app(state.clone()).await).await;
+        let admin_id = state.database_connector.create_admin_account("idempotent").await.unwrap();
+
+        let (status, _, body) = send(&base, "/admin/admin-api-keys", "k-admin", json!(admin_id)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(body["data"].is_string());
+        let (status, replayed, body) = send(&base, "/admin/admin-api-keys", "k-admin", json!(admin_id)).await;
+        assert_eq!((status, replayed), (StatusCode::CONFLICT, true));
+        assert_eq!(body["error_code"], "secret_already_shown");
+    }
+}
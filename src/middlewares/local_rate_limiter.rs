@@ -0,0 +1,203 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+use lru::LruCache;
+use crate::middlewares::rate_limit_middleware::RateLimitDecision;
+use crate::models::rate_limit_models::RateLimitBudget;
+
+const SHARDS: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+struct LocalBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl LocalBucket {
+    fn refilled(&self, budget: RateLimitBudget, now: Instant) -> f64 {
+        let elapsed_ms = now.saturating_duration_since(self.updated_at).as_secs_f64() * 1000.0;
+        (self.tokens + elapsed_ms * per_ms(budget)).min(budget.burst as f64)
+    }
+}
+
+fn per_ms(budget: RateLimitBudget) -> f64 {
+    budget.per_minute as f64 / 60_000.0
+}
+
+/// In-process token buckets with the same semantics as the Redis script, used while Redis is
+/// unreachable. Each instance only sees its own traffic, so limits hold per instance.
+/// Buckets are spread over mutex-guarded shards and each shard keeps at most
+/// `max_buckets / SHARDS` of them. A full shard forgets its least recently used bucket, which
+/// only ever resets that bucket to full, so it never blocks a key.
+pub struct LocalRateLimiter {
+    shards: Vec<Mutex<LruCache<String, LocalBucket>>>,
+}
+
+impl LocalRateLimiter {
+    pub fn new(max_buckets: usize) -> Self {
+        let max_per_shard = NonZeroUsize::new(max_buckets / SHARDS).unwrap_or(NonZeroUsize::MIN);
+        LocalRateLimiter {
+            shards: (0..SHARDS).map(|_| Mutex::new(LruCache::new(max_per_shard))).collect(),
+        }
+    }
+
+    fn shard_of(key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() as usize) % SHARDS
+    }
+
+    pub fn take_token(&self, buckets: &[(String, RateLimitBudget)]) -> RateLimitDecision {
+        self.take_token_at(buckets, Instant::now())
+    }
+
+    // the decision `take_token` would make, without changing any bucket
+    pub fn peek(&self, buckets: &[(String, RateLimitBudget)]) -> RateLimitDecision {
+        self.decide_at(buckets, Instant::now(), false)
+    }
+
+    pub(crate) fn take_token_at(&self, buckets: &[(String, RateLimitBudget)], now: Instant) -> RateLimitDecision {
+        self.decide_at(buckets, now, true)
+    }
+
+    fn decide_at(&self, buckets: &[(String, RateLimitBudget)], now: Instant, take: bool) -> RateLimitDecision {
+        // every shard involved is held for the whole decision, taken in index order
+        let mut shard_ids: Vec<usize> = buckets.iter().map(|(key, _)| Self::shard_of(key)).collect();
+        shard_ids.sort_unstable();
+        shard_ids.dedup();
+        let mut guards: Vec<(usize, MutexGuard<'_, LruCache<String, LocalBucket>>)> = shard_ids
+            .into_iter()
+            .map(|id| (id, self.shards[id].lock().unwrap_or_else(|e| e.into_inner())))
+            .collect();
+
+        let mut tokens: Vec<f64> = Vec::with_capacity(buckets.len());
+        for (key, budget) in buckets {
+            let shard = Self::shard_of(key);
+            let (_, guard) = guards.iter_mut().find(|(id, _)| *id == shard).expect("shard is locked");
+            // taking marks the buckets as used, so making room below never forgets one of them
+            let bucket = if take { guard.get(key) } else { guard.peek(key) };
+            let available = bucket
+                .map(|bucket| bucket.refilled(*budget, now))
+                .unwrap_or(budget.burst as f64);
+            tokens.push(available);
+        }
+        let allowed = tokens.iter().all(|t| *t >= 1.0);
+
+        let mut tightest = 0;
+        for (i, (key, _)) in buckets.iter().enumerate() {
+            if take {
+                if allowed {
+                    tokens[i] -= 1.0;
+                }
+                let shard = Self::shard_of(key);
+                let (_, guard) = guards.iter_mut().find(|(id, _)| *id == shard).expect("shard is locked");
+                guard.put(key.clone(), LocalBucket { tokens: tokens[i], updated_at: now });
+            }
+            if tokens[i] < tokens[tightest] {
+                tightest = i;
+            }
+        }
+
+        let budget = buckets[tightest].1;
+        let left = tokens[tightest];
+        let retry_after_ms = if left < 1.0 { ((1.0 - left) / per_ms(budget)).ceil() as i64 } else { 0 };
+        RateLimitDecision {
+            allowed,
+            limit: budget.burst as i64,
+            remaining: left.floor() as i64,
+            retry_after_ms,
+            reset_ms: ((budget.burst as f64 - left) / per_ms(budget)).ceil() as i64,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(|e| e.into_inner()).len())
+            .sum()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn budget(burst: i32, per_minute: i32) -> RateLimitBudget {
+        RateLimitBudget { burst, per_minute }
+    }
+
+    #[test]
+    fn bucket_refills_with_time() {
+        let limiter = LocalRateLimiter::new(64);
+        let buckets = vec![("refill".to_string(), budget(2, 60))];
+        let start = Instant::now();
+
+        assert!(limiter.take_token_at(&buckets, start).allowed);
+        assert!(limiter.take_token_at(&buckets, start).allowed);
+        assert!(!limiter.take_token_at(&buckets, start).allowed);
+
+        // 60 a minute is one a second
+        assert!(limiter.take_token_at(&buckets, start + Duration::from_secs(1)).allowed);
+        assert!(!limiter.take_token_at(&buckets, start + Duration::from_secs(1)).allowed);
+    }
+
+    #[test]
+    fn a_request_takes_from_every_bucket_or_from_none() {
+        let limiter = LocalRateLimiter::new(64);
+        let now = Instant::now();
+        let business = ("business".to_string(), budget(3, 1));
+        let key = ("key".to_string(), budget(1, 1));
+
+        assert!(limiter.take_token_at(&[business.clone(), key.clone()], now).allowed);
+        let rejected = limiter.take_token_at(&[business.clone(), key.clone()], now);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.limit, 1);
+
+        // the rejected request left the business bucket alone
+        let decision = limiter.take_token_at(&[business], now);
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 1);
+    }
+
+    #[test]
+    fn peeking_takes_no_token() {
+        let limiter = LocalRateLimiter::new(64);
+        let buckets = vec![("peek".to_string(), budget(1, 1))];
+
+        assert!(limiter.peek(&buckets).allowed);
+        assert!(limiter.peek(&buckets).allowed);
+        assert_eq!(limiter.len(), 0);
+        assert!(limiter.take_token(&buckets).allowed);
+        assert!(!limiter.peek(&buckets).allowed);
+    }
+
+    #[test]
+    fn memory_stays_bounded_under_many_keys() {
+        let limiter = LocalRateLimiter::new(SHARDS * 4);
+        let now = Instant::now();
+
+        for i in 0..10_000 {
+            limiter.take_token_at(&[(format!("ip-{}", i), budget(5, 5))], now);
+        }
+        assert!(limiter.len() <= SHARDS * 4);
+    }
+
+    #[test]
+    fn a_bucket_in_use_outlives_a_flood_of_new_keys() {
+        let limiter = LocalRateLimiter::new(SHARDS * 4);
+        let start = Instant::now();
+        let busy = vec![("busy".to_string(), budget(1, 1))];
+        assert!(limiter.take_token_at(&busy, start).allowed);
+
+        for i in 0..10_000 {
+            let now = start + Duration::from_millis(i);
+            limiter.take_token_at(&[(format!("ip-{}", i), budget(5, 5))], now);
+            assert!(!limiter.take_token_at(&busy, now).allowed, "forgotten after {} keys", i);
+        }
+    }
+}
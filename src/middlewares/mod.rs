@@ -1,2 +1,4 @@
 pub mod authentication_middleware;
-pub mod rate_limit_middleware;
\ No newline at end of file
+pub mod rate_limit_middleware;
+pub mod local_rate_limiter;
+pub mod idempotency_middleware;
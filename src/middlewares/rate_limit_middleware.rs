@@ -1,12 +1,432 @@
 use axum::{
     extract::{Request, State, ConnectInfo},
-    http::StatusCode,
+    http::{HeaderMap, HeaderValue, Method},
     middleware::Next,
     response::{Response, IntoResponse},
 };
-use std::{net::SocketAddr, sync::Arc};
-use redis::AsyncCommands;
+use std::{net::{IpAddr, SocketAddr}, sync::{Arc, LazyLock}};
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+use chrono::Utc;
 use crate::AppState;
+use crate::error::{AppError, ErrorCode};
+use crate::middlewares::authentication_middleware::is_business_route;
+use crate::middlewares::local_rate_limiter::LocalRateLimiter;
+use crate::models::bussiness_models::VerifiedApiKey;
+use crate::models::rate_limit_models::{RateLimitBudget, RouteClass};
+
+// Token buckets for every key in KEYS, refilled from the elapsed time since they were last
+// touched. A request takes one token from each bucket or, if any is empty, from none of them.
+// ARGV holds a burst and a per-minute refill for each key, in KEYS order, then 1 to take the
+// token or 0 to only look at the buckets without touching them.
+// Returns {allowed, remaining, limit, retry_after_ms, reset_ms} of the emptiest bucket.
+const TOKEN_BUCKET_SCRIPT: &str = r#"
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local take = tonumber(ARGV[2 * #KEYS + 1]) == 1
+local tokens = {}
+local allowed = 1
+for i = 1, #KEYS do
+    local burst = tonumber(ARGV[2 * i - 1])
+    local per_ms = tonumber(ARGV[2 * i]) / 60000
+    local bucket = redis.call('HMGET', KEYS[i], 'tokens', 'ts')
+    local available = tonumber(bucket[1])
+    local ts = tonumber(bucket[2])
+    if available == nil or ts == nil then
+        available = burst
+        ts = now
+    end
+    available = math.min(burst, available + math.max(0, now - ts) * per_ms)
+    tokens[i] = available
+    if available < 1 then
+        allowed = 0
+    end
+end
+
+local tightest = 1
+for i = 1, #KEYS do
+    local burst = tonumber(ARGV[2 * i - 1])
+    local per_ms = tonumber(ARGV[2 * i]) / 60000
+    if take then
+        if allowed == 1 then
+            tokens[i] = tokens[i] - 1
+        end
+        redis.call('HSET', KEYS[i], 'tokens', tostring(tokens[i]), 'ts', now)
+        redis.call('PEXPIRE', KEYS[i], math.ceil(burst / per_ms) + 1000)
+    end
+    if tokens[i] < tokens[tightest] then
+        tightest = i
+    end
+end
+
+local burst = tonumber(ARGV[2 * tightest - 1])
+local per_ms = tonumber(ARGV[2 * tightest]) / 60000
+local left = tokens[tightest]
+local retry_after_ms = 0
+if left < 1 then
+    retry_after_ms = math.ceil((1 - left) / per_ms)
+end
+local reset_ms = math.ceil((burst - left) / per_ms)
+return {allowed, math.floor(left), burst, retry_after_ms, reset_ms}
+"#;
+
+// hashed once, runs through EVALSHA and loads itself again after a Redis restart
+static TOKEN_BUCKET: LazyLock<redis::Script> = LazyLock::new(|| redis::Script::new(TOKEN_BUCKET_SCRIPT));
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: i64,
+    pub remaining: i64,
+    pub retry_after_ms: i64,
+    pub reset_ms: i64,
+}
+
+impl RateLimitDecision {
+    pub fn apply_headers(&self, headers: &mut HeaderMap) {
+        headers.insert("RateLimit-Limit", HeaderValue::from(self.limit));
+        headers.insert("RateLimit-Remaining", HeaderValue::from(self.remaining.max(0)));
+        headers.insert("RateLimit-Reset", HeaderValue::from(ms_to_secs(self.reset_ms)));
+        if !self.allowed {
+            headers.insert("Retry-After", HeaderValue::from(ms_to_secs(self.retry_after_ms)));
+        }
+    }
+
+    pub fn rejection(&self) -> Response {
+        let mut response = AppError::new(ErrorCode::RateLimited, "Rate limit exceeded").into_response();
+        self.apply_headers(response.headers_mut());
+        response
+    }
+}
+
+fn ms_to_secs(ms: i64) -> i64 {
+    (ms.max(0) + 999) / 1000
+}
+
+pub fn route_class(method: &Method) -> RouteClass {
+    if method == Method::GET || method == Method::HEAD {
+        RouteClass::Read
+    } else {
+        RouteClass::Write
+    }
+}
+
+fn env_budget(burst_var: &str, per_minute_var: &str, default: RateLimitBudget) -> RateLimitBudget {
+    let read = |var: &str, fallback: i32| {
+        std::env::var(var)
+            .ok()
+            .and_then(|v| v.parse::<i32>().ok())
+            .filter(|v| *v > 0)
+            .unwrap_or(fallback)
+    };
+    RateLimitBudget {
+        burst: read(burst_var, default.burst),
+        per_minute: read(per_minute_var, default.per_minute),
+    }
+}
+
+// budgets for a business and each of its keys unless the database overrides them
+pub fn default_budget(class: RouteClass) -> RateLimitBudget {
+    match class {
+        RouteClass::Read => env_budget(
+            "RATE_LIMIT_READ_BURST",
+            "RATE_LIMIT_READ_PER_MINUTE",
+            RateLimitBudget { burst: 120, per_minute: 600 },
+        ),
+        RouteClass::Write => env_budget(
+            "RATE_LIMIT_WRITE_BURST",
+            "RATE_LIMIT_WRITE_PER_MINUTE",
+            RateLimitBudget { burst: 60, per_minute: 300 },
+        ),
+    }
+}
+
+// budget of requests that carry no business identity, counted per client IP
+fn anonymous_budget() -> RateLimitBudget {
+    env_budget(
+        "RATE_LIMIT_ANONYMOUS_BURST",
+        "RATE_LIMIT_ANONYMOUS_PER_MINUTE",
+        RateLimitBudget { burst: 20, per_minute: 20 },
+    )
+}
+
+/// Takes one token from every bucket in a single atomic script run, or with `take` false
+/// only reports whether it could.
+pub async fn take_token(
+    redis_client: &redis::Client,
+    buckets: &[(String, RateLimitBudget)],
+    take: bool,
+) -> redis::RedisResult<RateLimitDecision> {
+    let mut conn = redis_client.get_multiplexed_async_connection().await?;
+
+    let mut invocation = TOKEN_BUCKET.prepare_invoke();
+    for (key, budget) in buckets {
+        invocation.key(key).arg(budget.burst).arg(budget.per_minute);
+    }
+    invocation.arg(take as i32);
+    let (allowed, remaining, limit, retry_after_ms, reset_ms): (i64, i64, i64, i64, i64) =
+        invocation.invoke_async(&mut conn).await?;
+
+    Ok(RateLimitDecision {
+        allowed: allowed == 1,
+        limit,
+        remaining,
+        retry_after_ms,
+        reset_ms,
+    })
+}
+
+// what to do with a request while Redis can't be reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitFallback {
+    Open,   // let it through unlimited
+    Closed, // reject it with 503
+    Local,  // limit it with in-process buckets
+}
+
+impl RateLimitFallback {
+    // unset means in-process. a value that isn't a mode stops the server from starting rather
+    // than quietly limiting it some other way
+    pub fn from_env() -> Self {
+        let value = std::env::var("RATE_LIMIT_FALLBACK").unwrap_or_default();
+        if value.trim().is_empty() {
+            return RateLimitFallback::Local;
+        }
+        RateLimitFallback::parse(&value).unwrap_or_else(|| {
+            panic!("RATE_LIMIT_FALLBACK must be open, closed or local (or fail-open, fail-closed, in-process), got {:?}", value)
+        })
+    }
+
+    // the short names and the ones `as_str` prints
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "open" | "fail-open" => Some(RateLimitFallback::Open),
+            "closed" | "fail-closed" => Some(RateLimitFallback::Closed),
+            "local" | "in-process" => Some(RateLimitFallback::Local),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitFallback::Open => "fail-open",
+            RateLimitFallback::Closed => "fail-closed",
+            RateLimitFallback::Local => "in-process",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitOutcome {
+    Limited(RateLimitDecision),
+    Unlimited,
+    Unavailable,
+}
+
+/// Runs token buckets in Redis and falls back to the configured mode while Redis is
+/// unreachable. After a failure Redis is left alone for `retry_after` so an outage doesn't
+/// cost every request a connection attempt; switching modes is logged both ways.
+pub struct RateLimiter {
+    fallback: RateLimitFallback,
+    local: LocalRateLimiter,
+    redis_timeout: Duration,
+    retry_after: Duration,
+    redis_down_until_ms: AtomicI64,
+    degraded: AtomicBool,
+}
+
+impl RateLimiter {
+    pub fn new(fallback: RateLimitFallback, max_local_buckets: usize, redis_timeout: Duration, retry_after: Duration) -> Self {
+        RateLimiter {
+            fallback,
+            local: LocalRateLimiter::new(max_local_buckets),
+            redis_timeout,
+            retry_after,
+            redis_down_until_ms: AtomicI64::new(0),
+            degraded: AtomicBool::new(false),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let read = |var: &str, fallback: u64| {
+            std::env::var(var)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .unwrap_or(fallback)
+        };
+        let limiter = RateLimiter::new(
+            RateLimitFallback::from_env(),
+            read("RATE_LIMIT_LOCAL_MAX_BUCKETS", 100_000) as usize,
+            Duration::from_millis(read("RATE_LIMIT_REDIS_TIMEOUT_MS", 100)),
+            Duration::from_secs(read("RATE_LIMIT_REDIS_RETRY_SECONDS", 5)),
+        );
+        tracing::info!("rate limiter falls back to {} while Redis is unreachable", limiter.fallback.as_str());
+        limiter
+    }
+
+    /// The mode requests are limited in right now: `redis`, or the fallback while Redis is down.
+    pub fn active_mode(&self) -> &'static str {
+        if self.degraded.load(Ordering::Relaxed) {
+            self.fallback.as_str()
+        } else {
+            "redis"
+        }
+    }
+
+    pub async fn check(
+        &self,
+        redis_client: &redis::Client,
+        buckets: &[(String, RateLimitBudget)],
+    ) -> RateLimitOutcome {
+        self.run(redis_client, buckets, true).await
+    }
+
+    /// Like `check`, but leaves the buckets as they are.
+    pub async fn peek(
+        &self,
+        redis_client: &redis::Client,
+        buckets: &[(String, RateLimitBudget)],
+    ) -> RateLimitOutcome {
+        self.run(redis_client, buckets, false).await
+    }
+
+    async fn run(
+        &self,
+        redis_client: &redis::Client,
+        buckets: &[(String, RateLimitBudget)],
+        take: bool,
+    ) -> RateLimitOutcome {
+        let now_ms = Utc::now().timestamp_millis();
+        if now_ms >= self.redis_down_until_ms.load(Ordering::Relaxed) {
+            match tokio::time::timeout(self.redis_timeout, take_token(redis_client, buckets, take)).await {
+                Ok(Ok(decision)) => {
+                    if self.degraded.swap(false, Ordering::Relaxed) {
+                        tracing::warn!("Redis is reachable again, rate limiter back in redis mode");
+                    }
+                    return RateLimitOutcome::Limited(decision);
+                }
+                Ok(Err(e)) => self.mark_redis_down(now_ms, &e.to_string()),
+                Err(_) => self.mark_redis_down(now_ms, "timed out"),
+            }
+        }
+
+        match self.fallback {
+            RateLimitFallback::Open => RateLimitOutcome::Unlimited,
+            RateLimitFallback::Closed => RateLimitOutcome::Unavailable,
+            RateLimitFallback::Local => RateLimitOutcome::Limited(if take {
+                self.local.take_token(buckets)
+            } else {
+                self.local.peek(buckets)
+            }),
+        }
+    }
+
+    fn mark_redis_down(&self, now_ms: i64, reason: &str) {
+        self.redis_down_until_ms
+            .store(now_ms + self.retry_after.as_millis() as i64, Ordering::Relaxed);
+        if !self.degraded.swap(true, Ordering::Relaxed) {
+            tracing::error!("Redis unreachable ({}), rate limiter switched to {} mode", reason, self.fallback.as_str());
+        } else {
+            tracing::warn!("Redis still unreachable ({}), rate limiter stays in {} mode", reason, self.fallback.as_str());
+        }
+    }
+}
+
+// the answer to a request the outcome doesn't let through
+fn rejection(outcome: RateLimitOutcome) -> Option<Response> {
+    match outcome {
+        RateLimitOutcome::Limited(decision) if !decision.allowed => Some(decision.rejection()),
+        RateLimitOutcome::Limited(_) | RateLimitOutcome::Unlimited => None,
+        RateLimitOutcome::Unavailable => {
+            let mut response = AppError::new(ErrorCode::RateLimiterUnavailable, "Rate limiter unavailable").into_response();
+            response.headers_mut().insert("Retry-After", HeaderValue::from(1));
+            Some(response)
+        }
+    }
+}
+
+// runs the request if the outcome allows it, with the limit headers when there are any
+async fn apply_outcome(outcome: RateLimitOutcome, req: Request, next: Next) -> Response {
+    if let Some(response) = rejection(outcome) {
+        return response;
+    }
+    let mut response = next.run(req).await;
+    if let RateLimitOutcome::Limited(decision) = outcome {
+        decision.apply_headers(response.headers_mut());
+    }
+    response
+}
+
+// the business bucket and the key bucket of the request's class, key overrides win over business ones
+fn tenant_buckets(key: &VerifiedApiKey, class: RouteClass) -> Vec<(String, RateLimitBudget)> {
+    let business_budget = key
+        .business_rate_limits
+        .map(|limits| limits.budget(class))
+        .unwrap_or_else(|| default_budget(class));
+    let key_budget = key
+        .key_rate_limits
+        .map(|limits| limits.budget(class))
+        .unwrap_or(business_budget);
+    vec![
+        (format!("rate_limit:business:{}:{}", key.business_id, class.as_str()), business_budget),
+        (format!("rate_limit:key:{}:{}", key.key_id, class.as_str()), key_budget),
+    ]
+}
+
+/// Runs an authenticated business request against its business and api key budgets.
+/// Called from `auth_check` once the key is known.
+pub async fn limit_tenant_request(
+    state: &Arc<AppState>,
+    key: &VerifiedApiKey,
+    req: Request,
+    next: Next,
+) -> Response {
+    let class = route_class(req.method());
+    let outcome = state.rate_limiter.check(&state.redis_client, &tenant_buckets(key, class)).await;
+
+    if let RateLimitOutcome::Limited(decision) = outcome
+        && !decision.allowed
+    {
+        tracing::warn!("rate limit exceeded for business {} key {} ({})", key.business_id, key.key_id, class.as_str());
+    }
+    apply_outcome(outcome, req, next).await
+}
+
+// failed business authentications of a client IP, on the anonymous budget
+fn failed_auth_buckets(ip: IpAddr) -> [(String, RateLimitBudget); 1] {
+    [(format!("rate_limit:ip:{}:failed_auth", ip), anonymous_budget())]
+}
+
+/// The 429 for a client IP whose failed authentications used up its budget, checked before
+/// the key is looked up so guessing keys can't keep the database busy. Called from `auth_check`.
+pub async fn failed_auth_rejection(state: &Arc<AppState>, ip: IpAddr) -> Option<Response> {
+    let outcome = state.rate_limiter.peek(&state.redis_client, &failed_auth_buckets(ip)).await;
+    rejection(outcome)
+}
+
+/// Charges a failed authentication to the client IP and answers with `unauthorized`, or with
+/// the 429 once that used the budget up. Called from `auth_check`.
+pub async fn charge_failed_auth(state: &Arc<AppState>, ip: IpAddr, unauthorized: Response) -> Response {
+    let outcome = state.rate_limiter.check(&state.redis_client, &failed_auth_buckets(ip)).await;
+    if let RateLimitOutcome::Limited(decision) = outcome
+        && !decision.allowed
+    {
+        tracing::warn!("too many failed authentications from IP: {}", ip);
+    }
+    rejection(outcome).unwrap_or(unauthorized)
+}
+
+// requests with a key to a business route are limited by `auth_check`, per tenant once the key
+// is verified and per IP when it isn't. everything else is limited per IP here
+fn is_tenant_request(req: &Request) -> bool {
+    let has_bearer = req
+        .headers()
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|v| v.starts_with("Bearer "));
+    has_bearer && is_business_route(req.uri().path())
+}
 
 pub async fn rate_limit_middleware(
     State(state): State<Arc<AppState>>,
@@ -14,42 +434,183 @@ pub async fn rate_limit_middleware(
     req: Request,
     next: Next,
 ) -> Response {
+    if is_tenant_request(&req) {
+        return next.run(req).await;
+    }
+
     let ip = addr.ip().to_string();
-    let key = format!("rate_limit:{}", ip);
-    
-    // Get redis connection
-    let mut redis_conn = match state.redis_client.get_multiplexed_async_connection().await {
-        Ok(conn) => conn,
-        Err(e) => {
-            tracing::error!("Failed to get redis connection: {}", e);
-            // Fail open
-            return next.run(req).await;
+    let buckets = [(format!("rate_limit:ip:{}", ip), anonymous_budget())];
+
+    let outcome = state.rate_limiter.check(&state.redis_client, &buckets).await;
+
+    if let RateLimitOutcome::Limited(decision) = outcome
+        && !decision.allowed
+    {
+        tracing::warn!("Rate limit exceeded for IP: {}", ip);
+    }
+    apply_outcome(outcome, req, next).await
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use axum::http::StatusCode;
+    use axum::routing::get;
+    use axum::Router;
+    use sqlx::postgres::PgPoolOptions;
+    use tokio::net::TcpListener;
+    use crate::services::db_operations::DbOperations;
+    use crate::test_support::{test_state, unreachable_redis};
+
+    // accepts connections and never answers, like a Redis stuck behind a dead network path
+    async fn hanging_redis() -> redis::Client {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                open.push(socket);
+            }
+        });
+        redis::Client::open(format!("redis://127.0.0.1:{}/", port)).unwrap()
+    }
+
+    #[test]
+    fn fallback_modes_parse_by_either_name() {
+        for fallback in [RateLimitFallback::Open, RateLimitFallback::Closed, RateLimitFallback::Local] {
+            assert_eq!(RateLimitFallback::parse(fallback.as_str()), Some(fallback));
         }
-    };
+        assert_eq!(RateLimitFallback::parse("open"), Some(RateLimitFallback::Open));
+        assert_eq!(RateLimitFallback::parse(" Closed "), Some(RateLimitFallback::Closed));
+        assert_eq!(RateLimitFallback::parse("local"), Some(RateLimitFallback::Local));
+        assert_eq!(RateLimitFallback::parse("fail_closed"), None);
+        assert_eq!(RateLimitFallback::parse("yes"), None);
+    }
+
+    fn limiter(fallback: RateLimitFallback) -> RateLimiter {
+        RateLimiter::new(fallback, 1024, Duration::from_millis(200), Duration::from_secs(5))
+    }
 
-    // Increment counter
-    let count: isize = match redis_conn.incr(&key, 1).await {
-        Ok(v) => v,
-        Err(e) => {
-            tracing::error!("Redis INCR error: {}", e);
-            return next.run(req).await;
+    fn bucket(key: &str, burst: i32) -> Vec<(String, RateLimitBudget)> {
+        vec![(key.to_string(), RateLimitBudget { burst, per_minute: 1 })]
+    }
+
+    #[tokio::test]
+    async fn fail_open_lets_requests_through_while_redis_is_down() {
+        let limiter = limiter(RateLimitFallback::Open);
+        assert_eq!(limiter.active_mode(), "redis");
+
+        for _ in 0..5 {
+            let outcome = limiter.check(&unreachable_redis(), &bucket("open", 1)).await;
+            assert_eq!(outcome, RateLimitOutcome::Unlimited);
         }
-    };
+        assert_eq!(limiter.active_mode(), "fail-open");
+    }
+
+    #[tokio::test]
+    async fn fail_closed_rejects_requests_while_redis_is_down() {
+        let limiter = limiter(RateLimitFallback::Closed);
+
+        let outcome = limiter.check(&unreachable_redis(), &bucket("closed", 10)).await;
+        assert_eq!(outcome, RateLimitOutcome::Unavailable);
+        assert_eq!(limiter.active_mode(), "fail-closed");
+    }
 
-    // If new key (count == 1), set expiry
-    if count == 1 {
-        // Set expiry to 60 seconds
-        let result: redis::RedisResult<()> = redis_conn.expire(&key, 60).await;
-        if let Err(e) = result {
-             tracing::error!("Redis EXPIRE error: {}", e);
+    #[tokio::test]
+    async fn in_process_fallback_enforces_the_budget_while_redis_is_down() {
+        let limiter = limiter(RateLimitFallback::Local);
+        let redis = unreachable_redis();
+
+        for expected_remaining in [2, 1, 0] {
+            match limiter.check(&redis, &bucket("local", 3)).await {
+                RateLimitOutcome::Limited(decision) => {
+                    assert!(decision.allowed);
+                    assert_eq!(decision.limit, 3);
+                    assert_eq!(decision.remaining, expected_remaining);
+                }
+                other => panic!("expected a local decision, got {:?}", other),
+            }
+        }
+        match limiter.check(&redis, &bucket("local", 3)).await {
+            RateLimitOutcome::Limited(decision) => {
+                assert!(!decision.allowed);
+                assert!(decision.retry_after_ms > 0);
+            }
+            other => panic!("expected a local decision, got {:?}", other),
         }
+        // other tenants keep their own budget
+        assert!(matches!(
+            limiter.check(&redis, &bucket("someone-else", 3)).await,
+            RateLimitOutcome::Limited(RateLimitDecision { allowed: true, .. })
+        ));
+        assert_eq!(limiter.active_mode(), "in-process");
     }
 
-    // Check limit
-    if count > 20 {
-        tracing::warn!("Rate limit exceeded for IP: {}", ip);
-        return (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded").into_response();
+    #[tokio::test]
+    async fn hanging_redis_times_out_and_is_skipped_until_the_retry_window_ends() {
+        let limiter = limiter(RateLimitFallback::Local);
+        let redis = hanging_redis().await;
+
+        let started = Instant::now();
+        assert!(matches!(limiter.check(&redis, &bucket("slow", 5)).await, RateLimitOutcome::Limited(_)));
+        assert!(started.elapsed() >= Duration::from_millis(200));
+
+        // Redis is known to be down, no time is spent waiting on it again
+        let started = Instant::now();
+        assert!(matches!(limiter.check(&redis, &bucket("slow", 5)).await, RateLimitOutcome::Limited(_)));
+        assert!(started.elapsed() < Duration::from_millis(100));
     }
 
-    next.run(req).await
+    async fn serve(fallback: RateLimitFallback) -> String {
+        let database_connector = DbOperations {
+            connector: PgPoolOptions::new().connect_lazy("postgres://127.0.0.1/unused").unwrap(),
+        };
+        let (state, _rx) = test_state(database_connector, limiter(fallback));
+        let app = Router::new()
+            .route("/health", get(|| async { "OK" }))
+            .with_state(state.clone())
+            .layer(axum::middleware::from_fn_with_state(state, rate_limit_middleware));
+        format!("{}/health", crate::test_support::serve(app).await)
+    }
+
+    #[tokio::test]
+    async fn fail_closed_answers_503_while_redis_is_down() {
+        let url = serve(RateLimitFallback::Closed).await;
+
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "1");
+    }
+
+    #[tokio::test]
+    async fn in_process_fallback_answers_429_with_limit_headers_while_redis_is_down() {
+        let url = serve(RateLimitFallback::Local).await;
+        let burst = anonymous_budget().burst;
+
+        for _ in 0..burst {
+            let response = reqwest::get(&url).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()["ratelimit-limit"], burst.to_string().as_str());
+        }
+        let response = reqwest::get(&url).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["ratelimit-remaining"], "0");
+        assert!(response.headers().contains_key("retry-after"));
+    }
+
+    #[tokio::test]
+    async fn a_bearer_token_does_not_lift_the_ip_limit_off_public_routes() {
+        let url = serve(RateLimitFallback::Local).await;
+        let client = reqwest::Client::new();
+        let burst = anonymous_budget().burst;
+
+        for _ in 0..burst {
+            let response = client.get(&url).bearer_auth("anything").send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = client.get(&url).bearer_auth("anything").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
 }
@@ -6,14 +6,127 @@ use rust_decimal::Decimal;
 pub struct Account {
     pub id: i64,
     pub name: String,
-    pub balance: Decimal,
+    pub balance: Decimal, // ledger balance
+    pub held_balance: Decimal,
+    pub available_balance: Decimal, // ledger balance minus active holds
+    pub overdraft_limit: Decimal,
+    pub allow_negative: bool,
     pub currency: String,
     pub status: String,
+    pub account_type: String,
     pub created_at: DateTime<Utc>
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NewAccount {
     pub name: String,
-    pub currency: String
-}
\ No newline at end of file
+    pub currency: String,
+    #[serde(default)]
+    pub overdraft_limit: Decimal,
+    #[serde(default)]
+    pub allow_negative: bool
+}
+
+// statuses an account can be moved to, a closed account is final
+pub const ACCOUNT_STATUSES: [&str; 3] = ["active", "frozen", "closed"];
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateAccountRequest {
+    pub name: Option<String>,
+    pub status: Option<String>,
+    pub reason: Option<String>, // required when renaming or changing status
+    pub overdraft_limit: Option<Decimal>,
+    pub allow_negative: Option<bool>,
+}
+
+// audit record of a status change, `actor_type` is api_key, admin or system
+#[derive(Debug, Serialize)]
+pub struct AccountStatusChange {
+    pub id: i64,
+    pub account_id: i64,
+    pub from_status: String,
+    pub to_status: String,
+    pub reason: String,
+    pub actor_type: String,
+    pub actor_id: Option<i64>,
+    pub created_at: DateTime<Utc>
+}
+
+// row state read under `SELECT ... FOR UPDATE` while moving money
+#[derive(Debug)]
+pub struct LockedAccount {
+    pub balance: Decimal,
+    pub held_balance: Decimal,
+    pub overdraft_limit: Decimal,
+    pub allow_negative: bool,
+    pub status: String,
+    pub currency: String,
+    pub minor_units: u32, // decimal places of the currency
+}
+
+impl LockedAccount {
+    // what can still be spent, holds reserve money without moving it
+    pub fn available_balance(&self) -> Decimal {
+        self.balance - self.held_balance
+    }
+
+    // whether `amount` can leave the account without breaking its negative-balance policy
+    pub fn can_spend(&self, amount: Decimal) -> bool {
+        self.allow_negative || self.available_balance() - amount >= -self.overdraft_limit
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountBalance {
+    pub ledger_balance: Decimal,
+    pub held_balance: Decimal,
+    pub available_balance: Decimal
+}
+
+#[derive(Debug, Serialize)]
+pub struct LedgerEntry {
+    pub id: i64,
+    pub transaction_id: i64,
+    pub account_id: i64,
+    pub direction: String,
+    pub amount: Decimal,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountReconciliation {
+    pub account_id: i64,
+    pub balance: Decimal,
+    pub ledger_balance: Decimal,
+    pub in_balance: bool
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BalanceQuery {
+    pub as_of: Option<DateTime<Utc>>, // balance as of this instant, current balance when absent
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountHistoryQuery {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+}
+
+// one movement on the account, `running_balance` is the balance right after it
+#[derive(Debug, Serialize)]
+pub struct AccountHistoryEntry {
+    pub entry_id: i64,
+    pub transaction_id: i64,
+    pub txn_type: String,
+    pub reference_id: Option<String>,
+    pub direction: String,
+    pub amount: Decimal,
+    pub running_balance: Decimal,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountHistoryPage {
+    pub entries: Vec<AccountHistoryEntry>,
+    pub next_cursor: Option<String>,
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use crate::models::rate_limit_models::RateLimits;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Business {
@@ -12,4 +13,131 @@ pub struct BusinessState {
     pub name: String,
     pub status: String,
     pub created_at: DateTime<Utc>
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BusinessStatusRequest {
+    pub reason: String
+}
+
+// granting this scope grants every scope
+pub const ALL_API_KEY_SCOPES: &str = "*";
+
+// scopes a business api key can carry, see `required_scope` for the routes each one opens
+pub const API_KEY_SCOPES: [&str; 6] = [
+    "accounts:read",
+    "accounts:write",
+    "transactions:read",
+    "transactions:write",
+    "webhooks:manage",
+    "api_keys:read",
+];
+
+pub fn validate_scopes(scopes: &[String]) -> Result<(), String> {
+    if scopes.is_empty() {
+        return Err("at least one scope is required".to_string());
+    }
+    match scopes
+        .iter()
+        .find(|s| s.as_str() != ALL_API_KEY_SCOPES && !API_KEY_SCOPES.contains(&s.as_str()))
+    {
+        Some(unknown) => Err(format!("unknown scope {}", unknown)),
+        None => Ok(()),
+    }
+}
+
+// the bare business id creates a key with every scope
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum NewApiKeyRequest {
+    BusinessId(i64),
+    Scoped {
+        business_id: i64,
+        scopes: Vec<String>
+    },
+}
+
+// how long a rotated key keeps working when the rotation doesn't say, and the most it may ask for
+pub const DEFAULT_ROTATION_GRACE_SECONDS: i64 = 7 * 24 * 60 * 60;
+pub const MAX_ROTATION_GRACE_SECONDS: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum RotateApiKeyRequest {
+    KeyAndBusiness(i64, i64),
+    WithGracePeriod {
+        key_id: i64,
+        business_id: i64,
+        grace_period_seconds: Option<i64>
+    },
+}
+
+// a rotated key read under `SELECT ... FOR UPDATE` by the expiry sweeper
+#[derive(Debug)]
+pub struct LockedApiKey {
+    pub id: i64,
+    pub business_id: i64,
+    pub key_prefix: Option<String>,
+    pub status: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub expiry_warned_at: Option<DateTime<Utc>>
+}
+
+// an active, unexpired business api key and the state of the business that owns it
+#[derive(Debug)]
+pub struct VerifiedApiKey {
+    pub key_id: i64,
+    pub business_id: i64,
+    pub business_status: String,
+    pub scopes: Vec<String>,
+    pub business_rate_limits: Option<RateLimits>,
+    pub key_rate_limits: Option<RateLimits>
+}
+
+impl VerifiedApiKey {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == ALL_API_KEY_SCOPES || s == scope)
+    }
+}
+
+// what an api key listing shows, never the key or its hash
+#[derive(Debug, Serialize)]
+pub struct ApiKeyInfo {
+    pub id: i64,
+    pub business_id: i64,
+    pub prefix: Option<String>, // unknown for keys issued before prefixes were stored
+    pub status: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scopes(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn known_scopes_and_the_wildcard_are_valid() {
+        assert!(validate_scopes(&scopes(&API_KEY_SCOPES)).is_ok());
+        assert!(validate_scopes(&scopes(&["accounts:read"])).is_ok());
+        assert!(validate_scopes(&scopes(&[ALL_API_KEY_SCOPES])).is_ok());
+    }
+
+    #[test]
+    fn unknown_or_missing_scopes_are_rejected() {
+        assert_eq!(validate_scopes(&[]), Err("at least one scope is required".to_string()));
+        assert_eq!(
+            validate_scopes(&scopes(&["accounts:read", "accounts:delete"])),
+            Err("unknown scope accounts:delete".to_string())
+        );
+        // scopes are matched exactly
+        assert!(validate_scopes(&scopes(&["Accounts:Read"])).is_err());
+        assert!(validate_scopes(&scopes(&["accounts:*"])).is_err());
+    }
+}
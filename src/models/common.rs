@@ -1,10 +1,14 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use crate::error::ErrorCode;
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    // responses stored before error codes existed have none
+    #[serde(default)]
+    pub error_code: Option<String>,
 }
 
 impl<T> ApiResponse<T> {
@@ -13,14 +17,16 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            error_code: None,
         }
     }
 
-    pub fn error(message: String) -> Self {
+    pub fn error(code: ErrorCode, message: String) -> Self {
         Self {
             success: false,
             data: None,
             error: Some(message),
+            error_code: Some(code.as_str().to_string()),
         }
     }
 }
@@ -29,4 +35,10 @@ impl<T> ApiResponse<T> {
 #[derive(Clone)]
 pub struct AccountId {
     pub account_id: i64
-}
\ No newline at end of file
+}
+
+// the business api key that authenticated the request, recorded as the actor of audited changes
+#[derive(Clone)]
+pub struct ApiKeyId {
+    pub key_id: i64
+}
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// 1 base_currency = rate quote_currency inside [valid_from, valid_to)
+#[derive(Debug, Serialize)]
+pub struct FxRate {
+    pub id: i64,
+    pub base_currency: String,
+    pub quote_currency: String,
+    pub rate: Decimal,
+    pub valid_from: DateTime<Utc>,
+    pub valid_to: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewFxRate {
+    pub base_currency: String,
+    pub quote_currency: String,
+    pub rate: Decimal,
+    pub valid_from: Option<DateTime<Utc>>, // defaults to now
+    pub valid_to: Option<DateTime<Utc>>, // open ended when absent
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FxQuoteRequest {
+    pub from_currency: String,
+    pub to_currency: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FxQuote {
+    pub id: i64,
+    pub from_currency: String,
+    pub to_currency: String,
+    pub rate: Decimal,
+    pub expires_at: DateTime<Utc>
+}
+
+// quote read under `SELECT ... FOR UPDATE` by the transfer consuming it
+#[derive(Debug)]
+pub struct LockedFxQuote {
+    pub id: i64,
+    pub from_currency: String,
+    pub to_currency: String,
+    pub rate: Decimal,
+    pub expires_at: DateTime<Utc>,
+    pub transaction_id: Option<i64>,
+}
@@ -0,0 +1,45 @@
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize)]
+pub struct Hold {
+    pub id: i64,
+    pub account_id: i64,
+    pub amount: Decimal,
+    pub currency: String,
+    pub status: String,
+    pub captured_amount: Option<Decimal>,
+    pub capture_transaction_id: Option<i64>,
+    pub reference_id: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HoldRequest {
+    pub account_id: i64,
+    pub amount: Decimal,
+    pub currency: String, // must match the account's currency
+    pub expires_at: Option<DateTime<Utc>>, // defaults to HOLD_DEFAULT_TTL_SECONDS from now
+    pub reference_id: Option<String>,
+    pub idempotency_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CaptureRequest {
+    pub amount: Option<Decimal>, // defaults to the full hold, the rest of a partial capture is released
+    pub reference_id: Option<String>,
+    pub idempotency_key: String,
+}
+
+// hold read under `SELECT ... FOR UPDATE`, always after its account is locked
+#[derive(Debug)]
+pub struct LockedHold {
+    pub id: i64,
+    pub amount: Decimal,
+    pub status: String,
+    pub expires_at: DateTime<Utc>,
+    pub reference_id: Option<String>,
+}
@@ -0,0 +1,36 @@
+
+// idempotency keys of different endpoint families live side by side
+pub const IDEMPOTENCY_SCOPE_TRANSACTIONS: &str = "transactions";
+pub const IDEMPOTENCY_SCOPE_HOLDS: &str = "holds";
+// keys sent in the Idempotency-Key header, whatever the endpoint
+pub const IDEMPOTENCY_SCOPE_REQUESTS: &str = "requests";
+
+// who a header key belongs to, business keys and admin keys never collide
+#[derive(Debug, Clone, Copy)]
+pub enum IdempotencyOwner {
+    Business(i64),
+    Admin(i64),
+}
+
+impl IdempotencyOwner {
+    // the idempotency_keys column holding the owner and its id
+    pub fn column(&self) -> (&'static str, i64) {
+        match self {
+            IdempotencyOwner::Business(id) => ("business_id", *id),
+            IdempotencyOwner::Admin(id) => ("admin_id", *id),
+        }
+    }
+}
+
+// what claiming an idempotency key inside the request's transaction turned up
+#[derive(Debug)]
+pub enum IdempotencyClaim {
+    // first use of the key, or its previous use expired: run the request
+    Claimed,
+    // same request seen before, answer with what it got back then
+    Replay { status: u16, body: serde_json::Value },
+    // the key was used for a different request
+    Mismatch,
+    // the first request with the key hasn't finished yet
+    InProgress,
+}
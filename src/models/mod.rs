@@ -5,4 +5,8 @@ pub mod transaction_models;
 pub mod bussiness_models;
 pub mod common;
 pub mod webhooks_models;
-pub mod event_queue;
\ No newline at end of file
+pub mod event_queue;
+pub mod fx_models;
+pub mod holds_models;
+pub mod rate_limit_models;
+pub mod idempotency_models;
@@ -0,0 +1,59 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+// a token bucket: holds at most `burst` requests and refills `per_minute` of them every minute
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitBudget {
+    pub burst: i32,
+    pub per_minute: i32
+}
+
+impl RateLimitBudget {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.burst < 1 || self.per_minute < 1 {
+            return Err("burst and per_minute must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+// reads are GET/HEAD, everything else spends the write budget
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RouteClass {
+    Read,
+    Write,
+}
+
+impl RouteClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteClass::Read => "read",
+            RouteClass::Write => "write",
+        }
+    }
+}
+
+// per-business or per-key replacement of the default budgets
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RateLimits {
+    pub read: RateLimitBudget,
+    pub write: RateLimitBudget
+}
+
+impl RateLimits {
+    pub fn budget(&self, class: RouteClass) -> RateLimitBudget {
+        match class {
+            RouteClass::Read => self.read,
+            RouteClass::Write => self.write,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RateLimitOverride {
+    pub business_id: i64,
+    pub api_key_id: Option<i64>, // None for the business-wide override
+    pub read: RateLimitBudget,
+    pub write: RateLimitBudget,
+    pub updated_at: DateTime<Utc>
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use rust_decimal::Decimal;
 use chrono::{DateTime, Utc};
+use crate::error::ErrorCode;
 
 
 #[derive(Debug, Serialize)]
@@ -11,40 +12,137 @@ pub struct Transaction {
     pub to_account_id: Option<i64>,
     pub txn_type: String,
     pub amount: Decimal,
+    pub currency: String,
+    // set on cross-currency transfers, `amount` / `currency` are then the source side
+    pub destination_amount: Option<Decimal>,
+    pub destination_currency: Option<String>,
+    pub fx_rate: Option<Decimal>,
+    pub fx_quote_id: Option<i64>,
+    // quoted transfers only, in the destination currency: positive when the quote paid out less than the rate at the time
+    pub fx_gain_loss: Option<Decimal>,
     pub status: String,
     pub reference_id: Option<String>,
     pub idempotency_key: String,
+    pub reversal_of: Option<i64>,
+    // set on failed transactions only
+    pub failure_code: Option<String>,
+    pub failure_reason: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct CreditRequest {
     pub to_account_id: i64,
     pub amount: Decimal,
+    pub currency: String, // must match the account's currency
     pub reference_id: Option<String>,
     pub idempotency_key: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct DebitRequest {
     pub from_account_id: i64,
     pub amount: Decimal,
+    pub currency: String, // must match the account's currency
     pub reference_id: Option<String>,
     pub idempotency_key: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TransferRequest {
     pub from_account_id: i64,
     pub to_account_id: i64,
-    pub amount: Decimal,
+    pub amount: Decimal, // in the sender's currency
+    pub currency: String, // must match the sender's currency
+    pub quote_id: Option<i64>, // cross-currency only, uses the current fx rate when absent
+    pub reference_id: Option<String>,
+    pub idempotency_key: String,
+}
+
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReverseRequest {
+    pub amount: Option<Decimal>, // defaults to everything not reversed yet
     pub reference_id: Option<String>,
     pub idempotency_key: String,
 }
 
+// a rejected credit, debit or transfer: the amount and currency as requested
+#[derive(Debug)]
+pub struct FailedTransaction<'a> {
+    pub from_account_id: Option<i64>,
+    pub to_account_id: Option<i64>,
+    pub txn_type: TransactionType,
+    pub amount: Decimal,
+    pub currency: &'a str,
+    pub reference_id: Option<String>,
+    pub idempotency_key: &'a str,
+    // why it was rejected, kept on the transaction and sent with `transaction.failed`
+    pub failure_code: ErrorCode,
+    pub failure_reason: String,
+}
+
+// original transaction read under `SELECT ... FOR UPDATE` while reversing it
+#[derive(Debug)]
+pub struct LockedTransaction {
+    pub id: i64,
+    pub from_account_id: Option<i64>,
+    pub to_account_id: Option<i64>,
+    pub txn_type: TransactionType,
+    pub amount: Decimal,
+    pub currency: String,
+    pub status: TransactionStatus,
+}
+
+
+// query string of GET /transaction, every filter is optional
+#[derive(Debug, Deserialize)]
+pub struct TransactionFilters {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub account_id: Option<i64>,
+    #[serde(rename = "type")]
+    pub txn_type: Option<TransactionType>,
+    pub status: Option<TransactionStatus>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    pub created_from: Option<DateTime<Utc>>,
+    pub created_to: Option<DateTime<Utc>>,
+    pub reference_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TransactionPage {
+    pub transactions: Vec<Transaction>,
+    pub next_cursor: Option<String>, // None on the last page
+}
+
+// amount rescaled to the currency's minor units, None when it is not positive or
+// carries more decimal places than the currency has (e.g. 1.5 JPY)
+pub fn to_currency_precision(amount: Decimal, minor_units: u32) -> Option<Decimal> {
+    if amount <= Decimal::ZERO || amount.normalize().scale() > minor_units {
+        return None;
+    }
+    let mut amount = amount;
+    amount.rescale(minor_units);
+    Some(amount)
+}
+
+// cursors are "<created_at micros>_<id>" of the last row of a page
+pub fn encode_cursor(created_at: DateTime<Utc>, id: i64) -> String {
+    format!("{}_{}", created_at.timestamp_micros(), id)
+}
+
+pub fn decode_cursor(cursor: &str) -> Option<(DateTime<Utc>, i64)> {
+    let (micros, id) = cursor.split_once('_')?;
+    let created_at = DateTime::from_timestamp_micros(micros.parse().ok()?)?;
+    Some((created_at, id.parse().ok()?))
+}
+
 
-#[derive(Debug, Clone, Copy, sqlx::Type, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, sqlx::Type, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[sqlx(type_name = "transaction_status_enum", rename_all = "snake_case")]
 pub enum TransactionStatus {
     Pending,
@@ -52,10 +150,12 @@ pub enum TransactionStatus {
     Failed,
 }
 
-#[derive(Debug, Clone, Copy, sqlx::Type, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, sqlx::Type, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[sqlx(type_name = "transaction_type_enum", rename_all = "snake_case")]
 pub enum TransactionType {
     Credit,
     Debit,
     Transfer,
+    Reversal,
 }
@@ -1,14 +1,45 @@
 use serde::{Deserialize, Serialize};
 
+// subscribing to this receives every event type
+pub const ALL_WEBHOOK_EVENTS: &str = "*";
+
+// event types an endpoint can subscribe to
+pub const WEBHOOK_EVENT_TYPES: [&str; 9] = [
+    "transaction.succeeded",
+    "transaction.failed",
+    "transaction.reversed",
+    "account.frozen",
+    "account.status_changed",
+    "account.negative_balance",
+    "account.positive_balance",
+    "api_key.expiring_soon",
+    "api_key.expired",
+];
+
+pub fn validate_event_types(event_types: &[String]) -> Result<(), String> {
+    if event_types.is_empty() {
+        return Err("at least one event type is required".to_string());
+    }
+    match event_types
+        .iter()
+        .find(|e| e.as_str() != ALL_WEBHOOK_EVENTS && !WEBHOOK_EVENT_TYPES.contains(&e.as_str()))
+    {
+        Some(unknown) => Err(format!("unknown event type {}", unknown)),
+        None => Ok(()),
+    }
+}
+
 #[derive(Deserialize)]
 pub struct CreateWebhookRequest {
     pub url: String,
+    pub event_types: Option<Vec<String>>, // defaults to every event
 }
 
 #[derive(Deserialize)]
 pub struct UpdateWebhookRequest {
     pub url: Option<String>,
     pub status: Option<String>, // active | disabled
+    pub event_types: Option<Vec<String>>,
 }
 
 #[derive(Serialize)]
@@ -17,6 +48,7 @@ pub struct WebhookResponse {
     pub url: String,
     pub business_id: i64,
     pub status: String,
+    pub event_types: Vec<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
@@ -35,6 +67,16 @@ pub struct WebhookEventRow {
     pub created_at: DateTime<Utc>,
 }
 
+#[derive(Debug)]
+pub struct DomainEventRow {
+    pub id: i64,
+    pub business_id: i64,
+    pub event_type: String,
+    pub payload: serde_json::Value,
+    pub dispatched_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
 #[derive(Debug)]
 pub struct WebhookRow {
     pub id: i64,
@@ -42,5 +84,6 @@ pub struct WebhookRow {
     pub url: String,
     pub secret: String,
     pub status: String,           // active | disabled
+    pub business_status: String,  // active | suspended
 }
 
@@ -1,14 +1,20 @@
 use std::sync::Arc;
 use axum::{middleware, Router};
-use axum::routing::{get, post};
+use axum::routing::{get, patch, post};
 use crate::AppState;
-use crate::controllers::accounts_controllers::{create_account, get_account_balance, get_account_details, get_accounts};
+use crate::controllers::accounts_controllers::{create_account, get_account_balance, get_account_details, get_account_ledger_entries, get_account_transactions, get_account_status_history, get_accounts, reconcile_account, update_account};
 
 pub async fn accounts_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/", get(get_accounts))
         .route("/{account_id}", get(get_account_details))
+        .route("/{account_id}", patch(update_account))
         .route("/{account_id}/balance", get(get_account_balance))
+        .route("/{account_id}/ledger-entries", get(get_account_ledger_entries))
+        .route("/{account_id}/transactions", get(get_account_transactions))
+        .route("/{account_id}/reconcile", get(reconcile_account))
+        .route("/{account_id}/status-history", get(get_account_status_history))
         .route("/", post(create_account))
+        .layer(middleware::from_fn_with_state(app_state.clone(), crate::middlewares::idempotency_middleware::idempotency_middleware))
         .layer(middleware::from_fn_with_state(app_state, crate::middlewares::authentication_middleware::auth_check))
 }
\ No newline at end of file
@@ -1,18 +1,32 @@
 use std::sync::Arc;
 use axum::{middleware, Router};
-use axum::routing::{delete, get, post};
+use axum::routing::{delete, get, post, put};
 use crate::AppState;
-use crate::controllers::admin_controllers::{create_business, generate_admin_api_keys, generate_api_keys, get_businesses, revoke_admin_api_key, revoke_api_key, rotate_api_key};
+use crate::middlewares::idempotency_middleware::{admin_key_idempotency_middleware, mark_secret_response};
+use crate::controllers::admin_controllers::{create_business, create_fx_rate, generate_admin_api_keys, generate_api_keys, get_api_key, get_business_api_keys, get_businesses, get_fx_rates, get_rate_limits, reinstate_business, revoke_admin_api_key, revoke_api_key, rotate_api_key, set_api_key_rate_limits, set_business_rate_limits, suspend_business};
 
 pub async fn admin_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/businesses", post(create_business
         ))
         .route("/businesses", get(get_businesses))
-        .route("/businesses/api-keys", post(generate_api_keys))
-        .route("/api-keys/{key_id}/{business_id}/rotate", post(rotate_api_key))
+        .route("/businesses/api-keys", post(generate_api_keys).layer(middleware::map_response(mark_secret_response)))
+        .route("/businesses/{business_id}/api-keys", get(get_business_api_keys))
+        .route("/businesses/{business_id}/rate-limits", get(get_rate_limits))
+        .route("/businesses/{business_id}/rate-limits", put(set_business_rate_limits))
+        .route("/businesses/{business_id}/suspend", post(suspend_business))
+        .route("/businesses/{business_id}/reinstate", post(reinstate_business))
+        .route("/api-keys/{key_id}/{business_id}/rotate", post(rotate_api_key).layer(middleware::map_response(mark_secret_response)))
+        .route("/api-keys/{key_id}", get(get_api_key))
         .route("/api-keys/{key_id}", delete(revoke_api_key))
+        .route("/api-keys/{key_id}/rate-limits", put(set_api_key_rate_limits))
         .route("/admin-api-keys/{key_id}", delete(revoke_admin_api_key))
-        .layer(middleware::from_fn_with_state(app_state, crate::middlewares::authentication_middleware::admin_auth_check))
-        .route("/admin-api-keys", post(generate_admin_api_keys))
+        .route("/fx-rates", post(create_fx_rate))
+        .route("/fx-rates", get(get_fx_rates))
+        .layer(middleware::from_fn_with_state(app_state.clone(), crate::middlewares::idempotency_middleware::admin_idempotency_middleware))
+        .layer(middleware::from_fn_with_state(app_state.clone(), crate::middlewares::authentication_middleware::admin_auth_check))
+        // the bootstrap step that creates the first admin key, so it has no admin key to check
+        .route("/admin-api-keys", post(generate_admin_api_keys)
+            .layer(middleware::map_response(mark_secret_response))
+            .layer(middleware::from_fn_with_state(app_state.clone(), admin_key_idempotency_middleware)))
 }
\ No newline at end of file
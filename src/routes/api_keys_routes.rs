@@ -0,0 +1,12 @@
+use std::sync::Arc;
+use axum::{middleware, Router};
+use axum::routing::get;
+use crate::AppState;
+use crate::controllers::business_controllers::{get_own_api_key, get_own_api_keys};
+
+pub async fn api_keys_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/", get(get_own_api_keys))
+        .route("/{key_id}", get(get_own_api_key))
+        .layer(middleware::from_fn_with_state(state.clone(), crate::middlewares::authentication_middleware::auth_check))
+}
@@ -1,4 +1,108 @@
 pub mod accounts_routes;
 pub mod admin_routes;
+pub mod api_keys_routes;
 pub mod transaction_routes;
-pub mod webhooks_routes;
\ No newline at end of file
+pub mod webhooks_routes;
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+    use crate::middlewares::rate_limit_middleware::RateLimiter;
+    use crate::test_support::{serve, test_db, test_state, Tenant};
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn a_business_cannot_reach_another_business_through_any_endpoint() {
+        let (state, _rx) = test_state(test_db(10).await, RateLimiter::from_env());
+        let base = serve(crate::app(state.clone()).await).await;
+        let owner = Tenant::new(&base, &state, "owner").await;
+        let intruder = Tenant::new(&base, &state, "intruder").await;
+
+        let account = |name: &str| json!({ "name": name, "currency": "USD" });
+        let wallet = owner.ok(Method::POST, "/accounts", Some(account("wallet"))).await;
+        let savings = owner.ok(Method::POST, "/accounts", Some(account("savings"))).await;
+        owner.ok(Method::POST, "/transaction/credit", Some(json!({
+            "to_account_id": wallet, "amount": "100.00", "currency": "USD", "idempotency_key": "fund"
+        }))).await;
+        let transfer = owner.ok(Method::POST, "/transaction/transfer", Some(json!({
+            "from_account_id": wallet, "to_account_id": savings, "amount": "5.00", "currency": "USD", "idempotency_key": "move"
+        }))).await;
+        let hold = owner.ok(Method::POST, "/transaction/holds", Some(json!({
+            "account_id": wallet, "amount": "10.00", "currency": "USD", "idempotency_key": "hold"
+        }))).await;
+        let webhook = owner.ok(Method::POST, "/webhooks", Some(json!({ "url": "http://127.0.0.1:1/hook" }))).await;
+        let owner_key = owner.ok(Method::GET, "/api-keys", None).await[0]["id"].clone();
+
+        let own_account = intruder.ok(Method::POST, "/accounts", Some(account("own"))).await;
+        intruder.ok(Method::POST, "/transaction/credit", Some(json!({
+            "to_account_id": own_account, "amount": "100.00", "currency": "USD", "idempotency_key": "fund"
+        }))).await;
+
+        let before = [owner.balance(wallet.as_i64().unwrap()).await, owner.balance(savings.as_i64().unwrap()).await];
+        let own_before = intruder.balance(own_account.as_i64().unwrap()).await;
+
+        let attempts = [
+            (Method::GET, format!("/accounts/{}", wallet), None),
+            (Method::PATCH, format!("/accounts/{}", wallet), Some(json!({ "status": "frozen", "reason": "mine now" }))),
+            (Method::GET, format!("/accounts/{}/balance", wallet), None),
+            (Method::GET, format!("/accounts/{}/ledger-entries", wallet), None),
+            (Method::GET, format!("/accounts/{}/transactions", wallet), None),
+            (Method::GET, format!("/accounts/{}/reconcile", wallet), None),
+            (Method::GET, format!("/accounts/{}/status-history", wallet), None),
+            (Method::POST, "/transaction/credit".to_string(), Some(json!({
+                "to_account_id": wallet, "amount": "1.00", "currency": "USD", "idempotency_key": "x-credit"
+            }))),
+            (Method::POST, "/transaction/debit".to_string(), Some(json!({
+                "from_account_id": wallet, "amount": "1.00", "currency": "USD", "idempotency_key": "x-debit"
+            }))),
+            (Method::POST, "/transaction/transfer".to_string(), Some(json!({
+                "from_account_id": wallet, "to_account_id": own_account, "amount": "1.00", "currency": "USD", "idempotency_key": "x-pull"
+            }))),
+            (Method::POST, "/transaction/transfer".to_string(), Some(json!({
+                "from_account_id": own_account, "to_account_id": wallet, "amount": "1.00", "currency": "USD", "idempotency_key": "x-push"
+            }))),
+            (Method::POST, "/transaction/holds".to_string(), Some(json!({
+                "account_id": wallet, "amount": "1.00", "currency": "USD", "idempotency_key": "x-hold"
+            }))),
+            (Method::GET, format!("/transaction/holds/{}", hold), None),
+            (Method::POST, format!("/transaction/holds/{}/capture", hold), Some(json!({ "idempotency_key": "x-capture" }))),
+            (Method::POST, format!("/transaction/holds/{}/void", hold), None),
+            (Method::GET, format!("/transaction/{}", transfer), None),
+            (Method::POST, format!("/transaction/{}/reverse", transfer), Some(json!({ "idempotency_key": "x-reverse" }))),
+            (Method::PUT, format!("/webhooks/{}", webhook), Some(json!({ "url": "http://127.0.0.1:1/stolen" }))),
+            (Method::GET, format!("/webhooks/{}/secret", webhook), None),
+            (Method::POST, format!("/webhooks/{}/secret/rotate", webhook), None),
+            (Method::DELETE, format!("/webhooks/{}", webhook), None),
+            (Method::GET, format!("/api-keys/{}", owner_key), None),
+        ];
+        for (method, path, body) in attempts {
+            let (status, body) = intruder.call(method.clone(), &path, body).await;
+            assert!(
+                status == StatusCode::NOT_FOUND || status == StatusCode::FORBIDDEN,
+                "{} {} answered {}: {}", method, path, status, body
+            );
+            assert_eq!(body["success"], false, "{} {}", method, path);
+        }
+
+        // listings only ever show the caller's own resources
+        let accounts = intruder.ok(Method::GET, "/accounts", None).await;
+        assert!(accounts.as_array().unwrap().iter().all(|a| a["id"] != wallet && a["id"] != savings));
+        let transactions = intruder.ok(Method::GET, "/transaction", None).await;
+        assert!(transactions["transactions"].as_array().unwrap().iter().all(|t| t["id"] != transfer));
+        let webhooks = intruder.ok(Method::GET, "/webhooks", None).await;
+        assert!(webhooks.as_array().unwrap().iter().all(|w| w["id"] != webhook));
+
+        // nothing of the owner moved or changed
+        let after = [owner.balance(wallet.as_i64().unwrap()).await, owner.balance(savings.as_i64().unwrap()).await];
+        assert_eq!(before, after);
+        assert_eq!(own_before, intruder.balance(own_account.as_i64().unwrap()).await);
+        let wallet_details = owner.ok(Method::GET, &format!("/accounts/{}", wallet), None).await;
+        assert_eq!(wallet_details["status"], "active");
+        let hold_details = owner.ok(Method::GET, &format!("/transaction/holds/{}", hold), None).await;
+        assert_eq!(hold_details["status"], "active");
+        let owner_webhooks = owner.ok(Method::GET, "/webhooks", None).await;
+        assert_eq!(owner_webhooks[0]["url"], "http://127.0.0.1:1/hook");
+        assert_eq!(owner_webhooks[0]["status"], "active");
+    }
+}
@@ -2,14 +2,22 @@ use std::sync::Arc;
 use axum::{middleware, Router};
 use axum::routing::{get, post};
 use crate::AppState;
-use crate::controllers::transaction_controllers::{credit_money, debit_money, get_all_transactions, get_transaction_details, transfer_money};
+use crate::controllers::holds_controllers::{capture_hold, create_hold, get_hold, void_hold};
+use crate::controllers::transaction_controllers::{create_fx_quote, credit_money, debit_money, get_all_transactions, get_transaction_details, reverse_transaction, transfer_money};
 
 pub async fn transaction_routes(app_state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
         .route("/credit", post(credit_money))
         .route("/debit", post(debit_money))
         .route("/transfer", post(transfer_money))
+        .route("/fx-quotes", post(create_fx_quote))
+        .route("/holds", post(create_hold))
+        .route("/holds/{hold_id}", get(get_hold))
+        .route("/holds/{hold_id}/capture", post(capture_hold))
+        .route("/holds/{hold_id}/void", post(void_hold))
         .route("/", get(get_all_transactions))
         .route("/{transaction_id}", get(get_transaction_details))
+        .route("/{transaction_id}/reverse", post(reverse_transaction))
+        .layer(middleware::from_fn_with_state(app_state.clone(), crate::middlewares::idempotency_middleware::idempotency_middleware))
         .layer(middleware::from_fn_with_state(app_state, crate::middlewares::authentication_middleware::auth_check))
 }
\ No newline at end of file
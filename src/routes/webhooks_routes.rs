@@ -2,7 +2,8 @@ use std::sync::Arc;
 use axum::{middleware, Router};
 use axum::routing::{delete, get, post, put};
 use crate::AppState;
-use crate::controllers::webhooks_controllers::{delete_webhook, get_webhooks, register_webhook, update_webhook};
+use crate::middlewares::idempotency_middleware::mark_secret_response;
+use crate::controllers::webhooks_controllers::{delete_webhook, get_webhook_secret, get_webhooks, register_webhook, rotate_webhook_secret, update_webhook};
 
 pub async fn webhook_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
     Router::new()
@@ -10,5 +11,8 @@ pub async fn webhook_routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
         .route("/", post(register_webhook))
         .route("/{webhook_id}", delete(delete_webhook))
         .route("/{webhook_id}", put(update_webhook))
+        .route("/{webhook_id}/secret", get(get_webhook_secret))
+        .route("/{webhook_id}/secret/rotate", post(rotate_webhook_secret).layer(middleware::map_response(mark_secret_response)))
+        .layer(middleware::from_fn_with_state(state.clone(), crate::middlewares::idempotency_middleware::idempotency_middleware))
         .layer(middleware::from_fn_with_state(state.clone(), crate::middlewares::authentication_middleware::auth_check))
 }
\ No newline at end of file
@@ -0,0 +1,105 @@
+use std::sync::Arc;
+use chrono::{Duration, Utc};
+use tracing::{error, info};
+use crate::AppState;
+use crate::models::bussiness_models::LockedApiKey;
+use crate::services::db_operations::DbOperations;
+
+
+fn api_key_expiry_warning_secs() -> i64 {
+    std::env::var("API_KEY_EXPIRY_WARNING_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .unwrap_or(24 * 60 * 60)
+}
+
+
+/// Sends `api_key.expiring_soon` for a rotated key, once, if it is still in its grace period.
+pub async fn warn_api_key_expiry(app_state: &Arc<AppState>, key_id: i64) -> Result<(), sqlx::Error> {
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    let key = DbOperations::lock_api_key(&mut tx, key_id).await?;
+    if key.status != "expiring" || key.expiry_warned_at.is_some() {
+        return Ok(());
+    }
+
+    DbOperations::mark_api_key_warned(&mut tx, key.id).await?;
+    publish_api_key_event(app_state, tx, &key, "api_key.expiring_soon").await?;
+    info!("business {} warned that api key {} is expiring", key.business_id, key.id);
+    Ok(())
+}
+
+
+/// Moves a rotated key past its grace period to `revoked` and sends `api_key.expired`.
+pub async fn retire_api_key(app_state: &Arc<AppState>, key_id: i64) -> Result<(), sqlx::Error> {
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    let key = DbOperations::lock_api_key(&mut tx, key_id).await?;
+    if key.status != "expiring" || key.expires_at.is_none_or(|expires_at| expires_at > Utc::now()) {
+        return Ok(());
+    }
+
+    DbOperations::retire_api_key(&mut tx, key.id).await?;
+    publish_api_key_event(app_state, tx, &key, "api_key.expired").await?;
+    info!("api key {} of business {} retired", key.id, key.business_id);
+    Ok(())
+}
+
+
+async fn publish_api_key_event(
+    app_state: &Arc<AppState>,
+    mut tx: sqlx::Transaction<'_, sqlx::Postgres>,
+    key: &LockedApiKey,
+    event_type: &str,
+) -> Result<(), sqlx::Error> {
+    let payload = serde_json::json!({
+        "event": event_type,
+        "data": {
+            "key_id": key.id,
+            "business_id": key.business_id,
+            "prefix": key.key_prefix,
+            "expires_at": key.expires_at
+        }
+    });
+    let queue_messages = app_state
+        .database_connector
+        .publish_domain_event_txn(&mut tx, key.business_id, event_type, payload)
+        .await?;
+
+    tx.commit().await?;
+
+    for message in queue_messages {
+        let _ = app_state.event_queue.send(message);
+    }
+    Ok(())
+}
+
+
+pub async fn api_key_expiry_sweeper(app_state: Arc<AppState>) {
+    info!("API key expiry sweeper started");
+    loop {
+        let warn_before = Utc::now() + Duration::seconds(api_key_expiry_warning_secs());
+        match app_state.database_connector.get_api_keys_due_for_warning(warn_before).await {
+            Ok(keys) => {
+                for key_id in keys {
+                    if let Err(e) = warn_api_key_expiry(&app_state, key_id).await {
+                        error!("failed to warn about expiring api key {}: {}", key_id, e);
+                    }
+                }
+            }
+            Err(e) => error!("db error loading expiring api keys: {}", e),
+        }
+
+        match app_state.database_connector.get_expired_api_keys().await {
+            Ok(keys) => {
+                for key_id in keys {
+                    if let Err(e) = retire_api_key(&app_state, key_id).await {
+                        error!("failed to retire api key {}: {}", key_id, e);
+                    }
+                }
+            }
+            Err(e) => error!("db error loading expired api keys: {}", e),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+    }
+}
@@ -1,12 +1,19 @@
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
-use sqlx::{Pool, Postgres, Row, Transaction};
+use sqlx::{Pool, Postgres, QueryBuilder, Row, Transaction};
+use sqlx::postgres::PgRow;
 use sqlx::postgres::PgPoolOptions;
-use crate::models;
-use crate::models::accounts_models::{Account, NewAccount};
-use crate::models::bussiness_models::BusinessState;
-use crate::models::transaction_models::{TransactionStatus, TransactionType};
-use crate::models::webhooks_models::{WebhookEventRow, WebhookResponse, WebhookRow};
+use crate::models::accounts_models::{Account, AccountBalance, AccountHistoryEntry, AccountHistoryPage, AccountReconciliation, AccountStatusChange, LedgerEntry, LockedAccount, NewAccount};
+use crate::models::bussiness_models::{ApiKeyInfo, BusinessState, LockedApiKey, VerifiedApiKey};
+use crate::models::event_queue::WebhookQueueMessage;
+use crate::models::holds_models::{Hold, LockedHold};
+use crate::models::idempotency_models::{IdempotencyClaim, IdempotencyOwner, IDEMPOTENCY_SCOPE_REQUESTS};
+use crate::models::rate_limit_models::{RateLimitBudget, RateLimitOverride, RateLimits};
+use crate::models::fx_models::{FxQuote, FxRate, LockedFxQuote, NewFxRate};
+use crate::models::transaction_models;
+use crate::models::transaction_models::{encode_cursor, FailedTransaction, LockedTransaction, TransactionFilters, TransactionPage, TransactionStatus, TransactionType};
+use crate::models::webhooks_models::{DomainEventRow, WebhookEventRow, WebhookResponse, WebhookRow};
+use crate::services::db_scope::apply_db_scope;
 
 pub struct DbOperations {
     pub(crate) connector: Pool<Postgres>
@@ -20,7 +27,7 @@ impl DbOperations {
         let db_name = std::env::var("DB_NAME").unwrap();
         let url = format!("postgres://{}:{}@{}/{}", username, password, host, db_name);
         let max_connections = std::env::var("MAX_CONNECTIONS").unwrap().parse::<u32>().unwrap();
-        let pool = PgPoolOptions::new().max_connections(max_connections).connect(&url).await.unwrap() ;
+        let pool = Self::pool_options().max_connections(max_connections).connect(&url).await.unwrap() ;
         
         tracing::info!("Running database migrations...");
         sqlx::migrate!("./migrations")
@@ -34,6 +41,13 @@ impl DbOperations {
         }
     }
 
+    // every connection is switched to the role and tenant of the request using it
+    pub fn pool_options() -> PgPoolOptions {
+        PgPoolOptions::new()
+            .after_connect(|conn, _| Box::pin(apply_db_scope(conn)))
+            .before_acquire(|conn, _| Box::pin(async move { apply_db_scope(conn).await.map(|_| true) }))
+    }
+
     pub async fn get_admin_count(&self) -> Result<i64, sqlx::Error> {
         let result = sqlx::query("select count(*) from admins").fetch_one(&self.connector).await ;
         match result {
@@ -153,12 +167,57 @@ impl DbOperations {
     }
 
 
-    pub async fn store_api_key(&self, business_id: i64, key_hash: &str) -> Result<(), sqlx::Error> {
-        let result = sqlx::query("insert into api_keys (business_id, key_hash) values ($1, $2)")
-            .bind(business_id) .bind(key_hash) .execute(&self.connector).await ;
+    // status of the business, locked until the caller's transaction ends
+    pub async fn lock_business_status(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let row = sqlx::query("SELECT status::TEXT FROM businesses WHERE id = $1 FOR UPDATE")
+            .bind(business_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+        Ok(row.map(|r| r.get("status")))
+    }
+
+
+    pub async fn update_business_status(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        from_status: &str,
+        to_status: &str,
+        reason: &str,
+        admin_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE businesses SET status = $2::business_status_enum WHERE id = $1")
+            .bind(business_id)
+            .bind(to_status)
+            .execute(&mut **tx)
+            .await?;
+
+        sqlx::query(
+        r#"
+        INSERT INTO business_status_changes (business_id, from_status, to_status, reason, admin_id)
+        VALUES ($1, $2::business_status_enum, $3::business_status_enum, $4, $5)
+        "#
+    )
+            .bind(business_id)
+            .bind(from_status)
+            .bind(to_status)
+            .bind(reason)
+            .bind(admin_id)
+            .execute(&mut **tx)
+            .await?;
+        tracing::info!("business {} moved from {} to {}", business_id, from_status, to_status) ;
+        Ok(())
+    }
+
+
+    pub async fn store_api_key(&self, business_id: i64, key_hash: &str, key_prefix: &str, scopes: &[String]) -> Result<(), sqlx::Error> {
+        let result = sqlx::query("insert into api_keys (business_id, key_hash, key_prefix, scopes) values ($1, $2, $3, $4)")
+            .bind(business_id) .bind(key_hash) .bind(key_prefix) .bind(scopes) .execute(&self.connector).await ;
         tracing::info!("executed an insert query for storing the new api key") ;
         match result {
-            Ok(res) => {
+            Ok(_) => {
                 tracing::info!("successfully inserted") ;
                 Ok(())
             },
@@ -175,13 +234,17 @@ impl DbOperations {
         tx: &mut Transaction<'_, Postgres>,
         business_id: i64,
         key_hash: &str,
+        key_prefix: &str,
+        scopes: &[String],
     ) -> Result<(), sqlx::Error> {
         sqlx::query(
-            "INSERT INTO api_keys (business_id, key_hash, status)
-             VALUES ($1, $2, 'active')"
+            "INSERT INTO api_keys (business_id, key_hash, key_prefix, scopes, status)
+             VALUES ($1, $2, $3, $4, 'active')"
         )
             .bind(business_id)
             .bind(key_hash)
+            .bind(key_prefix)
+            .bind(scopes)
             .execute(&mut **tx)
             .await?;
         Ok(())
@@ -192,18 +255,20 @@ impl DbOperations {
         tx: &mut Transaction<'_, Postgres>,
         key_id: i64,
         expires_at: chrono::DateTime<chrono::Utc>,
-    ) -> Result<(), sqlx::Error> {
-        sqlx::query(
+    ) -> Result<Vec<String>, sqlx::Error> {
+        // the scopes are handed to the replacement key
+        let row = sqlx::query(
             "UPDATE api_keys
              SET status = 'expiring',
                  expires_at = $1
-             WHERE id = $2"
+             WHERE id = $2
+             RETURNING scopes"
         )
             .bind(expires_at)
             .bind(key_id)
-            .execute(&mut **tx)
+            .fetch_one(&mut **tx)
             .await?;
-        Ok(())
+        Ok(row.get("scopes"))
     }
 
     pub async fn revoke_api_key_txn(
@@ -223,6 +288,84 @@ impl DbOperations {
         Ok(())
     }
 
+    // rotated keys whose grace period ends before `warn_before` and whose business hasn't been told yet
+    pub async fn get_api_keys_due_for_warning(
+        &self,
+        warn_before: DateTime<Utc>,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT id FROM api_keys
+             WHERE status = 'expiring' AND expiry_warned_at IS NULL
+               AND expires_at > now() AND expires_at <= $1
+             ORDER BY expires_at LIMIT 500"
+        )
+            .bind(warn_before)
+            .fetch_all(&self.connector)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.get("id")).collect())
+    }
+
+
+    // rotated keys past their grace period, auth already ignores them
+    pub async fn get_expired_api_keys(&self) -> Result<Vec<i64>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT id FROM api_keys WHERE status = 'expiring' AND expires_at <= now() ORDER BY expires_at LIMIT 500"
+        )
+            .fetch_all(&self.connector)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.get("id")).collect())
+    }
+
+
+    pub async fn lock_api_key(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        key_id: i64,
+    ) -> Result<LockedApiKey, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT id, business_id, key_prefix, status::TEXT, expires_at, expiry_warned_at
+             FROM api_keys WHERE id = $1 FOR UPDATE"
+        )
+            .bind(key_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(LockedApiKey {
+            id: row.get("id"),
+            business_id: row.get("business_id"),
+            key_prefix: row.get("key_prefix"),
+            status: row.get("status"),
+            expires_at: row.get("expires_at"),
+            expiry_warned_at: row.get("expiry_warned_at"),
+        })
+    }
+
+
+    pub async fn mark_api_key_warned(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        key_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE api_keys SET expiry_warned_at = now() WHERE id = $1")
+            .bind(key_id)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+
+    // unlike revoking, keeps the expiry the rotation set
+    pub async fn retire_api_key(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        key_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE api_keys SET status = 'revoked' WHERE id = $1")
+            .bind(key_id)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
     // ===============================
     // ADMIN API KEYS
     // ===============================
@@ -268,7 +411,8 @@ impl DbOperations {
 
         let rows = sqlx::query(
         r#"
-        SELECT id, name, currency, status::TEXT, balance, created_at
+        SELECT id, name, currency, status::TEXT, account_type::TEXT, balance, held_balance,
+               overdraft_limit, allow_negative, created_at
         FROM business_accounts
         WHERE business_id = $1
         ORDER BY created_at DESC
@@ -283,7 +427,12 @@ impl DbOperations {
             name: r.get("name"),
             currency: r.get("currency"),
             status: r.get("status"),
+            account_type: r.get("account_type"),
             balance: r.get("balance"),
+            held_balance: r.get("held_balance"),
+            available_balance: r.get::<Decimal, _>("balance") - r.get::<Decimal, _>("held_balance"),
+            overdraft_limit: r.get("overdraft_limit"),
+            allow_negative: r.get("allow_negative"),
             created_at: r.get("created_at"),
         }).collect())
     }
@@ -309,16 +458,104 @@ impl DbOperations {
     pub async fn get_account_balance(
         &self,
         account_id: i64,
-    ) -> Result<Decimal, sqlx::Error> {
+    ) -> Result<AccountBalance, sqlx::Error> {
 
         let row = sqlx::query(
-        "SELECT balance FROM business_accounts WHERE id = $1"
+        "SELECT balance, held_balance FROM business_accounts WHERE id = $1"
     )
             .bind(account_id)
             .fetch_one(&self.connector)
             .await?;
         tracing::info!("executed query for getting account balance") ;
-        Ok(row.get("balance"))
+        let ledger_balance: Decimal = row.get("balance");
+        let held_balance: Decimal = row.get("held_balance");
+        Ok(AccountBalance {
+            ledger_balance,
+            held_balance,
+            available_balance: ledger_balance - held_balance,
+        })
+    }
+
+    // sum of the account's ledger entries up to and including `as_of`,
+    // less the holds that were open at that instant
+    pub async fn get_account_balance_as_of(
+        &self,
+        account_id: i64,
+        as_of: DateTime<Utc>,
+    ) -> Result<AccountBalance, sqlx::Error> {
+
+        let row = sqlx::query(
+        r#"
+        SELECT
+            (SELECT COALESCE(SUM(CASE WHEN direction = 'credit' THEN amount ELSE -amount END), 0.00)
+             FROM ledger_entries
+             WHERE account_id = $1 AND created_at <= $2) AS balance,
+            (SELECT COALESCE(SUM(amount), 0.00)
+             FROM holds
+             WHERE account_id = $1 AND created_at <= $2 AND (resolved_at IS NULL OR resolved_at > $2)) AS held_balance
+        "#
+    )
+            .bind(account_id)
+            .bind(as_of)
+            .fetch_one(&self.connector)
+            .await?;
+        tracing::info!("executed query for getting account balance as of {}", as_of) ;
+        let ledger_balance: Decimal = row.get("balance");
+        let held_balance: Decimal = row.get("held_balance");
+        Ok(AccountBalance {
+            ledger_balance,
+            held_balance,
+            available_balance: ledger_balance - held_balance,
+        })
+    }
+
+    pub async fn get_account_history(
+        &self,
+        account_id: i64,
+        cursor: Option<(DateTime<Utc>, i64)>,
+        limit: i64,
+    ) -> Result<AccountHistoryPage, sqlx::Error> {
+
+        // each entry carries the balance it left behind, a page only reads its own rows
+        let (before_created_at, before_id) = cursor.unzip();
+        let rows = sqlx::query(
+        r#"
+        SELECT e.id, e.transaction_id, t.type::TEXT AS txn_type, t.reference_id,
+               e.direction::TEXT, e.amount, e.balance_after AS running_balance, e.created_at
+        FROM ledger_entries e
+        JOIN transactions t ON t.id = e.transaction_id
+        WHERE e.account_id = $1
+          AND ($2::TIMESTAMPTZ IS NULL OR (e.created_at, e.id) < ($2, $3))
+        ORDER BY e.created_at DESC, e.id DESC
+        LIMIT $4
+        "#
+    )
+            .bind(account_id)
+            .bind(before_created_at)
+            .bind(before_id)
+            .bind(limit + 1)
+            .fetch_all(&self.connector)
+            .await?;
+        tracing::info!("executed query for getting history of the account") ;
+
+        let mut entries: Vec<AccountHistoryEntry> = rows.into_iter().map(|r| AccountHistoryEntry {
+            entry_id: r.get("id"),
+            transaction_id: r.get("transaction_id"),
+            txn_type: r.get("txn_type"),
+            reference_id: r.get("reference_id"),
+            direction: r.get("direction"),
+            amount: r.get("amount"),
+            running_balance: r.get("running_balance"),
+            created_at: r.get("created_at"),
+        }).collect();
+        let next_cursor = if entries.len() as i64 > limit {
+            entries.truncate(limit as usize);
+            entries.last().map(|e| encode_cursor(e.created_at, e.entry_id))
+        } else {
+            None
+        };
+
+        Ok(AccountHistoryPage { entries, next_cursor })
     }
 
     pub async fn get_account_details(
@@ -328,7 +565,8 @@ impl DbOperations {
 
         let r = sqlx::query(
         r#"
-        SELECT id, name, currency, status::TEXT, balance, created_at
+        SELECT id, name, currency, status::TEXT, account_type::TEXT, balance, held_balance,
+               overdraft_limit, allow_negative, created_at
         FROM business_accounts
         WHERE id = $1
         "#
@@ -341,144 +579,915 @@ impl DbOperations {
             name: r.get("name"),
             currency: r.get("currency"),
             status: r.get("status"),
+            account_type: r.get("account_type"),
             balance: r.get("balance"),
+            held_balance: r.get("held_balance"),
+            available_balance: r.get::<Decimal, _>("balance") - r.get::<Decimal, _>("held_balance"),
+            overdraft_limit: r.get("overdraft_limit"),
+            allow_negative: r.get("allow_negative"),
             created_at: r.get("created_at"),
         })
     }
 
 
-    pub async fn create_account(
-        &self,
+    // fields left as None keep their value
+    pub async fn update_account_settings(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        account_id: i64,
+        name: Option<&str>,
+        status: Option<&str>,
+        overdraft_limit: Option<Decimal>,
+        allow_negative: Option<bool>,
+    ) -> Result<(), sqlx::Error> {
+
+        sqlx::query(
+        r#"
+        UPDATE business_accounts
+        SET name = COALESCE($2, name),
+            status = COALESCE($3::account_status_enum, status),
+            overdraft_limit = COALESCE($4, overdraft_limit),
+            allow_negative = COALESCE($5, allow_negative)
+        WHERE id = $1
+        "#
+    )
+            .bind(account_id)
+            .bind(name)
+            .bind(status)
+            .bind(overdraft_limit)
+            .bind(allow_negative)
+            .execute(&mut **tx)
+            .await?;
+        tracing::info!("executed the update of account settings") ;
+        Ok(())
+    }
+
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_account_status_change(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
         business_id: i64,
-        new_account: NewAccount,
+        account_id: i64,
+        from_status: &str,
+        to_status: &str,
+        reason: &str,
+        actor_type: &str,
+        actor_id: Option<i64>,
     ) -> Result<i64, sqlx::Error> {
 
         let row = sqlx::query(
         r#"
-        INSERT INTO business_accounts (business_id, name, currency)
-        VALUES ($1, $2, $3)
+        INSERT INTO account_status_changes
+            (business_id, account_id, from_status, to_status, reason, actor_type, actor_id)
+        VALUES ($1, $2, $3::account_status_enum, $4::account_status_enum, $5, $6, $7)
         RETURNING id
         "#
     )
             .bind(business_id)
-            .bind(new_account.name)
-            .bind(new_account.currency)
-            .fetch_one(&self.connector)
+            .bind(account_id)
+            .bind(from_status)
+            .bind(to_status)
+            .bind(reason)
+            .bind(actor_type)
+            .bind(actor_id)
+            .fetch_one(&mut **tx)
             .await?;
-        tracing::info!("executed the creation of business account sucessfully") ;
         Ok(row.get("id"))
     }
 
-    pub async fn verify_business_api_key(
-        &self,
-        key_hash: &str,
-    ) -> Result<i64, sqlx::Error> {
-        tracing::info!("the key hash was {}", key_hash) ;
-        let rec = sqlx::query(
+
+    // records a rename before it is applied, None when the account already has that name
+    pub async fn insert_account_name_change(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        account_id: i64,
+        to_name: &str,
+        reason: &str,
+        actor_type: &str,
+        actor_id: Option<i64>,
+    ) -> Result<Option<i64>, sqlx::Error> {
+
+        let row = sqlx::query(
         r#"
-        SELECT business_id
-        FROM api_keys
-        WHERE key_hash = $1
-          AND status = 'active'
-          AND (expires_at IS NULL OR expires_at > now())
+        INSERT INTO account_name_changes
+            (business_id, account_id, from_name, to_name, reason, actor_type, actor_id)
+        SELECT $1, id, name, $3, $4, $5, $6
+        FROM business_accounts
+        WHERE id = $2 AND name <> $3
+        RETURNING id
         "#
-        
-    )       .bind(key_hash)
-            .fetch_optional(&self.connector)
+    )
+            .bind(business_id)
+            .bind(account_id)
+            .bind(to_name)
+            .bind(reason)
+            .bind(actor_type)
+            .bind(actor_id)
+            .fetch_optional(&mut **tx)
             .await?;
-
-        match rec {
-            Some(r) => Ok(r.get("business_id")),
-            None => Err(sqlx::Error::RowNotFound),
-        }
+        Ok(row.map(|r| r.get("id")))
     }
 
 
-    pub async fn verify_admin_api_key(
+    pub async fn get_account_status_changes(
         &self,
-        key_hash: &str,
-    ) -> Result<i64, sqlx::Error> {
+        account_id: i64,
+    ) -> Result<Vec<AccountStatusChange>, sqlx::Error> {
 
-        let rec = sqlx::query(
+        let rows = sqlx::query(
         r#"
-        SELECT admin_id
-        FROM admin_api_keys
-        WHERE key_hash = $1
-          AND status = 'active'
+        SELECT id, account_id, from_status::TEXT, to_status::TEXT, reason, actor_type, actor_id, created_at
+        FROM account_status_changes
+        WHERE account_id = $1
+        ORDER BY id DESC
         "#
-    )       .bind(key_hash)
-            .fetch_optional(&self.connector)
+    )
+            .bind(account_id)
+            .fetch_all(&self.connector)
             .await?;
 
-        match rec {
-            Some(r) => Ok(r.get("admin_id")),
-            None => Err(sqlx::Error::RowNotFound),
-        }
+        Ok(rows
+            .into_iter()
+            .map(|r| AccountStatusChange {
+                id: r.get("id"),
+                account_id: r.get("account_id"),
+                from_status: r.get("from_status"),
+                to_status: r.get("to_status"),
+                reason: r.get("reason"),
+                actor_type: r.get("actor_type"),
+                actor_id: r.get("actor_id"),
+                created_at: r.get("created_at"),
+            })
+            .collect())
     }
 
-    pub async fn get_business_account_by_id(&self, account_id: i64) -> Result<BusinessState, sqlx::Error> {
-        let row = sqlx::query(
-        "
-        SELECT id, name,status::TEXT, created_at
-        FROM businesses
-        WHERE id = $1
-        " ).bind(account_id).fetch_one(&self.connector).await ;
 
-        match row {
-            Ok(res) => {
-                tracing::info!("got the business account") ;
-                Ok(BusinessState {
-                    id: res.get("id"),
-                    name: res.get("name"),
-                    status: res.get("status"),
-                    created_at: res.get("created_at")
-                })
-            },
-            Err(err) => {
-                tracing::error!("the error was {}", err) ;
-                Err(err)
-            }
-        }
+    pub async fn get_ledger_entries(
+        &self,
+        account_id: i64,
+    ) -> Result<Vec<LedgerEntry>, sqlx::Error> {
+
+        let rows = sqlx::query(
+        r#"
+        SELECT id, transaction_id, account_id, direction::TEXT, amount, created_at
+        FROM ledger_entries
+        WHERE account_id = $1
+        ORDER BY id DESC
+        "#
+    )
+            .bind(account_id)
+            .fetch_all(&self.connector)
+            .await?;
+        tracing::info!("executed query for getting ledger entries of the account") ;
+        Ok(rows.into_iter().map(|r| LedgerEntry {
+            id: r.get("id"),
+            transaction_id: r.get("transaction_id"),
+            account_id: r.get("account_id"),
+            direction: r.get("direction"),
+            amount: r.get("amount"),
+            created_at: r.get("created_at"),
+        }).collect())
     }
 
-    pub async fn lock_account(
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+
+    pub async fn reconcile_account(
+        &self,
         account_id: i64,
-    ) -> Result<(Decimal, String), sqlx::Error> {
+    ) -> Result<AccountReconciliation, sqlx::Error> {
 
         let row = sqlx::query(
-            "SELECT balance, status::TEXT
-         FROM business_accounts
-         WHERE id = $1
-         FOR UPDATE"
-        )
+        r#"
+        SELECT a.balance,
+               COALESCE(SUM(CASE WHEN e.direction = 'credit' THEN e.amount ELSE -e.amount END), 0) AS ledger_balance
+        FROM business_accounts a
+        LEFT JOIN ledger_entries e ON e.account_id = a.id
+        WHERE a.id = $1
+        GROUP BY a.id
+        "#
+    )
             .bind(account_id)
-            .fetch_one(&mut **tx)
+            .fetch_one(&self.connector)
             .await?;
-
-        Ok((row.get("balance"), row.get("status")))
+        tracing::info!("executed query for reconciling account balance against the ledger") ;
+        let balance: Decimal = row.get("balance");
+        let ledger_balance: Decimal = row.get("ledger_balance");
+        Ok(AccountReconciliation {
+            account_id,
+            balance,
+            ledger_balance,
+            in_balance: balance == ledger_balance,
+        })
     }
 
 
-    pub async fn check_idempotency(
-        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    pub async fn create_account(
+        &self,
         business_id: i64,
-        key: &str,
-    ) -> Result<Option<i64>, sqlx::Error> {
+        new_account: NewAccount,
+    ) -> Result<i64, sqlx::Error> {
 
         let row = sqlx::query(
-            "SELECT id FROM transactions
-         WHERE business_id = $1 AND idempotency_key = $2"
-        )
+        r#"
+        INSERT INTO business_accounts (business_id, name, currency, overdraft_limit, allow_negative)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id
+        "#
+    )
+            .bind(business_id)
+            .bind(new_account.name)
+            .bind(new_account.currency)
+            .bind(new_account.overdraft_limit)
+            .bind(new_account.allow_negative)
+            .fetch_one(&self.connector)
+            .await?;
+        tracing::info!("executed the creation of business account sucessfully") ;
+        Ok(row.get("id"))
+    }
+
+    // minor units of an ISO 4217 currency, None when the code is not one
+    pub async fn get_currency_minor_units(
+        &self,
+        code: &str,
+    ) -> Result<Option<i16>, sqlx::Error> {
+
+        let row = sqlx::query(
+        "SELECT minor_units FROM currencies WHERE code = $1"
+    )
+            .bind(code)
+            .fetch_optional(&self.connector)
+            .await?;
+        Ok(row.map(|r| r.get("minor_units")))
+    }
+
+    pub async fn verify_business_api_key(
+        &self,
+        key_hash: &str,
+    ) -> Result<VerifiedApiKey, sqlx::Error> {
+        tracing::info!("the key hash was {}", key_hash) ;
+        let rec = sqlx::query(
+        r#"
+        SELECT k.id, k.business_id, k.scopes, b.status::TEXT AS business_status,
+               bl.read_burst AS b_read_burst, bl.read_per_minute AS b_read_per_minute,
+               bl.write_burst AS b_write_burst, bl.write_per_minute AS b_write_per_minute,
+               kl.read_burst AS k_read_burst, kl.read_per_minute AS k_read_per_minute,
+               kl.write_burst AS k_write_burst, kl.write_per_minute AS k_write_per_minute
+        FROM api_keys k
+        JOIN businesses b ON b.id = k.business_id
+        LEFT JOIN rate_limit_overrides bl ON bl.business_id = k.business_id AND bl.api_key_id IS NULL
+        LEFT JOIN rate_limit_overrides kl ON kl.api_key_id = k.id
+        WHERE k.key_hash = $1
+          AND k.status = 'active'
+          AND (k.expires_at IS NULL OR k.expires_at > now())
+        "#
+        
+    )       .bind(key_hash)
+            .fetch_optional(&self.connector)
+            .await?;
+
+        let key = match rec {
+            Some(r) => VerifiedApiKey {
+                key_id: r.get("id"),
+                business_id: r.get("business_id"),
+                business_status: r.get("business_status"),
+                scopes: r.get("scopes"),
+                business_rate_limits: rate_limits_from_row(&r, "b_"),
+                key_rate_limits: rate_limits_from_row(&r, "k_"),
+            },
+            None => return Err(sqlx::Error::RowNotFound),
+        };
+
+        // minute granularity is plenty and spares a write on every request
+        sqlx::query(
+            "UPDATE api_keys SET last_used_at = now()
+             WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < now() - interval '1 minute')"
+        )
+            .bind(key.key_id)
+            .execute(&self.connector)
+            .await?;
+
+        Ok(key)
+    }
+
+
+    // `api_key_id` None sets the business-wide budgets
+    pub async fn upsert_rate_limit_override(
+        &self,
+        business_id: i64,
+        api_key_id: Option<i64>,
+        limits: &RateLimits,
+    ) -> Result<RateLimitOverride, sqlx::Error> {
+        let conflict_target = if api_key_id.is_some() {
+            "(api_key_id) WHERE api_key_id IS NOT NULL"
+        } else {
+            "(business_id) WHERE api_key_id IS NULL"
+        };
+        let row = sqlx::query(&format!(
+            r#"
+        INSERT INTO rate_limit_overrides
+            (business_id, api_key_id, read_burst, read_per_minute, write_burst, write_per_minute)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT {}
+        DO UPDATE SET read_burst = EXCLUDED.read_burst,
+                      read_per_minute = EXCLUDED.read_per_minute,
+                      write_burst = EXCLUDED.write_burst,
+                      write_per_minute = EXCLUDED.write_per_minute,
+                      updated_at = now()
+        RETURNING business_id, api_key_id, read_burst, read_per_minute, write_burst, write_per_minute, updated_at
+        "#,
+            conflict_target
+        ))
+            .bind(business_id)
+            .bind(api_key_id)
+            .bind(limits.read.burst)
+            .bind(limits.read.per_minute)
+            .bind(limits.write.burst)
+            .bind(limits.write.per_minute)
+            .fetch_one(&self.connector)
+            .await?;
+
+        Ok(rate_limit_override_from_row(&row))
+    }
+
+
+    pub async fn get_rate_limit_overrides(
+        &self,
+        business_id: i64,
+    ) -> Result<Vec<RateLimitOverride>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+        SELECT business_id, api_key_id, read_burst, read_per_minute, write_burst, write_per_minute, updated_at
+        FROM rate_limit_overrides
+        WHERE business_id = $1
+        ORDER BY api_key_id NULLS FIRST
+        "#
+        )
+            .bind(business_id)
+            .fetch_all(&self.connector)
+            .await?;
+
+        Ok(rows.iter().map(rate_limit_override_from_row).collect())
+    }
+
+
+    pub async fn get_api_keys(
+        &self,
+        business_id: i64,
+    ) -> Result<Vec<ApiKeyInfo>, sqlx::Error> {
+        let rows = sqlx::query(
+        r#"
+        SELECT id, business_id, key_prefix, status::TEXT, scopes, created_at, expires_at, last_used_at
+        FROM api_keys
+        WHERE business_id = $1
+        ORDER BY id DESC
+        "#
+    )
+            .bind(business_id)
+            .fetch_all(&self.connector)
+            .await?;
+
+        Ok(rows.iter().map(api_key_from_row).collect())
+    }
+
+
+    // `business_id` scopes the lookup to one business, admins look up any key
+    pub async fn get_api_key(
+        &self,
+        business_id: Option<i64>,
+        key_id: i64,
+    ) -> Result<Option<ApiKeyInfo>, sqlx::Error> {
+        let row = sqlx::query(
+        r#"
+        SELECT id, business_id, key_prefix, status::TEXT, scopes, created_at, expires_at, last_used_at
+        FROM api_keys
+        WHERE id = $1 AND ($2::BIGINT IS NULL OR business_id = $2)
+        "#
+    )
+            .bind(key_id)
+            .bind(business_id)
+            .fetch_optional(&self.connector)
+            .await?;
+
+        Ok(row.as_ref().map(api_key_from_row))
+    }
+
+
+    pub async fn verify_admin_api_key(
+        &self,
+        key_hash: &str,
+    ) -> Result<i64, sqlx::Error> {
+
+        let rec = sqlx::query(
+        r#"
+        SELECT admin_id
+        FROM admin_api_keys
+        WHERE key_hash = $1
+          AND status = 'active'
+        "#
+    )       .bind(key_hash)
+            .fetch_optional(&self.connector)
+            .await?;
+
+        match rec {
+            Some(r) => Ok(r.get("admin_id")),
+            None => Err(sqlx::Error::RowNotFound),
+        }
+    }
+
+    pub async fn get_business_account_by_id(&self, account_id: i64) -> Result<BusinessState, sqlx::Error> {
+        let row = sqlx::query(
+        "
+        SELECT id, name,status::TEXT, created_at
+        FROM businesses
+        WHERE id = $1
+        " ).bind(account_id).fetch_one(&self.connector).await ;
+
+        match row {
+            Ok(res) => {
+                tracing::info!("got the business account") ;
+                Ok(BusinessState {
+                    id: res.get("id"),
+                    name: res.get("name"),
+                    status: res.get("status"),
+                    created_at: res.get("created_at")
+                })
+            },
+            Err(err) => {
+                tracing::error!("the error was {}", err) ;
+                Err(err)
+            }
+        }
+    }
+
+    pub async fn lock_account(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        account_id: i64,
+    ) -> Result<LockedAccount, sqlx::Error> {
+
+        // external accounts are only ever moved through postings, never addressed directly.
+        // another business's account is RowNotFound, ownership holds for as long as the lock does
+        let row = sqlx::query(
+            "SELECT a.balance, a.held_balance, a.overdraft_limit, a.allow_negative,
+                a.status::TEXT, a.currency, c.minor_units
+         FROM business_accounts a
+         JOIN currencies c ON c.code = a.currency
+         WHERE a.id = $1 AND a.business_id = $2 AND a.account_type = 'customer'
+         FOR UPDATE OF a"
+        )
+            .bind(account_id)
+            .bind(business_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(LockedAccount {
+            balance: row.get("balance"),
+            held_balance: row.get("held_balance"),
+            overdraft_limit: row.get("overdraft_limit"),
+            allow_negative: row.get("allow_negative"),
+            status: row.get("status"),
+            currency: row.get("currency"),
+            minor_units: row.get::<i16, _>("minor_units") as u32,
+        })
+    }
+
+
+    // locks both accounts in ascending id order and returns them in argument order,
+    // every multi-account path must lock through here to stay deadlock free
+    pub async fn lock_account_pair(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        first_account_id: i64,
+        second_account_id: i64,
+    ) -> Result<(LockedAccount, LockedAccount), sqlx::Error> {
+
+        if first_account_id <= second_account_id {
+            let first = Self::lock_account(tx, business_id, first_account_id).await?;
+            let second = Self::lock_account(tx, business_id, second_account_id).await?;
+            Ok((first, second))
+        } else {
+            let second = Self::lock_account(tx, business_id, second_account_id).await?;
+            let first = Self::lock_account(tx, business_id, first_account_id).await?;
+            Ok((first, second))
+        }
+    }
+
+
+    pub async fn get_external_account(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        currency: &str,
+    ) -> Result<i64, sqlx::Error> {
+        Self::get_system_account(tx, business_id, currency, "external", "External").await
+    }
+
+
+    // both legs of a currency conversion go through the fx account of their currency
+    pub async fn get_fx_account(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        currency: &str,
+    ) -> Result<i64, sqlx::Error> {
+        Self::get_system_account(tx, business_id, currency, "fx", "FX").await
+    }
+
+
+    // gains and losses of quoted conversions, against the fx account of the same currency
+    pub async fn get_fx_gain_loss_account(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        currency: &str,
+    ) -> Result<i64, sqlx::Error> {
+        Self::get_system_account(tx, business_id, currency, "fx_gain_loss", "FX gain/loss").await
+    }
+
+
+    // system accounts are created on first use, one per business, currency and type
+    async fn get_system_account(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        currency: &str,
+        account_type: &str,
+        name: &str,
+    ) -> Result<i64, sqlx::Error> {
+
+        sqlx::query(
+            "INSERT INTO business_accounts (business_id, name, currency, account_type)
+         VALUES ($1, $2, $3, $4::account_type_enum)
+         ON CONFLICT DO NOTHING"
+        )
+            .bind(business_id)
+            .bind(name)
+            .bind(currency)
+            .bind(account_type)
+            .execute(&mut **tx)
+            .await?;
+
+        let row = sqlx::query(
+            "SELECT id FROM business_accounts
+         WHERE business_id = $1 AND currency = $2 AND account_type = $3::account_type_enum"
+        )
+            .bind(business_id)
+            .bind(currency)
+            .bind(account_type)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(row.get("id"))
+    }
+
+
+    // the newest rate whose validity window contains now
+    pub async fn get_current_fx_rate(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        base_currency: &str,
+        quote_currency: &str,
+    ) -> Result<Option<Decimal>, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT rate FROM fx_rates
+         WHERE base_currency = $1 AND quote_currency = $2
+           AND valid_from <= now() AND (valid_to IS NULL OR valid_to > now())
+         ORDER BY valid_from DESC
+         LIMIT 1"
+        )
+            .bind(base_currency)
+            .bind(quote_currency)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        Ok(row.map(|r| r.get("rate")))
+    }
+
+
+    pub async fn lock_fx_quote(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        quote_id: i64,
+    ) -> Result<Option<LockedFxQuote>, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT id, from_currency, to_currency, rate, expires_at, transaction_id
+         FROM fx_quotes
+         WHERE id = $1 AND business_id = $2
+         FOR UPDATE"
+        )
+            .bind(quote_id)
+            .bind(business_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        Ok(row.map(|r| LockedFxQuote {
+            id: r.get("id"),
+            from_currency: r.get("from_currency"),
+            to_currency: r.get("to_currency"),
+            rate: r.get("rate"),
+            expires_at: r.get("expires_at"),
+            transaction_id: r.get("transaction_id"),
+        }))
+    }
+
+
+    // stores the destination side of a cross-currency transfer and consumes its quote
+    pub async fn record_fx_conversion(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        txn_id: i64,
+        destination_amount: Decimal,
+        destination_currency: &str,
+        rate: Decimal,
+        quote_id: Option<i64>,
+        gain_loss: Option<Decimal>,
+    ) -> Result<(), sqlx::Error> {
+
+        sqlx::query(
+            "UPDATE transactions
+         SET destination_amount = $2, destination_currency = $3, fx_rate = $4, fx_quote_id = $5, fx_gain_loss = $6
+         WHERE id = $1"
+        )
+            .bind(txn_id)
+            .bind(destination_amount)
+            .bind(destination_currency)
+            .bind(rate)
+            .bind(quote_id)
+            .bind(gain_loss)
+            .execute(&mut **tx)
+            .await?;
+
+        if let Some(quote_id) = quote_id {
+            sqlx::query("UPDATE fx_quotes SET transaction_id = $2 WHERE id = $1")
+                .bind(quote_id)
+                .bind(txn_id)
+                .execute(&mut **tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+
+    pub async fn create_fx_rate(
+        &self,
+        new_rate: &NewFxRate,
+    ) -> Result<i64, sqlx::Error> {
+
+        let row = sqlx::query(
+            "INSERT INTO fx_rates (base_currency, quote_currency, rate, valid_from, valid_to)
+         VALUES ($1, $2, $3, COALESCE($4, now()), $5)
+         RETURNING id"
+        )
+            .bind(&new_rate.base_currency)
+            .bind(&new_rate.quote_currency)
+            .bind(new_rate.rate)
+            .bind(new_rate.valid_from)
+            .bind(new_rate.valid_to)
+            .fetch_one(&self.connector)
+            .await?;
+        tracing::info!("executed the creation of fx rate {}/{}", new_rate.base_currency, new_rate.quote_currency) ;
+        Ok(row.get("id"))
+    }
+
+
+    pub async fn get_fx_rates(&self) -> Result<Vec<FxRate>, sqlx::Error> {
+
+        let rows = sqlx::query(
+            "SELECT id, base_currency, quote_currency, rate, valid_from, valid_to, created_at
+         FROM fx_rates
+         ORDER BY base_currency, quote_currency, valid_from DESC"
+        )
+            .fetch_all(&self.connector)
+            .await?;
+        tracing::info!("executed query for getting fx rates") ;
+        Ok(rows.into_iter().map(|r| FxRate {
+            id: r.get("id"),
+            base_currency: r.get("base_currency"),
+            quote_currency: r.get("quote_currency"),
+            rate: r.get("rate"),
+            valid_from: r.get("valid_from"),
+            valid_to: r.get("valid_to"),
+            created_at: r.get("created_at"),
+        }).collect())
+    }
+
+
+    // locks the current rate of the pair for `ttl_secs`, None when the pair has no current rate
+    pub async fn create_fx_quote(
+        &self,
+        business_id: i64,
+        from_currency: &str,
+        to_currency: &str,
+        ttl_secs: i64,
+    ) -> Result<Option<FxQuote>, sqlx::Error> {
+
+        let row = sqlx::query(
+            "INSERT INTO fx_quotes (business_id, fx_rate_id, from_currency, to_currency, rate, expires_at)
+         SELECT $1, id, base_currency, quote_currency, rate, now() + make_interval(secs => $4)
+         FROM fx_rates
+         WHERE base_currency = $2 AND quote_currency = $3
+           AND valid_from <= now() AND (valid_to IS NULL OR valid_to > now())
+         ORDER BY valid_from DESC
+         LIMIT 1
+         RETURNING id, from_currency, to_currency, rate, expires_at"
+        )
             .bind(business_id)
+            .bind(from_currency)
+            .bind(to_currency)
+            .bind(ttl_secs as f64)
+            .fetch_optional(&self.connector)
+            .await?;
+        tracing::info!("executed the creation of fx quote for business {}", business_id) ;
+        Ok(row.map(|r| FxQuote {
+            id: r.get("id"),
+            from_currency: r.get("from_currency"),
+            to_currency: r.get("to_currency"),
+            rate: r.get("rate"),
+            expires_at: r.get("expires_at"),
+        }))
+    }
+
+
+    /// Takes an idempotency key for the request running in `tx`. A concurrent request with the
+    /// same key waits on the row until this transaction ends, so it either replays what this one
+    /// stored or, after a rollback, claims the key itself. Expired keys are taken over.
+    pub async fn claim_idempotency_key(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        scope: &str,
+        key: &str,
+        fingerprint: &str,
+        ttl_secs: i64,
+    ) -> Result<IdempotencyClaim, sqlx::Error> {
+        Self::claim_owned_idempotency_key(tx, IdempotencyOwner::Business(business_id), scope, key, fingerprint, ttl_secs, None).await
+    }
+
+
+    /// Claims an `Idempotency-Key` header in a transaction of its own, the row left without a
+    /// response is the in-progress lock. A lock older than `lock_timeout_secs` was left by a
+    /// request that never finished and is taken over.
+    pub async fn claim_request_idempotency_key(
+        &self,
+        owner: IdempotencyOwner,
+        key: &str,
+        fingerprint: &str,
+        ttl_secs: i64,
+        lock_timeout_secs: i64,
+    ) -> Result<IdempotencyClaim, sqlx::Error> {
+
+        let mut tx = self.connector.begin().await?;
+        let claim = Self::claim_owned_idempotency_key(
+            &mut tx,
+            owner,
+            IDEMPOTENCY_SCOPE_REQUESTS,
+            key,
+            fingerprint,
+            ttl_secs,
+            Some(lock_timeout_secs),
+        )
+            .await?;
+        tx.commit().await?;
+        Ok(claim)
+    }
+
+
+    async fn claim_owned_idempotency_key(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        owner: IdempotencyOwner,
+        scope: &str,
+        key: &str,
+        fingerprint: &str,
+        ttl_secs: i64,
+        lock_timeout_secs: Option<i64>,
+    ) -> Result<IdempotencyClaim, sqlx::Error> {
+
+        let (owner_column, owner_id) = owner.column();
+        let conflict_target = match owner {
+            IdempotencyOwner::Business(_) => "(business_id, scope, idempotency_key)",
+            IdempotencyOwner::Admin(_) => "(admin_id, scope, idempotency_key) WHERE admin_id IS NOT NULL",
+        };
+        let claimed = sqlx::query(&format!(
+            "INSERT INTO idempotency_keys ({owner_column}, scope, idempotency_key, request_fingerprint, expires_at)
+             VALUES ($1, $2, $3, $4, now() + make_interval(secs => $5))
+             ON CONFLICT {conflict_target} DO UPDATE
+             SET request_fingerprint = EXCLUDED.request_fingerprint,
+                 response_status = NULL,
+                 response_body = NULL,
+                 created_at = now(),
+                 expires_at = EXCLUDED.expires_at
+             WHERE idempotency_keys.expires_at <= now()
+                OR (idempotency_keys.response_status IS NULL
+                    AND idempotency_keys.created_at <= now() - make_interval(secs => $6))
+             RETURNING id"
+        ))
+            .bind(owner_id)
+            .bind(scope)
             .bind(key)
+            .bind(fingerprint)
+            .bind(ttl_secs as f64)
+            .bind(lock_timeout_secs.map(|secs| secs as f64))
             .fetch_optional(&mut **tx)
             .await?;
+        if claimed.is_some() {
+            return Ok(IdempotencyClaim::Claimed);
+        }
 
-        Ok(row.map(|r| r.get("id")))
+        let row = sqlx::query(&format!(
+            "SELECT request_fingerprint, response_status, response_body
+             FROM idempotency_keys
+             WHERE {owner_column} = $1 AND scope = $2 AND idempotency_key = $3"
+        ))
+            .bind(owner_id)
+            .bind(scope)
+            .bind(key)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        // keys recorded before fingerprints existed can't be compared, they always replay
+        let stored: Option<String> = row.get("request_fingerprint");
+        if stored.is_some_and(|stored| stored != fingerprint) {
+            return Ok(IdempotencyClaim::Mismatch);
+        }
+        let status: Option<i16> = row.get("response_status");
+        let body: Option<serde_json::Value> = row.get("response_body");
+        Ok(match (status, body) {
+            (Some(status), Some(body)) => IdempotencyClaim::Replay { status: status as u16, body },
+            _ => IdempotencyClaim::InProgress,
+        })
     }
 
 
+    // stores what the request answered, in the same transaction as the rest of its work
+    pub async fn complete_idempotency_key(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        scope: &str,
+        key: &str,
+        status: u16,
+        body: &serde_json::Value,
+    ) -> Result<(), sqlx::Error> {
+
+        sqlx::query(
+            "UPDATE idempotency_keys
+             SET response_status = $4, response_body = $5
+             WHERE business_id = $1 AND scope = $2 AND idempotency_key = $3"
+        )
+            .bind(business_id)
+            .bind(scope)
+            .bind(key)
+            .bind(status as i16)
+            .bind(body)
+            .execute(&mut **tx)
+            .await?;
+        Ok(())
+    }
+
+
+    // stores the response of a request claimed with `claim_request_idempotency_key`, which unlocks the key
+    pub async fn complete_request_idempotency_key(
+        &self,
+        owner: IdempotencyOwner,
+        key: &str,
+        status: u16,
+        body: &serde_json::Value,
+    ) -> Result<(), sqlx::Error> {
+
+        let (owner_column, owner_id) = owner.column();
+        sqlx::query(&format!(
+            "UPDATE idempotency_keys
+             SET response_status = $4, response_body = $5
+             WHERE {owner_column} = $1 AND scope = $2 AND idempotency_key = $3"
+        ))
+            .bind(owner_id)
+            .bind(IDEMPOTENCY_SCOPE_REQUESTS)
+            .bind(key)
+            .bind(status as i16)
+            .bind(body)
+            .execute(&self.connector)
+            .await?;
+        Ok(())
+    }
+
+
+    // gives up the in-progress lock of a request that didn't succeed, so it can be retried
+    pub async fn release_request_idempotency_key(&self, owner: IdempotencyOwner, key: &str) -> Result<(), sqlx::Error> {
+        let (owner_column, owner_id) = owner.column();
+        sqlx::query(&format!(
+            "DELETE FROM idempotency_keys
+             WHERE {owner_column} = $1 AND scope = $2 AND idempotency_key = $3 AND response_status IS NULL"
+        ))
+            .bind(owner_id)
+            .bind(IDEMPOTENCY_SCOPE_REQUESTS)
+            .bind(key)
+            .execute(&self.connector)
+            .await?;
+        Ok(())
+    }
+
+
+    pub async fn purge_expired_idempotency_keys(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= now()")
+            .execute(&self.connector)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+
+    #[allow(clippy::too_many_arguments)]
     pub async fn insert_transaction(
         tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
         business_id: i64,
@@ -486,23 +1495,315 @@ impl DbOperations {
         to_account: Option<i64>,
         txn_type: TransactionType,
         amount: Decimal,
+        currency: &str,
+        reference_id: Option<String>,
+        idempotency_key: &str,
+        status: TransactionStatus,
+    ) -> Result<i64, sqlx::Error> {
+
+        let row = sqlx::query(
+            "INSERT INTO transactions
+         (business_id, from_account_id, to_account_id, type, amount, currency, status, reference_id, idempotency_key)
+         VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9)
+         RETURNING id"
+        )
+            .bind(business_id)
+            .bind(from_account)
+            .bind(to_account)
+            .bind(txn_type)
+            .bind(amount)
+            .bind(currency)
+            .bind(status)
+            .bind(reference_id)
+            .bind(idempotency_key)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(row.get("id"))
+    }
+
+
+    // a rejected movement, it never gets ledger entries
+    pub async fn insert_failed_transaction(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        failed: &FailedTransaction<'_>,
+    ) -> Result<i64, sqlx::Error> {
+
+        let row = sqlx::query(
+            "INSERT INTO transactions
+         (business_id, from_account_id, to_account_id, type, amount, currency, status, reference_id,
+          idempotency_key, failure_code, failure_reason)
+         VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
+         RETURNING id"
+        )
+            .bind(business_id)
+            .bind(failed.from_account_id)
+            .bind(failed.to_account_id)
+            .bind(failed.txn_type)
+            .bind(failed.amount)
+            .bind(failed.currency)
+            .bind(TransactionStatus::Failed)
+            .bind(failed.reference_id.clone())
+            .bind(failed.idempotency_key)
+            .bind(failed.failure_code.as_str())
+            .bind(&failed.failure_reason)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(row.get("id"))
+    }
+
+
+    /// Writes the two journal lines of a movement and applies them to the stored balances,
+    /// balances are never written any other way. Customer accounts must already be locked.
+    /// Each line records the balance its account was left with.
+    pub async fn post_ledger_entries(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        transaction_id: i64,
+        debit_account_id: i64,
+        credit_account_id: i64,
+        amount: Decimal,
+    ) -> Result<(), sqlx::Error> {
+
+        let debit_balance: Decimal = sqlx::query_scalar(
+            "UPDATE business_accounts
+         SET balance = balance - $1
+         WHERE id = $2
+         RETURNING balance"
+        )
+            .bind(amount)
+            .bind(debit_account_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        let credit_balance: Decimal = sqlx::query_scalar(
+            "UPDATE business_accounts
+         SET balance = balance + $1
+         WHERE id = $2
+         RETURNING balance"
+        )
+            .bind(amount)
+            .bind(credit_account_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        // stamped with the posting time rather than the transaction's start, so entries of an
+        // account are ordered the way their balances were applied
+        sqlx::query(
+            "INSERT INTO ledger_entries
+         (business_id, transaction_id, account_id, direction, amount, balance_after, created_at)
+         VALUES ($1, $2, $3, 'debit', $5, $6, clock_timestamp()), ($1, $2, $4, 'credit', $5, $7, clock_timestamp())"
+        )
+            .bind(business_id)
+            .bind(transaction_id)
+            .bind(debit_account_id)
+            .bind(credit_account_id)
+            .bind(amount)
+            .bind(debit_balance)
+            .bind(credit_balance)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_transactions(
+        &self,
+        business_id: i64,
+        filters: &TransactionFilters,
+        cursor: Option<(DateTime<Utc>, i64)>,
+        limit: i64,
+    ) -> Result<TransactionPage, sqlx::Error> {
+
+        let mut query = QueryBuilder::<Postgres>::new(
+            "SELECT id, business_id, from_account_id, to_account_id,
+                type::TEXT, amount, currency, destination_amount, destination_currency,
+                fx_rate, fx_quote_id, fx_gain_loss, status::TEXT, reference_id,
+                idempotency_key, reversal_of, failure_code, failure_reason, created_at
+         FROM transactions
+         WHERE business_id = "
+        );
+        query.push_bind(business_id);
+
+        if let Some(account_id) = filters.account_id {
+            query.push(" AND (from_account_id = ").push_bind(account_id)
+                .push(" OR to_account_id = ").push_bind(account_id).push(")");
+        }
+        if let Some(txn_type) = filters.txn_type {
+            query.push(" AND type = ").push_bind(txn_type);
+        }
+        if let Some(status) = filters.status {
+            query.push(" AND status = ").push_bind(status);
+        }
+        if let Some(min_amount) = filters.min_amount {
+            query.push(" AND amount >= ").push_bind(min_amount);
+        }
+        if let Some(max_amount) = filters.max_amount {
+            query.push(" AND amount <= ").push_bind(max_amount);
+        }
+        if let Some(created_from) = filters.created_from {
+            query.push(" AND created_at >= ").push_bind(created_from);
+        }
+        if let Some(created_to) = filters.created_to {
+            query.push(" AND created_at < ").push_bind(created_to);
+        }
+        if let Some(reference_id) = &filters.reference_id {
+            query.push(" AND reference_id = ").push_bind(reference_id.clone());
+        }
+        if let Some((created_at, id)) = cursor {
+            query.push(" AND (created_at, id) < (").push_bind(created_at)
+                .push(", ").push_bind(id).push(")");
+        }
+        // one extra row tells us whether there is a next page
+        query.push(" ORDER BY created_at DESC, id DESC LIMIT ").push_bind(limit + 1);
+
+        let rows = query.build().fetch_all(&self.connector).await?;
+        tracing::info!("executed paginated query for transactions of the business") ;
+
+        let mut transactions: Vec<transaction_models::Transaction> = rows.iter().map(transaction_from_row).collect();
+        let next_cursor = if transactions.len() as i64 > limit {
+            transactions.truncate(limit as usize);
+            transactions.last().map(|t| encode_cursor(t.created_at, t.id))
+        } else {
+            None
+        };
+
+        Ok(TransactionPage { transactions, next_cursor })
+    }
+
+
+    pub async fn get_transaction(
+        &self,
+        business_id: i64,
+        txn_id: i64,
+    ) -> Result<Option<transaction_models::Transaction>, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT id, business_id, from_account_id, to_account_id,
+                type::TEXT, amount, currency, destination_amount, destination_currency,
+                fx_rate, fx_quote_id, fx_gain_loss, status::TEXT, reference_id,
+                idempotency_key, reversal_of, failure_code, failure_reason, created_at
+         FROM transactions
+         WHERE id = $1 AND business_id = $2"
+        )
+            .bind(txn_id)
+            .bind(business_id)
+            .fetch_optional(&self.connector)
+            .await?;
+
+        Ok(row.as_ref().map(transaction_from_row))
+    }
+
+
+    pub async fn lock_transaction(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        txn_id: i64,
+    ) -> Result<Option<LockedTransaction>, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT id, from_account_id, to_account_id, type, amount, currency, status
+         FROM transactions
+         WHERE id = $1 AND business_id = $2
+         FOR UPDATE"
+        )
+            .bind(txn_id)
+            .bind(business_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        Ok(row.map(|r| LockedTransaction {
+            id: r.get("id"),
+            from_account_id: r.get("from_account_id"),
+            to_account_id: r.get("to_account_id"),
+            txn_type: r.get("type"),
+            amount: r.get("amount"),
+            currency: r.get("currency"),
+            status: r.get("status"),
+        }))
+    }
+
+
+    // the original must be locked first so concurrent reversals see each other's totals
+    pub async fn get_reversed_amount(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        original_txn_id: i64,
+    ) -> Result<Decimal, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT COALESCE(SUM(amount), 0) AS reversed
+         FROM transactions
+         WHERE reversal_of = $1 AND status = 'succeeded'"
+        )
+            .bind(original_txn_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(row.get("reversed"))
+    }
+
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_reversal(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        original_txn_id: i64,
+        from_account: Option<i64>,
+        to_account: Option<i64>,
+        amount: Decimal,
+        currency: &str,
+        reference_id: Option<String>,
+        idempotency_key: &str,
+    ) -> Result<i64, sqlx::Error> {
+
+        let row = sqlx::query(
+            "INSERT INTO transactions
+         (business_id, from_account_id, to_account_id, type, amount, currency, status, reference_id, idempotency_key, reversal_of)
+         VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10)
+         RETURNING id"
+        )
+            .bind(business_id)
+            .bind(from_account)
+            .bind(to_account)
+            .bind(TransactionType::Reversal)
+            .bind(amount)
+            .bind(currency)
+            .bind(TransactionStatus::Pending)
+            .bind(reference_id)
+            .bind(idempotency_key)
+            .bind(original_txn_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(row.get("id"))
+    }
+
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn insert_hold(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        account_id: i64,
+        amount: Decimal,
+        currency: &str,
+        expires_at: DateTime<Utc>,
         reference_id: Option<String>,
         idempotency_key: &str,
-        status: TransactionStatus,
     ) -> Result<i64, sqlx::Error> {
 
         let row = sqlx::query(
-            "INSERT INTO transactions
-         (business_id, from_account_id, to_account_id, type, amount, status, reference_id, idempotency_key)
-         VALUES ($1,$2,$3,$4,$5,$6,$7,$8)
+            "INSERT INTO holds
+         (business_id, account_id, amount, currency, expires_at, reference_id, idempotency_key)
+         VALUES ($1,$2,$3,$4,$5,$6,$7)
          RETURNING id"
         )
             .bind(business_id)
-            .bind(from_account)
-            .bind(to_account)
-            .bind(txn_type)
+            .bind(account_id)
             .bind(amount)
-            .bind(status)
+            .bind(currency)
+            .bind(expires_at)
             .bind(reference_id)
             .bind(idempotency_key)
             .fetch_one(&mut **tx)
@@ -512,18 +1813,66 @@ impl DbOperations {
     }
 
 
-    pub async fn update_balance(
+
+
+    // the account a hold is on, read without locking so the account can be locked first
+    pub async fn get_hold_account_id(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        hold_id: i64,
+    ) -> Result<Option<i64>, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT account_id FROM holds WHERE id = $1 AND business_id = $2"
+        )
+            .bind(hold_id)
+            .bind(business_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        Ok(row.map(|r| r.get("account_id")))
+    }
+
+
+    // the hold's account must already be locked, account then hold is the only lock order
+    pub async fn lock_hold(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        hold_id: i64,
+    ) -> Result<LockedHold, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT id, amount, status::TEXT, expires_at, reference_id
+         FROM holds
+         WHERE id = $1 AND business_id = $2
+         FOR UPDATE"
+        )
+            .bind(hold_id)
+            .bind(business_id)
+            .fetch_one(&mut **tx)
+            .await?;
+
+        Ok(LockedHold {
+            id: row.get("id"),
+            amount: row.get("amount"),
+            status: row.get("status"),
+            expires_at: row.get("expires_at"),
+            reference_id: row.get("reference_id"),
+        })
+    }
+
+
+    // `delta` is added to the account's held balance, negative to release
+    pub async fn adjust_held_balance(
         tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
         account_id: i64,
-        new_balance: Decimal,
+        delta: Decimal,
     ) -> Result<(), sqlx::Error> {
 
         sqlx::query(
-            "UPDATE business_accounts
-         SET balance = $1
-         WHERE id = $2"
+            "UPDATE business_accounts SET held_balance = held_balance + $1 WHERE id = $2"
         )
-            .bind(new_balance)
+            .bind(delta)
             .bind(account_id)
             .execute(&mut **tx)
             .await?;
@@ -531,6 +1880,78 @@ impl DbOperations {
         Ok(())
     }
 
+
+    // moves an active hold to its final status, the caller releases its amount
+    pub async fn resolve_hold(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        hold_id: i64,
+        status: &str,
+        captured_amount: Option<Decimal>,
+        capture_transaction_id: Option<i64>,
+    ) -> Result<(), sqlx::Error> {
+
+        sqlx::query(
+            "UPDATE holds
+         SET status = $2::hold_status_enum, captured_amount = $3, capture_transaction_id = $4, resolved_at = now()
+         WHERE id = $1"
+        )
+            .bind(hold_id)
+            .bind(status)
+            .bind(captured_amount)
+            .bind(capture_transaction_id)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
+    }
+
+
+    pub async fn get_hold(
+        &self,
+        business_id: i64,
+        hold_id: i64,
+    ) -> Result<Option<Hold>, sqlx::Error> {
+
+        let row = sqlx::query(
+            "SELECT id, account_id, amount, currency, status::TEXT, captured_amount,
+                capture_transaction_id, reference_id, expires_at, created_at, resolved_at
+         FROM holds
+         WHERE id = $1 AND business_id = $2"
+        )
+            .bind(hold_id)
+            .bind(business_id)
+            .fetch_optional(&self.connector)
+            .await?;
+
+        Ok(row.map(|r| Hold {
+            id: r.get("id"),
+            account_id: r.get("account_id"),
+            amount: r.get("amount"),
+            currency: r.get("currency"),
+            status: r.get("status"),
+            captured_amount: r.get("captured_amount"),
+            capture_transaction_id: r.get("capture_transaction_id"),
+            reference_id: r.get("reference_id"),
+            expires_at: r.get("expires_at"),
+            created_at: r.get("created_at"),
+            resolved_at: r.get("resolved_at"),
+        }))
+    }
+
+
+    // (business_id, hold_id) of active holds past their expiry
+    pub async fn get_expired_holds(&self) -> Result<Vec<(i64, i64)>, sqlx::Error> {
+
+        let rows = sqlx::query(
+            "SELECT business_id, id FROM holds WHERE status = 'active' AND expires_at <= now() ORDER BY expires_at LIMIT 500"
+        )
+            .fetch_all(&self.connector)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| (r.get("business_id"), r.get("id"))).collect())
+    }
+
+
     pub async fn mark_transaction_status(
         tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
         txn_id: i64,
@@ -556,7 +1977,7 @@ impl DbOperations {
     ) -> Result<Vec<WebhookResponse>, sqlx::Error> {
         let rows = sqlx::query(
             r#"
-        SELECT id, url, business_id, status, created_at
+        SELECT id, url, business_id, status::TEXT, event_types, created_at
         FROM webhooks
         WHERE business_id = $1
         ORDER BY created_at DESC
@@ -573,6 +1994,7 @@ impl DbOperations {
                 url: row.get("url"),
                 business_id: row.get("business_id"),
                 status: row.get("status"),
+                event_types: row.get("event_types"),
                 created_at: row.get("created_at"),
             })
             .collect();
@@ -585,16 +2007,18 @@ impl DbOperations {
         &self,
         business_id: i64,
         url: &str,
+        event_types: &[String],
     ) -> Result<i64, sqlx::Error> {
         let row = sqlx::query(
             r#"
-        INSERT INTO webhooks (business_id, url, secret, status)
-        VALUES ($1, $2, gen_random_uuid()::text, 'active')
+        INSERT INTO webhooks (business_id, url, secret, status, event_types)
+        VALUES ($1, $2, gen_random_uuid()::text, 'active', $3)
         RETURNING id
         "#
         )
             .bind(business_id)
             .bind(url)
+            .bind(event_types)
             .fetch_one(&self.connector)
             .await?;
 
@@ -627,13 +2051,15 @@ impl DbOperations {
         webhook_id: i64,
         url: Option<String>,
         status: Option<String>,
+        event_types: Option<Vec<String>>,
     ) -> Result<u64, sqlx::Error> {
         let result = sqlx::query(
             r#"
         UPDATE webhooks
         SET
             url = COALESCE($1, url),
-            status = COALESCE($2, status)
+            status = COALESCE($2::webhook_status_enum, status),
+            event_types = COALESCE($5, event_types)
         WHERE id = $3 AND business_id = $4
         "#
         )
@@ -641,62 +2067,300 @@ impl DbOperations {
             .bind(status)
             .bind(webhook_id)
             .bind(business_id)
+            .bind(event_types)
             .execute(&self.connector)
             .await?;
 
         Ok(result.rows_affected())
     }
 
-    pub async fn get_webhook(&self, business_id: i64) -> Result<i64, sqlx::Error> {
-        let row = sqlx::query("select id from webhooks where business_id = $1 AND status = 'active' ")
+    pub async fn get_webhook_secret(
+        &self,
+        business_id: i64,
+        webhook_id: i64,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let row = sqlx::query(
+            "SELECT secret FROM webhooks WHERE id = $1 AND business_id = $2"
+        )
+            .bind(webhook_id)
             .bind(business_id)
-            .fetch_one(&self.connector).await? ;
-        Ok(row.get("id"))
+            .fetch_optional(&self.connector)
+            .await?;
+
+        Ok(row.map(|r| r.get("secret")))
+    }
+
+    pub async fn rotate_webhook_secret(
+        &self,
+        business_id: i64,
+        webhook_id: i64,
+    ) -> Result<Option<String>, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+        UPDATE webhooks
+        SET secret = gen_random_uuid()::text
+        WHERE id = $1 AND business_id = $2
+        RETURNING secret
+        "#
+        )
+            .bind(webhook_id)
+            .bind(business_id)
+            .fetch_optional(&self.connector)
+            .await?;
+
+        Ok(row.map(|r| r.get("secret")))
     }
-    
+
     // get_full_webhook
     pub async fn get_full_webhook(&self, id: i64) -> Result<WebhookRow, sqlx::Error> {
-        let row = sqlx::query("select id, business_id, url, status::Text, secret from webhooks where id=$1 ")
+        let row = sqlx::query(
+            "select w.id, w.business_id, w.url, w.status::Text, w.secret, b.status::Text as business_status
+             from webhooks w join businesses b on b.id = w.business_id where w.id=$1 ")
             .bind(id)
             .fetch_one(&self.connector).await? ;
         Ok(WebhookRow {
             id: row.get("id"),
             business_id: row.get("business_id"),
             status: row.get("status"),
+            business_status: row.get("business_status"),
             url: row.get("url"),
             secret: row.get("secret")
         })
     }
 
-    pub async fn create_webhook_event(
+    // every active endpoint of the business subscribed to the event type
+    pub async fn get_subscribed_webhooks_txn(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        business_id: i64,
+        event_type: &str,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+        SELECT id
+        FROM webhooks
+        WHERE business_id = $1
+          AND status = 'active'
+          AND ($2 = ANY(event_types) OR '*' = ANY(event_types))
+        ORDER BY id
+        "#
+        )
+            .bind(business_id)
+            .bind(event_type)
+            .fetch_all(&mut **tx)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.get("id")).collect())
+    }
+
+
+    // None when the endpoint already has a delivery for this domain event
+    pub async fn create_webhook_event_txn(
         &self,
+        tx: &mut Transaction<'_, Postgres>,
         webhook_id: i64,
+        domain_event_id: i64,
         event_type: &str,
-        payload: serde_json::Value,
-    ) -> Result<i64, sqlx::Error> {
+        payload: &serde_json::Value,
+    ) -> Result<Option<i64>, sqlx::Error> {
         let row = sqlx::query(
             r#"
         INSERT INTO webhook_events (
             webhook_id,
+            domain_event_id,
             event_type,
             payload,
             status,
             attempt_count
         )
-        VALUES ($1, $2, $3, 'pending', 0)
+        VALUES ($1, $2, $3, $4, 'pending', 0)
+        ON CONFLICT (domain_event_id, webhook_id) DO NOTHING
         RETURNING id
         "#
         )
             .bind(webhook_id)
+            .bind(domain_event_id)
             .bind(event_type)
             .bind(payload)
-            .fetch_one(&self.connector)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        Ok(row.map(|r| r.get("id")))
+    }
+
+
+    pub async fn record_domain_event(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        business_id: i64,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> Result<i64, sqlx::Error> {
+        let row = sqlx::query(
+            "INSERT INTO domain_events (business_id, event_type, payload)
+         VALUES ($1, $2, $3)
+         RETURNING id"
+        )
+            .bind(business_id)
+            .bind(event_type)
+            .bind(payload)
+            .fetch_one(&mut **tx)
             .await?;
 
         Ok(row.get("id"))
     }
 
 
+    // locks the outbox row so two dispatchers never materialise the same event concurrently
+    pub async fn get_domain_event_txn(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        domain_event_id: i64,
+    ) -> Result<Option<DomainEventRow>, sqlx::Error> {
+        let row = sqlx::query(
+            r#"
+        SELECT id, business_id, event_type, payload, dispatched_at, created_at
+        FROM domain_events
+        WHERE id = $1
+        FOR UPDATE SKIP LOCKED
+        "#
+        )
+            .bind(domain_event_id)
+            .fetch_optional(&mut **tx)
+            .await?;
+
+        Ok(row.map(|r| DomainEventRow {
+            id: r.get("id"),
+            business_id: r.get("business_id"),
+            event_type: r.get("event_type"),
+            payload: r.get("payload"),
+            dispatched_at: r.get("dispatched_at"),
+            created_at: r.get("created_at"),
+        }))
+    }
+
+
+    pub async fn mark_domain_event_dispatched_txn(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        domain_event_id: i64,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+        UPDATE domain_events
+        SET dispatched_at = now()
+        WHERE id = $1
+        "#
+        )
+            .bind(domain_event_id)
+            .execute(&mut **tx)
+            .await?;
+
+        Ok(())
+    }
+
+
+    /// Fans an outbox event out into one webhook_event per subscribed endpoint and marks it
+    /// dispatched, inside the caller's transaction. The messages must only be queued after commit.
+    pub async fn materialise_domain_event_txn(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        domain_event_id: i64,
+        business_id: i64,
+        event_type: &str,
+        payload: &serde_json::Value,
+    ) -> Result<Vec<WebhookQueueMessage>, sqlx::Error> {
+        let webhook_ids = self.get_subscribed_webhooks_txn(tx, business_id, event_type).await?;
+        // no endpoints is fine, the event stays in the outbox as the durable record
+        let mut queue_messages = Vec::with_capacity(webhook_ids.len());
+        for webhook_id in webhook_ids {
+            if let Some(webhook_event_id) = self
+                .create_webhook_event_txn(tx, webhook_id, domain_event_id, event_type, payload)
+                .await?
+            {
+                queue_messages.push(WebhookQueueMessage { webhook_event_id, webhook_id });
+            }
+        }
+        self.mark_domain_event_dispatched_txn(tx, domain_event_id).await?;
+
+        Ok(queue_messages)
+    }
+
+
+    /// Emits `account.negative_balance` / `account.positive_balance` when a posting moved the
+    /// account's balance across zero. Call after posting, with the balance read when it was locked.
+    pub async fn publish_balance_events_txn(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        business_id: i64,
+        account_id: i64,
+        previous_balance: Decimal,
+        transaction_id: i64,
+    ) -> Result<Vec<WebhookQueueMessage>, sqlx::Error> {
+        let row = sqlx::query("SELECT balance, currency FROM business_accounts WHERE id = $1")
+            .bind(account_id)
+            .fetch_one(&mut **tx)
+            .await?;
+        let balance: Decimal = row.get("balance");
+        let currency: String = row.get("currency");
+
+        let event_type = if previous_balance >= Decimal::ZERO && balance < Decimal::ZERO {
+            "account.negative_balance"
+        } else if previous_balance < Decimal::ZERO && balance >= Decimal::ZERO {
+            "account.positive_balance"
+        } else {
+            return Ok(Vec::new());
+        };
+
+        let payload = serde_json::json!({
+            "event": event_type,
+            "data": {
+                "account_id": account_id,
+                "business_id": business_id,
+                "balance": balance,
+                "previous_balance": previous_balance,
+                "currency": currency,
+                "transaction_id": transaction_id
+            }
+        });
+        self.publish_domain_event_txn(tx, business_id, event_type, payload).await
+    }
+
+
+    /// Records a domain event and its webhook deliveries atomically with the caller's ledger write.
+    pub async fn publish_domain_event_txn(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        business_id: i64,
+        event_type: &str,
+        payload: serde_json::Value,
+    ) -> Result<Vec<WebhookQueueMessage>, sqlx::Error> {
+        let domain_event_id = Self::record_domain_event(tx, business_id, event_type, &payload).await?;
+        self.materialise_domain_event_txn(tx, domain_event_id, business_id, event_type, &payload).await
+    }
+
+
+    // events the request path committed but never dispatched (crash, db hiccup after commit)
+    pub async fn get_undispatched_domain_events(
+        &self,
+        older_than: DateTime<Utc>,
+    ) -> Result<Vec<i64>, sqlx::Error> {
+        let rows = sqlx::query(
+            r#"
+        SELECT id
+        FROM domain_events
+        WHERE dispatched_at IS NULL
+          AND created_at <= $1
+        ORDER BY id
+        "#
+        )
+            .bind(older_than)
+            .fetch_all(&self.connector)
+            .await?;
+
+        Ok(rows.into_iter().map(|r| r.get("id")).collect())
+    }
+
+
     pub async fn get_webhook_event(
         &self,
         event_id: i64,
@@ -794,23 +2458,103 @@ impl DbOperations {
     }
 
 
-    pub async fn get_pending_webhook_events(
+    // deliveries held back while the business was suspended, with the endpoint they go to
+    pub async fn get_pending_webhook_events_for_business(
         &self,
-    ) -> Result<Vec<i64>, sqlx::Error> {
+        business_id: i64,
+    ) -> Result<Vec<(i64, i64)>, sqlx::Error> {
         let rows = sqlx::query(
             r#"
-        SELECT id
-        FROM webhook_events
-        WHERE status = 'pending'
-          AND (next_retry_at IS NULL OR next_retry_at <= now())
-        ORDER BY created_at
+        SELECT e.id, e.webhook_id
+        FROM webhook_events e
+        JOIN webhooks w ON w.id = e.webhook_id
+        WHERE w.business_id = $1
+          AND e.status = 'pending'
+          AND (e.next_retry_at IS NULL OR e.next_retry_at <= now())
+        ORDER BY e.created_at
         "#
         )
+            .bind(business_id)
             .fetch_all(&self.connector)
             .await?;
 
-        Ok(rows.into_iter().map(|r| r.get("id")).collect())
+        Ok(rows.into_iter().map(|r| (r.get("id"), r.get("webhook_id"))).collect())
+    }
+
+
+}
+
+
+// the override columns of a LEFT JOIN, aliased with `prefix`, None when nothing joined
+fn rate_limits_from_row(r: &PgRow, prefix: &str) -> Option<RateLimits> {
+    let read_burst: Option<i32> = r.get(format!("{}read_burst", prefix).as_str());
+    read_burst.map(|read_burst| RateLimits {
+        read: RateLimitBudget {
+            burst: read_burst,
+            per_minute: r.get(format!("{}read_per_minute", prefix).as_str()),
+        },
+        write: RateLimitBudget {
+            burst: r.get(format!("{}write_burst", prefix).as_str()),
+            per_minute: r.get(format!("{}write_per_minute", prefix).as_str()),
+        },
+    })
+}
+
+
+fn rate_limit_override_from_row(r: &PgRow) -> RateLimitOverride {
+    RateLimitOverride {
+        business_id: r.get("business_id"),
+        api_key_id: r.get("api_key_id"),
+        read: RateLimitBudget { burst: r.get("read_burst"), per_minute: r.get("read_per_minute") },
+        write: RateLimitBudget { burst: r.get("write_burst"), per_minute: r.get("write_per_minute") },
+        updated_at: r.get("updated_at"),
+    }
+}
+
+
+fn api_key_from_row(r: &PgRow) -> ApiKeyInfo {
+    ApiKeyInfo {
+        id: r.get("id"),
+        business_id: r.get("business_id"),
+        prefix: r.get("key_prefix"),
+        status: r.get("status"),
+        scopes: r.get("scopes"),
+        created_at: r.get("created_at"),
+        expires_at: r.get("expires_at"),
+        last_used_at: r.get("last_used_at"),
+    }
+}
+
+
+// deadlock detected / serialization failure, the whole transaction can safely be run again
+pub fn is_retryable_error(err: &sqlx::Error) -> bool {
+    match err {
+        sqlx::Error::Database(db_err) => matches!(db_err.code().as_deref(), Some("40P01") | Some("40001")),
+        _ => false,
     }
+}
 
 
-}
\ No newline at end of file
+fn transaction_from_row(r: &PgRow) -> transaction_models::Transaction {
+    transaction_models::Transaction {
+        id: r.get("id"),
+        business_id: r.get("business_id"),
+        from_account_id: r.get("from_account_id"),
+        to_account_id: r.get("to_account_id"),
+        txn_type: r.get("type"),
+        amount: r.get("amount"),
+        currency: r.get("currency"),
+        destination_amount: r.get("destination_amount"),
+        destination_currency: r.get("destination_currency"),
+        fx_rate: r.get("fx_rate"),
+        fx_quote_id: r.get("fx_quote_id"),
+        fx_gain_loss: r.get("fx_gain_loss"),
+        status: r.get("status"),
+        reference_id: r.get("reference_id"),
+        idempotency_key: r.get("idempotency_key"),
+        reversal_of: r.get("reversal_of"),
+        failure_code: r.get("failure_code"),
+        failure_reason: r.get("failure_reason"),
+        created_at: r.get("created_at"),
+    }
+}
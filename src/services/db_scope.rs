@@ -0,0 +1,131 @@
+use std::future::Future;
+use sqlx::PgConnection;
+
+// roles of the row level security policies, see the row_level_security migration
+pub const TENANT_ROLE: &str = "ledger_tenant";
+pub const ADMIN_ROLE: &str = "ledger_admin";
+
+/// Whom the queries of the running request are made for. The auth middlewares set it around
+/// the rest of the request, and every connection taken from the pool is switched to its role.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbScope {
+    Business(i64),
+    Admin,
+}
+
+tokio::task_local! {
+    static DB_SCOPE: DbScope;
+}
+
+pub async fn with_db_scope<F: Future>(scope: DbScope, f: F) -> F::Output {
+    DB_SCOPE.scope(scope, f).await
+}
+
+// None outside of a request, e.g. in the background workers
+pub fn current_db_scope() -> Option<DbScope> {
+    DB_SCOPE.try_with(|scope| *scope).ok()
+}
+
+
+// Runs on every connection the pool hands out, in the task that asked for it. Business scope
+// gets the tenant role and app.business_id, admin scope the admin role, and no scope the login
+// role, which owns the tables and isn't subject to the policies. Both settings are always
+// written, so nothing is left over from the connection's previous user.
+pub async fn apply_db_scope(conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+    let (role, business_id) = match current_db_scope() {
+        Some(DbScope::Business(business_id)) => (TENANT_ROLE, business_id.to_string()),
+        Some(DbScope::Admin) => (ADMIN_ROLE, String::new()),
+        None => ("none", String::new()),
+    };
+    sqlx::query("SELECT set_config('role', $1, false), set_config('app.business_id', $2, false)")
+        .bind(role)
+        .bind(business_id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use crate::models::accounts_models::NewAccount;
+    use crate::models::transaction_models::{TransactionStatus, TransactionType};
+    use crate::models::webhooks_models::ALL_WEBHOOK_EVENTS;
+    use crate::services::db_operations::DbOperations;
+    use crate::test_support::test_db;
+
+    struct Seeded {
+        business_id: i64,
+        account_id: i64,
+        webhook_id: i64,
+    }
+
+    fn wallet() -> NewAccount {
+        NewAccount { name: "wallet".into(), currency: "USD".into(), overdraft_limit: Decimal::ZERO, allow_negative: false }
+    }
+
+    // a business with an account, a transaction, a webhook and a delivery of it, written without a scope
+    async fn seed(db: &DbOperations, name: &str) -> Seeded {
+        let business_id = db.create_new_business(name).await.unwrap();
+        let account_id = db.create_account(business_id, wallet()).await.unwrap();
+        let webhook_id = db.create_webhook(business_id, "http://127.0.0.1:1/hook", &[ALL_WEBHOOK_EVENTS.to_string()]).await.unwrap();
+        let mut tx = db.connector.begin().await.unwrap();
+        DbOperations::insert_transaction(
+            &mut tx, business_id, None, Some(account_id), TransactionType::Credit, Decimal::ONE, "USD", None, "seed", TransactionStatus::Pending,
+        )
+            .await
+            .unwrap();
+        db.publish_domain_event_txn(&mut tx, business_id, "transaction.succeeded", serde_json::json!({ "event": "transaction.succeeded" }))
+            .await
+            .unwrap();
+        tx.commit().await.unwrap();
+        Seeded { business_id, account_id, webhook_id }
+    }
+
+    // rows of `seeded` in each protected table that the current scope can see
+    async fn visible(db: &DbOperations, seeded: &Seeded) -> [i64; 4] {
+        let count = |sql: &'static str, id: i64| {
+            let pool = db.connector.clone();
+            async move { sqlx::query_scalar::<_, i64>(sql).bind(id).fetch_one(&pool).await.unwrap() }
+        };
+        [
+            count("SELECT count(*) FROM business_accounts WHERE business_id = $1", seeded.business_id).await,
+            count("SELECT count(*) FROM transactions WHERE business_id = $1", seeded.business_id).await,
+            count("SELECT count(*) FROM webhooks WHERE business_id = $1", seeded.business_id).await,
+            count("SELECT count(*) FROM webhook_events WHERE webhook_id = $1", seeded.webhook_id).await,
+        ]
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    async fn policies_confine_a_business_to_its_own_rows() {
+        // two connections, so the scoped ones are reused by the unscoped queries below
+        let db = test_db(2).await;
+        let own = seed(&db, "own").await;
+        let other = seed(&db, "other").await;
+
+        with_db_scope(DbScope::Business(own.business_id), async {
+            assert_eq!(visible(&db, &other).await, [0; 4]);
+            assert!(visible(&db, &own).await.iter().all(|&rows| rows > 0));
+            // queries that only filter on the account id are covered too
+            assert!(matches!(db.get_account_details(other.account_id).await, Err(sqlx::Error::RowNotFound)));
+            assert!(db.get_account_details(own.account_id).await.is_ok());
+            // and nothing can be written for another business
+            let err = db.create_account(other.business_id, wallet()).await.unwrap_err();
+            assert_eq!(err.as_database_error().and_then(|e| e.code()).as_deref(), Some("42501"));
+        })
+            .await;
+
+        // admins and the background workers see every business
+        with_db_scope(DbScope::Admin, async {
+            assert!(visible(&db, &other).await.iter().all(|&rows| rows > 0));
+        })
+            .await;
+        assert!(visible(&db, &other).await.iter().all(|&rows| rows > 0));
+
+        // a business scope doesn't outlive its request on the pooled connections
+        assert!(db.get_account_details(other.account_id).await.is_ok());
+    }
+}
@@ -0,0 +1,49 @@
+use std::sync::Arc;
+use tracing::{error, info};
+use crate::AppState;
+use crate::services::db_operations::DbOperations;
+
+/// Voids or expires a hold and gives its amount back to the available balance. Returns the
+/// status the hold had before, only an `active` one is released, or None when there is no such hold.
+pub async fn release_hold(
+    app_state: &Arc<AppState>,
+    business_id: i64,
+    hold_id: i64,
+    status: &str,
+) -> Result<Option<String>, sqlx::Error> {
+
+    let mut tx = app_state.database_connector.connector.begin().await?;
+
+    let Some(account_id) = DbOperations::get_hold_account_id(&mut tx, business_id, hold_id).await? else {
+        return Ok(None);
+    };
+    DbOperations::lock_account(&mut tx, business_id, account_id).await?;
+    let hold = DbOperations::lock_hold(&mut tx, business_id, hold_id).await?;
+
+    if hold.status == "active" {
+        DbOperations::adjust_held_balance(&mut tx, account_id, -hold.amount).await?;
+        DbOperations::resolve_hold(&mut tx, hold.id, status, None, None).await?;
+        tx.commit().await?;
+        info!("hold {} {}", hold.id, status);
+    }
+
+    Ok(Some(hold.status))
+}
+
+
+pub async fn hold_expiry_sweeper(app_state: Arc<AppState>) {
+    info!("Hold expiry sweeper started");
+    loop {
+        match app_state.database_connector.get_expired_holds().await {
+            Ok(holds) => {
+                for (business_id, hold_id) in holds {
+                    if let Err(e) = release_hold(&app_state, business_id, hold_id, "expired").await {
+                        error!("failed to expire hold {}: {}", hold_id, e);
+                    }
+                }
+            }
+            Err(e) => error!("db error loading expired holds: {}", e),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
+    }
+}
@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, info};
+use crate::AppState;
+
+
+pub fn idempotency_key_ttl_secs() -> i64 {
+    std::env::var("IDEMPOTENCY_KEY_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(24 * 60 * 60)
+}
+
+
+/// Hex encoded SHA-256 of the operation name and the request as JSON, object keys are sorted
+/// so the same request always hashes the same whatever order its fields were sent in.
+pub fn request_fingerprint<T: Serialize>(operation: &str, request: &T) -> String {
+    let value = serde_json::to_value(request).expect("requests serialize to json");
+    let mut hasher = Sha256::new();
+    hasher.update(operation.as_bytes());
+    hasher.update(b".");
+    hasher.update(value.to_string().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+
+pub async fn idempotency_key_sweeper(app_state: Arc<AppState>) {
+    info!("Idempotency key sweeper started");
+    loop {
+        match app_state.database_connector.purge_expired_idempotency_keys().await {
+            Ok(0) => {}
+            Ok(purged) => info!("purged {} expired idempotency keys", purged),
+            Err(e) => error!("db error purging expired idempotency keys: {}", e),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(60 * 60)).await;
+    }
+}
@@ -1,3 +1,7 @@
 pub mod db_operations;
+pub mod db_scope;
 pub mod other_services;
-pub mod webhook_events_executor;
\ No newline at end of file
+pub mod webhook_events_executor;
+pub mod holds_executor;
+pub mod api_keys_executor;
+pub mod idempotency_executor;
@@ -1,7 +1,11 @@
-use axum::Json;
+use std::sync::Arc;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::HeaderMap;
 use rand::{distributions::Alphanumeric, Rng};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
+use crate::AppState;
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -34,6 +38,13 @@ pub fn generate_api_key() -> (String, String) {
     (raw_key, hashed_key)
 }
 
+// long enough to tell keys apart, far too short to guess the rest
+pub const API_KEY_PREFIX_LEN: usize = 14;
+
+pub fn api_key_prefix(raw_key: &str) -> String {
+    raw_key.chars().take(API_KEY_PREFIX_LEN).collect()
+}
+
 pub fn hash_api_key(raw_key: &str) -> String {
     use hmac::{Hmac, Mac};
     use sha2::Sha256;
@@ -51,8 +62,119 @@ pub fn hash_api_key(raw_key: &str) -> String {
 }
 
 
-pub async fn demo_listening_webhook(Json(value): Json<serde_json::Value>) -> impl axum::response::IntoResponse {
+pub const WEBHOOK_ID_HEADER: &str = "X-Webhook-Id";
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+
+/// Signature sent with every webhook delivery:
+/// hex encoded HMAC-SHA256 of `"{timestamp}.{raw body}"` keyed with the webhook secret
+pub fn sign_webhook_payload(secret: &str, timestamp: i64, body: &[u8]) -> String {
+    hex::encode(webhook_mac(secret, timestamp, body).finalize().into_bytes())
+}
+
+/// Receiver side check of a delivery, compares in constant time and rejects
+/// timestamps further than `tolerance_secs` away from now (replayed deliveries)
+pub fn verify_webhook_signature(
+    secret: &str,
+    timestamp: i64,
+    body: &[u8],
+    signature: &str,
+    tolerance_secs: i64,
+) -> bool {
+    if (chrono::Utc::now().timestamp() - timestamp).abs() > tolerance_secs {
+        return false;
+    }
+    let signature = match hex::decode(signature) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+
+    webhook_mac(secret, timestamp, body).verify_slice(&signature).is_ok()
+}
+
+// the HMAC of a delivery, shared by the sender and the receiver side
+fn webhook_mac(secret: &str, timestamp: i64, body: &[u8]) -> HmacSha256 {
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    mac
+}
+
+
+pub async fn demo_listening_webhook(
+    State(app_state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> impl axum::response::IntoResponse {
     tracing::info!("Successfully Listened the webhook sent") ;
-    tracing::info!("demo_listening_webhook {:?}", value);
+
+    // verifying the delivery the same way a receiver would, the demo can read the secret from the db
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok()).map(|h| h.to_string());
+    let webhook_id = header(WEBHOOK_ID_HEADER).and_then(|h| h.parse::<i64>().ok());
+    let timestamp = header(WEBHOOK_TIMESTAMP_HEADER).and_then(|h| h.parse::<i64>().ok());
+    let signature = header(WEBHOOK_SIGNATURE_HEADER);
+    match (webhook_id, timestamp, signature) {
+        (Some(webhook_id), Some(timestamp), Some(signature)) => {
+            match app_state.database_connector.get_full_webhook(webhook_id).await {
+                Ok(webhook) => {
+                    let valid = verify_webhook_signature(&webhook.secret, timestamp, &body, &signature, 300);
+                    tracing::info!("webhook {} signature valid: {}", webhook_id, valid) ;
+                },
+                Err(e) => tracing::warn!("unable to load webhook {} to verify signature {}", webhook_id, e),
+            }
+        },
+        _ => tracing::warn!("webhook delivery without signature headers"),
+    }
+
+    tracing::info!("demo_listening_webhook {}", String::from_utf8_lossy(&body));
     "Ok"
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "whsec_test";
+    const BODY: &[u8] = br#"{"event":"transaction.succeeded"}"#;
+
+    #[test]
+    fn a_signed_delivery_verifies() {
+        let now = chrono::Utc::now().timestamp();
+        let signature = sign_webhook_payload(SECRET, now, BODY);
+
+        assert!(verify_webhook_signature(SECRET, now, BODY, &signature, 300));
+    }
+
+    #[test]
+    fn a_tampered_body_is_rejected() {
+        let now = chrono::Utc::now().timestamp();
+        let signature = sign_webhook_payload(SECRET, now, BODY);
+
+        assert!(!verify_webhook_signature(SECRET, now, br#"{"event":"transaction.failed"}"#, &signature, 300));
+        // the timestamp is signed along with the body
+        assert!(!verify_webhook_signature(SECRET, now - 1, BODY, &signature, 300));
+    }
+
+    #[test]
+    fn a_signature_from_another_secret_is_rejected() {
+        let now = chrono::Utc::now().timestamp();
+        let signature = sign_webhook_payload("whsec_other", now, BODY);
+
+        assert!(!verify_webhook_signature(SECRET, now, BODY, &signature, 300));
+        assert!(!verify_webhook_signature(SECRET, now, BODY, "not hex", 300));
+    }
+
+    #[test]
+    fn a_timestamp_outside_the_tolerance_is_rejected() {
+        let now = chrono::Utc::now().timestamp();
+        for timestamp in [now - 301, now + 301] {
+            let signature = sign_webhook_payload(SECRET, timestamp, BODY);
+            assert!(!verify_webhook_signature(SECRET, timestamp, BODY, &signature, 300));
+        }
+        let signature = sign_webhook_payload(SECRET, now - 290, BODY);
+        assert!(verify_webhook_signature(SECRET, now - 290, BODY, &signature, 300));
+    }
+}
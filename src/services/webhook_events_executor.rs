@@ -43,6 +43,13 @@ pub async fn webhook_worker(
         // Loading webhook config
         let webhook: WebhookRow = app_state.database_connector.get_full_webhook(event.webhook_id).await.unwrap() ;
 
+        // deliveries of a suspended business stay pending without using up attempts,
+        // reinstating the business queues them again
+        if webhook.business_status == "suspended" {
+            info!("webhook_event {} paused, business {} is suspended", event_id, webhook.business_id);
+            continue;
+        }
+
 
         // Sending HTTP webhook
         let send_result = send_webhook_http(&webhook, &event).await;
@@ -110,9 +117,18 @@ async fn send_webhook_http(
 ) -> Result<(), String> {
     let client = Client::new();
 
+    // signing the exact bytes we send, receivers verify against the raw body
+    let body = serde_json::to_vec(&event.payload).map_err(|e| e.to_string())?;
+    let timestamp = Utc::now().timestamp();
+    let signature = sign_webhook_payload(&webhook.secret, timestamp, &body);
+
     let res = client
         .post(&webhook.url)
-        .json(&event.payload)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(WEBHOOK_ID_HEADER, webhook.id.to_string())
+        .header(WEBHOOK_SIGNATURE_HEADER, signature)
+        .header(WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string())
+        .body(body)
         .send()
         .await
         .map_err(|e| e.to_string())?;
@@ -140,8 +156,8 @@ fn compute_next_retry(attempts: i32) -> Option<DateTime<Utc>> {
 
 
 use redis::AsyncCommands;
-use sqlx::Error;
 use crate::models::webhooks_models::{WebhookEventRow, WebhookRow};
+use crate::services::other_services::{sign_webhook_payload, WEBHOOK_ID_HEADER, WEBHOOK_SIGNATURE_HEADER, WEBHOOK_TIMESTAMP_HEADER};
 
 async fn schedule_redis_retry(
     redis_client: &redis::Client,
@@ -160,19 +176,69 @@ async fn schedule_redis_retry(
 }
 
 
-pub async fn enqueue_pending_webhooks(app_state: Arc<AppState>) {
-    let events = app_state
-        .database_connector
-        .get_pending_webhook_events()
+/// Materialises the webhook deliveries of an outbox event left undispatched and hands them
+/// to the webhook worker. Safe to call more than once for the same event.
+pub async fn dispatch_domain_event(app_state: Arc<AppState>, domain_event_id: i64) {
+    let db = &app_state.database_connector;
+    let mut tx = match db.connector.begin().await {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("unable to begin transaction for domain event {}: {}", domain_event_id, e);
+            return;
+        }
+    };
+
+    let event = match db.get_domain_event_txn(&mut tx, domain_event_id).await {
+        Ok(Some(e)) if e.dispatched_at.is_none() => e,
+        // dispatched already or another dispatcher holds it
+        Ok(_) => return,
+        Err(e) => {
+            error!("db error loading domain event {}: {}", domain_event_id, e);
+            return;
+        }
+    };
+
+    let queue_messages = match db
+        .materialise_domain_event_txn(&mut tx, event.id, event.business_id, &event.event_type, &event.payload)
         .await
-        .unwrap_or_default();
-
-    for event_id in events {
-        let webhook = app_state.database_connector.get_webhook_event(event_id).await.unwrap().unwrap();
-        let _ = app_state.event_queue.send(WebhookQueueMessage {
-            webhook_event_id: event_id,
-            webhook_id: webhook.webhook_id
-        });
+    {
+        Ok(messages) => messages,
+        Err(e) => {
+            // rolled back on drop, the next sweep picks it up again
+            error!("failed to materialise domain event {}: {}", domain_event_id, e);
+            return;
+        }
+    };
+
+    if let Err(e) = tx.commit().await {
+        error!("failed to commit dispatch of domain event {}: {}", domain_event_id, e);
+        return;
+    }
+    for message in queue_messages {
+        let _ = app_state.event_queue.send(message);
+    }
+}
+
+
+pub async fn outbox_sweeper(app_state: Arc<AppState>) {
+    info!("Outbox sweeper started");
+    loop {
+        // giving the request path a head start so we don't race it
+        let older_than = Utc::now() - Duration::seconds(10);
+        match app_state
+            .database_connector
+            .get_undispatched_domain_events(older_than)
+            .await
+        {
+            Ok(ids) => {
+                for domain_event_id in ids {
+                    warn!("dispatching domain event {} left behind in the outbox", domain_event_id);
+                    dispatch_domain_event(app_state.clone(), domain_event_id).await;
+                }
+            }
+            Err(e) => error!("db error loading undispatched domain events: {}", e),
+        }
+        tokio::time::sleep(std::time::Duration::from_secs(30)).await;
     }
 }
 
@@ -232,10 +298,7 @@ fn parse_webhook_retry_key(key: &str) -> Option<i64> {
     tracing::info!("extracting the event_id from the key: {}", key) ;
     let prefix = "webhook:retry:";
 
-    if key.starts_with(prefix) {
-        key[prefix.len()..].parse::<i64>().ok()
-    } else {
-        None
-    }
+    key.strip_prefix(prefix)
+        .and_then(|event_id| event_id.parse::<i64>().ok())
 }
 
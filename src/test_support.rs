@@ -0,0 +1,93 @@
+// fixtures shared by the tests. the ones that touch Postgres are only used by `#[ignore]`d tests
+// and need a throwaway database and the key secret:
+// TEST_DATABASE_URL=postgres://... API_KEY_SECRET=... cargo test -- --ignored
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use axum::http::{Method, StatusCode};
+use axum::Router;
+use serde_json::Value;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use crate::{AppState, MIGRATOR};
+use crate::middlewares::rate_limit_middleware::RateLimiter;
+use crate::models::bussiness_models::ALL_API_KEY_SCOPES;
+use crate::models::event_queue::WebhookQueueMessage;
+use crate::services::db_operations::DbOperations;
+use crate::services::other_services::{api_key_prefix, generate_api_key};
+
+// nothing listens there, every connection attempt is refused
+pub fn unreachable_redis() -> redis::Client {
+    redis::Client::open("redis://127.0.0.1:1/").unwrap()
+}
+
+// a migrated pool on the database of TEST_DATABASE_URL
+pub async fn test_db(max_connections: u32) -> DbOperations {
+    let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL must be set");
+    let pool = DbOperations::pool_options().max_connections(max_connections).connect(&url).await.unwrap();
+    MIGRATOR.run(&pool).await.unwrap();
+    DbOperations { connector: pool }
+}
+
+// the state `top_level_routes` builds, without the workers. the receiver gets what the webhook worker would
+pub fn test_state(database_connector: DbOperations, rate_limiter: RateLimiter) -> (Arc<AppState>, UnboundedReceiver<WebhookQueueMessage>) {
+    let (event_queue, rx) = unbounded_channel();
+    let state = Arc::new(AppState {
+        database_connector,
+        event_queue,
+        redis_client: unreachable_redis(),
+        rate_limiter,
+    });
+    (state, rx)
+}
+
+// serves `router` on a free local port and returns its base url
+pub async fn serve(router: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>()).await.unwrap();
+    });
+    format!("http://{}", addr)
+}
+
+// a business with a key holding every scope, calling the served app with it
+pub struct Tenant {
+    pub base: String,
+    pub key: String,
+    pub client: reqwest::Client,
+}
+
+impl Tenant {
+    pub async fn new(base: &str, state: &Arc<AppState>, name: &str) -> Tenant {
+        let business_id = state.database_connector.create_new_business(name).await.unwrap();
+        let (raw_key, hashed_key) = generate_api_key();
+        state.database_connector
+            .store_api_key(business_id, &hashed_key, &api_key_prefix(&raw_key), &[ALL_API_KEY_SCOPES.to_string()])
+            .await
+            .unwrap();
+        Tenant { base: base.to_string(), key: raw_key, client: reqwest::Client::new() }
+    }
+
+    pub async fn call(&self, method: Method, path: &str, body: Option<Value>) -> (StatusCode, Value) {
+        let mut request = self.client
+            .request(method, format!("{}{}", self.base, path))
+            .bearer_auth(&self.key);
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let response = request.send().await.unwrap();
+        let status = response.status();
+        (status, response.json().await.unwrap_or(Value::Null))
+    }
+
+    // the `data` of a successful call
+    pub async fn ok(&self, method: Method, path: &str, body: Option<Value>) -> Value {
+        let (status, body) = self.call(method, path, body).await;
+        assert!(status.is_success(), "{} failed with {}: {}", path, status, body);
+        body["data"].clone()
+    }
+
+    pub async fn balance(&self, account_id: i64) -> Value {
+        self.ok(Method::GET, &format!("/accounts/{}/balance", account_id), None).await
+    }
+}